@@ -3,46 +3,82 @@ use std::{
     path::PathBuf,
 };
 
-use egui::{Align2, Direction, Event, InputState, Key};
+use egui::{Align2, Direction, Event, InputState, Key, Rect, Vec2};
 use egui_file::FileDialog;
 use egui_toast::{Toast, ToastOptions, Toasts};
 
 use verifactory_lib::{
-    backends::{
-        belt_balancer_f, equal_drain_f, throughput_unlimited, universal_balancer,
-        BlueprintProofEntity, ModelFlags, ProofResult,
-    },
+    backends::{BlueprintProofEntity, SolverOptions},
     entities::{EntityId, FBEntity},
     frontend::{Compiler, RelMap},
-    import::string_to_entities,
-    ir::{CoalesceStrength, FlowGraph, FlowGraphFun, Node, Reversable},
+    import::{string_to_entities_with_options, ImportOptions, ImportReport},
+    inserters::InserterModel,
+    ir::{min_cut, CoalesceStrength, FlowGraph, FlowGraphFun, MinCut, Node, Reversable},
+    prototypes::PrototypeRegistry,
     utils::Position,
 };
 
-use super::menu::BlueprintString;
+use super::{grid::find_bottlenecks, menu::BlueprintString, proofs::ProofState};
 
 #[derive(Default)]
 pub struct FileState {
     pub opened_file: Option<PathBuf>,
     pub open_file_dialog: Option<FileDialog>,
+    pub prototypes_dialog: Option<FileDialog>,
 }
 
+/// Position and zoom of the blueprint canvas
 pub struct GridSettings {
+    pub max_x: i32,
     pub max_y: i32,
-    pub y_offset: i32,
-    pub x_offset: i32,
-    pub size: i32,
+    /// Offset of the grid from the top-left corner of the canvas, in pixels
+    pub offset: Vec2,
+    /// Size of a tile, in pixels
+    pub size: f32,
+    /// Area of the screen covered by the canvas in the last frame
+    pub canvas: Rect,
+    /// Fit the grid to the canvas in the next frame
+    pub fit_requested: bool,
 }
 
 impl GridSettings {
+    pub const MIN_SIZE: f32 = 2.;
+    pub const MAX_SIZE: f32 = 200.;
+
     pub fn from(grid: &EntityGrid) -> Self {
         Self {
-            max_y: grid.len() as i32 + 1,
-            y_offset: 0,
-            x_offset: 0,
-            size: 50,
+            max_x: grid.iter().map(Vec::len).max().unwrap_or(0) as i32 - 1,
+            max_y: grid.len() as i32 - 1,
+            offset: Vec2::ZERO,
+            size: 50.,
+            canvas: Rect::NOTHING,
+            fit_requested: true,
         }
     }
+
+    /// Zooms by `factor` keeping the point `anchor`, relative to the canvas, at the same place.
+    pub fn zoom(&mut self, factor: f32, anchor: Vec2) {
+        let size = (self.size * factor).clamp(Self::MIN_SIZE, Self::MAX_SIZE);
+        let factor = size / self.size;
+        self.offset = anchor - (anchor - self.offset) * factor;
+        self.size = size;
+    }
+
+    /// Zooms around the center of the canvas.
+    pub fn zoom_center(&mut self, factor: f32) {
+        self.zoom(factor, self.canvas.size() / 2.);
+    }
+
+    /// Scales and centers the grid s.t. it fits the canvas.
+    pub fn fit(&mut self) {
+        let tiles =
+            Vec2::new((self.max_x + 1) as f32, (self.max_y + 1) as f32).max(Vec2::splat(1.));
+        let available = self.canvas.size();
+        self.size = (available / tiles)
+            .min_elem()
+            .clamp(Self::MIN_SIZE, Self::MAX_SIZE);
+        self.offset = (available - tiles * self.size) / 2.;
+    }
 }
 
 #[derive(Default)]
@@ -75,12 +111,60 @@ impl IOState {
     }
 }
 
-#[derive(Default)]
-pub struct ProofState {
-    balancer: Option<ProofResult>,
-    equal_drain: Option<ProofResult>,
-    throughput_unlimited: Option<ProofResult>,
-    universal: Option<ProofResult>,
+/// Flows of the counterexample of the last failed proof, drawn on top of the blueprint
+pub struct FlowOverlay {
+    /// Flow through each entity relative to its throughput, 1.0 being a fully used entity
+    pub utilisation: HashMap<EntityId, f32>,
+    /// Rate of each input of the blueprint
+    pub input_rates: HashMap<EntityId, String>,
+    /// Rate of each output of the blueprint
+    pub output_rates: HashMap<EntityId, String>,
+}
+
+impl FlowOverlay {
+    /// Reads the flows of the counterexample found by the proof, if any.
+    ///
+    /// Inputs and outputs are swapped back if the proof was modelled on the `reversed` graph.
+    pub fn from_proof(
+        proof: &BlueprintProofEntity,
+        entities: &[FBEntity<i32>],
+        reversed: bool,
+    ) -> Option<Self> {
+        let counterexample = proof.counterexample()?;
+        let flows = counterexample.entity_flows(proof.graph());
+        let utilisation = entities
+            .iter()
+            .filter_map(|e| {
+                let base = e.get_base();
+                let flow = flows.get(&base.id)?;
+                let flow = *flow.numer()? as f32 / *flow.denom()? as f32;
+                /* a splitter carries the throughput of two belts */
+                let lanes = if matches!(e, FBEntity::Splitter(_)) {
+                    2.
+                } else {
+                    1.
+                };
+                Some((base.id, flow / (base.throughput as f32 * lanes)))
+            })
+            .collect();
+        let rates = |map: &HashMap<EntityId, _>| {
+            map.iter()
+                .map(|(id, rate)| (*id, format!("{}", rate)))
+                .collect::<HashMap<_, _>>()
+        };
+        let (mut input_rates, mut output_rates) = (
+            rates(&counterexample.inputs),
+            rates(&counterexample.outputs),
+        );
+        if reversed {
+            std::mem::swap(&mut input_rates, &mut output_rates);
+        }
+        Some(Self {
+            utilisation,
+            input_rates,
+            output_rates,
+        })
+    }
 }
 
 pub type EntityGrid = Vec<Vec<Option<FBEntity<i32>>>>;
@@ -94,7 +178,19 @@ pub struct MyApp {
     pub selection: Option<FBEntity<i32>>,
     pub blueprint_string: BlueprintString,
     pub feeds_from: RelMap<Position<i32>>,
-    pub show_error: bool,
+    pub load_error: Option<String>,
+    pub import_report: Option<ImportReport>,
+    pub import_options: ImportOptions,
+    pub model_lanes: bool,
+    pub flow_overlay: Option<FlowOverlay>,
+    pub custom_property: String,
+    pub solver_options: SolverOptions,
+    pub show_tier_legend: bool,
+    pub highlight_bottlenecks: bool,
+    /// Runs of entities slower than the entities around them, see [`find_bottlenecks`]
+    pub bottlenecks: HashSet<Position<i32>>,
+    /// Minimum cut between the selected inputs and outputs, outlined on the blueprint
+    pub min_cut: Option<MinCut>,
 }
 
 impl Default for MyApp {
@@ -108,7 +204,17 @@ impl Default for MyApp {
         let selection = None;
         let blueprint_string = BlueprintString::default();
         let feeds_from = HashMap::new();
-        let show_error = false;
+        let load_error = None;
+        let import_report = None;
+        let import_options = ImportOptions::default();
+        let model_lanes = false;
+        let flow_overlay = None;
+        let custom_property = String::new();
+        let solver_options = SolverOptions::default();
+        let show_tier_legend = true;
+        let highlight_bottlenecks = false;
+        let bottlenecks = HashSet::new();
+        let min_cut = None;
         Self {
             grid,
             grid_settings,
@@ -119,13 +225,23 @@ impl Default for MyApp {
             selection,
             blueprint_string,
             feeds_from,
-            show_error,
+            load_error,
+            import_report,
+            import_options,
+            model_lanes,
+            flow_overlay,
+            custom_property,
+            solver_options,
+            show_tier_legend,
+            highlight_bottlenecks,
+            bottlenecks,
+            min_cut,
         }
     }
 }
 
 impl MyApp {
-    fn generate_graph(&self, reversed: bool) -> FlowGraph {
+    pub fn generate_graph(&self, reversed: bool) -> FlowGraph {
         let mut graph = self.graph.clone();
         let io_state = &self.io_state;
         let removed_inputs = io_state
@@ -156,23 +272,77 @@ impl MyApp {
         self.load_string(&blueprint_string)
     }
 
+    /// Adds the prototypes of the file to the vanilla ones and reloads the opened blueprint file.
+    pub fn load_prototypes(&mut self, file: PathBuf) -> anyhow::Result<()> {
+        let modded = PrototypeRegistry::from_file(&file.to_string_lossy())?;
+        let mut registry = PrototypeRegistry::vanilla();
+        registry.extend(modded);
+        self.import_options.registry = registry;
+        self.reload_file()
+    }
+
+    /// Sets the model computing the throughput of inserters, `None` to use the one of their prototype,
+    /// and reloads the opened blueprint file.
+    pub fn set_inserter_model(&mut self, model: Option<InserterModel>) -> anyhow::Result<()> {
+        self.import_options.inserters = model;
+        self.reload_file()
+    }
+
+    fn reload_file(&mut self) -> anyhow::Result<()> {
+        match self.open_file_state.opened_file.clone() {
+            Some(file) => self.load_file(file),
+            None => Ok(()),
+        }
+    }
+
     pub fn load_string(&mut self, blueprint: &str) -> anyhow::Result<()> {
-        let loaded_entities = string_to_entities(blueprint)?;
+        let (loaded_entities, report) =
+            string_to_entities_with_options(blueprint, &self.import_options)?;
+        self.import_report = (!report.is_empty()).then_some(report);
         self.grid = Self::entities_to_grid(loaded_entities.clone());
         self.grid_settings = GridSettings::from(&self.grid);
+        self.compile(loaded_entities);
+        Ok(())
+    }
 
-        let compiler = Compiler::new(loaded_entities);
+    /// Compiles the entities to the graph used for the proofs, modelling lanes if `model_lanes` is set
+    /// and the blueprint can be modelled with lanes.
+    pub fn compile(&mut self, entities: Vec<FBEntity<i32>>) {
+        let compiler = Compiler::with_registry(entities, &self.import_options.registry);
         self.feeds_from = compiler.feeds_from.clone();
-        self.graph = compiler.create_graph();
+        self.bottlenecks = find_bottlenecks(&self.feeds_from, &self.grid);
+        self.graph = match self.model_lanes.then(|| compiler.create_lane_graph()) {
+            Some(Ok(graph)) => graph,
+            /* fall back to the graph without lanes */
+            Some(Err(e)) => {
+                self.model_lanes = false;
+                self.load_error = Some(e.to_string());
+                compiler.create_graph()
+            }
+            None => compiler.create_graph(),
+        };
         self.graph.simplify(&[], CoalesceStrength::Lossless);
         self.io_state = IOState::from_graph(&self.graph);
+        self.proof_state.cancel_all();
         self.proof_state = ProofState::default();
-        Ok(())
+        self.flow_overlay = None;
+        self.min_cut = None;
+    }
+
+    /// Computes the minimum cut between the selected inputs and outputs, see [`min_cut`].
+    pub fn find_min_cut(&mut self) {
+        let graph = self.generate_graph(false);
+        self.min_cut = Some(min_cut(&graph, None, None));
+    }
+
+    /// Returns all the entities of the blueprint.
+    pub fn entities(&self) -> Vec<FBEntity<i32>> {
+        self.grid.iter().flatten().flatten().cloned().collect()
     }
 }
 
 impl eframe::App for MyApp {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Set up toast notifications in the top right
         let mut toasts = Toasts::new()
             .anchor(Align2::RIGHT_TOP, (-10.0, 10.0))
@@ -187,9 +357,9 @@ impl eframe::App for MyApp {
                 _ => None,
             });
             if let Some(pasted_string) = pasted_string {
-                if self.load_string(pasted_string).is_err() {
+                if let Err(e) = self.load_string(pasted_string) {
                     toasts.add(Toast {
-                        text: "Failed to load blueprint from clipboard!".into(),
+                        text: format!("Failed to load blueprint from clipboard: {}", e).into(),
                         kind: egui_toast::ToastKind::Error,
                         options: ToastOptions::default().duration_in_seconds(10.0),
                     });
@@ -199,16 +369,16 @@ impl eframe::App for MyApp {
 
         toasts.show(ctx);
 
-        egui::TopBottomPanel::top("blueprint_panel").show(ctx, |ui| {
-            let s = &self.grid_settings;
-            let dimensions = (s.size * s.max_y) as f32;
-            ui.set_height_range(dimensions..=dimensions);
-            ui.heading("Blueprint");
-            self.draw_grid(ui);
-        });
+        egui::TopBottomPanel::top("blueprint_panel")
+            .resizable(true)
+            .default_height(450.)
+            .show(ctx, |ui| {
+                ui.heading("Blueprint");
+                self.draw_grid(ui);
+            });
 
         let io_state = &mut self.io_state;
-        if let Some(sel) = self.selection {
+        if let Some(sel) = &self.selection {
             let (i_pressed, o_pressed) =
                 ctx.input(|i: &InputState| (i.key_pressed(Key::I), i.key_pressed(Key::O)));
             egui::SidePanel::right("right").show(ctx, |ui| {
@@ -250,105 +420,60 @@ impl eframe::App for MyApp {
             });
         }
 
-        if self.show_error {
+        if let Some(error) = &self.load_error {
+            let mut close = false;
             egui::Window::new("Error").title_bar(false).show(ctx, |ui| {
                 ui.heading("Error whilst loading blueprint!");
-                ui.label("Blueprint string is either malformed or uses non supported entities.");
+                ui.label(error);
                 if ui.button("Close").clicked() {
-                    self.show_error = false;
+                    close = true;
                 }
             });
+            if close {
+                self.load_error = None;
+            }
         }
 
-        egui::TopBottomPanel::top("proof_panel").show(ctx, |ui| {
-            ui.heading("Proofs");
-            ui.separator();
-
-            // TODO: figure out lifetimes and fix code duplication
-            ui.heading("Is it a belt-balancer?");
-            ui.horizontal(|ui| {
-                if ui.button("Prove").clicked() {
-                    let graph = self.generate_graph(false);
-                    let mut proof = BlueprintProofEntity::new(graph);
-                    let res = proof.model(belt_balancer_f, ModelFlags::empty());
-                    self.proof_state.balancer = Some(res);
-                }
-                if let Some(proof_res) = self.proof_state.balancer {
-                    ui.label(format!("Proof result: {}", proof_res));
-                }
-            });
-
-            ui.label("\n");
-
-            ui.heading("Is it an equal drain belt-balancer (assumes it is a belt-balancer)?");
-            ui.horizontal(|ui| {
-                if ui.button("Prove").clicked() {
-                    let graph = self.generate_graph(true);
-                    let mut proof = BlueprintProofEntity::new(graph);
-                    let res = proof.model(equal_drain_f, ModelFlags::empty());
-                    self.proof_state.equal_drain = Some(res);
-                }
-                if let Some(proof_res) = self.proof_state.equal_drain {
-                    ui.label(format!("Proof result: {}", proof_res));
-                }
-            });
-
-            ui.label("\n");
-
-            ui.heading(
-                "Is it a throughput unlimited belt-balancer (assumes it is a belt-balancer)?",
-            );
-            ui.horizontal(|ui| {
-                if ui.button("Prove").clicked() {
-                    let graph = self.generate_graph(false);
-                    let mut proof = BlueprintProofEntity::new(graph);
-                    let entities = self.grid.iter().flatten().flatten().cloned().collect();
-                    let res = proof.model(throughput_unlimited(entities), ModelFlags::Relaxed);
-                    self.proof_state.throughput_unlimited = Some(res);
-                }
-                if let Some(proof_res) = self.proof_state.throughput_unlimited {
-                    ui.label(format!("Proof result: {}", proof_res));
-                }
-            });
-            ui.label("\n");
-
-            ui.heading("Is it a universal belt-balancer?");
-            ui.horizontal(|ui| {
-                if ui.button("Prove").clicked() {
-                    let graph = self.generate_graph(false);
-                    let mut proof = BlueprintProofEntity::new(graph);
-                    let res = proof.model(universal_balancer, ModelFlags::Blocked);
-                    self.proof_state.universal = Some(res);
-                }
-                if let Some(proof_res) = self.proof_state.universal {
-                    ui.label(format!("Proof result: {}", proof_res));
+        if let Some(report) = &self.import_report {
+            let mut close = false;
+            egui::Window::new("Skipped entities").show(ctx, |ui| {
+                ui.label("The following entities are not supported and were ignored:");
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for skipped in &report.skipped {
+                        ui.label(skipped.to_string());
+                    }
+                });
+                if ui.button("Close").clicked() {
+                    close = true;
                 }
             });
-
-            ui.label("\n");
-
-            if ui.button("Save svg").clicked() {
-                self.generate_graph(false).to_svg("out.svg").unwrap();
-            }
-            if ui.button("Save reversed svg").clicked() {
-                self.generate_graph(true).to_svg("out.svg").unwrap();
+            if close {
+                self.import_report = None;
             }
-            ui.label("\n");
-        });
+        }
+
+        self.draw_proof_panel(ctx);
 
         /* Show features and current state of project */
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Current state of the project");
-            ui.label("- Currently only supports belts, underground belts and splitters (with priorities).\n  \
-            Side-loading and other constructs taking advantage of a belt being split into two lanes is currently WIP.\n  \
-            Read: The analysis will *definetely* be wrong.");
-            ui.label("- All belts show as yellow but they are still modelled correctly.\n  \
-            Clicking on a belt will show its real throughput (15 for yellow, 30 for red, 45 for blue.");
-            ui.label("- Big blueprints won't fit on the screen.\n  \
-            Use *View > Decrease blueprint size* to zoom out. \
-            A better, zoomable and movable, canvas is WIP.");
+            ui.label("- Currently only supports belts, underground belts, splitters (with priorities), inserters and assemblers.\n  \
+            Assemblers consume and produce one item per second of crafting speed, inserters take items off a belt before it moves on.\n  \
+            Side-loading and other constructs taking advantage of a belt being split into two lanes\n  \
+            are only modelled correctly when enabling *View > Model belt lanes*.");
+            ui.label("- Belts, undergrounds and splitters are tinted with the colour of their tier, see the legend on the blueprint.\n  \
+            *View > Highlight bottlenecks* outlines the runs of belts slower than the belts feeding them and fed by them.\n  \
+            *View > Highlight min cut* outlines the entities limiting the throughput between the selected inputs and outputs.");
+            ui.label("- Scroll over the blueprint to zoom, drag it to move it around and double-click it to fit it to the window.\n  \
+            The blueprint panel can be resized by dragging its bottom edge.");
+            ui.label("- Proofs run in the background and can be cancelled whilst running.\n  \
+            The timeout and other limits of the solver can be set in the *Solver options* of the proof panel.");
+            ui.label("- When a proof fails the flows of the counterexample are shown on the blueprint.\n  \
+            Entities are coloured from blue (empty) to red (full), inputs and outputs show their rate.");
             ui.label("- VeriFactory can prove much more than the automatic proofs above.\n  \
-            A custom language to specify own properties is WIP.");
+            Write your own properties in the custom property box, e.g. `output 3 >= input 1 / 2`\n  \
+            or `forall o in outputs: not blocked(o) => o <= 15`. See the README for the full language.");
             ui.label("\n  Thank you for testing VeriFactory and have fun.\n  The factory must grow!");
         });
     }
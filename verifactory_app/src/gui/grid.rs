@@ -1,9 +1,12 @@
-use std::f32::consts::PI;
+use std::{
+    collections::{HashMap, HashSet},
+    f32::consts::PI,
+};
 
-use egui::{Color32, Image, Pos2, Rect, Sense, Vec2};
+use egui::{Align2, Color32, FontId, Image, PointerButton, Rect, Response, Sense, Stroke, Vec2};
 
 use verifactory_lib::{
-    entities::{BeltType, FBBelt, FBEntity, FBSplitter, Priority},
+    entities::{BeltType, EntityId, FBBelt, FBEntity, FBSplitter, Priority},
     frontend::RelMap,
     utils::{Direction, Position, Rotation},
 };
@@ -55,35 +58,137 @@ fn prio_rect(splitter: &FBSplitter<i32>, rect: Rect, size: f32) -> Vec<Rect> {
     vec
 }
 
+/// Colour of an entity given its utilisation, from blue for an empty entity to red for a full one
+fn flow_color(utilisation: f32) -> Color32 {
+    let t = utilisation.clamp(0., 1.);
+    let lerp = |low: u8, high: u8| (low as f32 + (high as f32 - low as f32) * t) as u8;
+    let (low, high) = (Color32::LIGHT_BLUE, Color32::RED);
+    Color32::from_rgb(
+        lerp(low.r(), high.r()),
+        lerp(low.g(), high.g()),
+        lerp(low.b(), high.b()),
+    )
+}
+
+/// Colours of the belt tiers, starting with yellow (15/s) and increasing by 15/s per tier
+const TIER_COLORS: [Color32; 6] = [
+    Color32::from_rgb(230, 190, 40),  // yellow
+    Color32::from_rgb(220, 40, 30),   // red
+    Color32::from_rgb(40, 140, 230),  // blue
+    Color32::from_rgb(90, 200, 70),   // turbo
+    Color32::from_rgb(170, 70, 220),  // modded tiers
+    Color32::from_rgb(240, 240, 240), // modded tiers
+];
+
+/// Colour of the tier of an entity given its throughput, the last colour is used for all the faster tiers
+fn tier_color(throughput: f64) -> Color32 {
+    let tier = ((throughput / 15.).round() as usize).clamp(1, TIER_COLORS.len());
+    TIER_COLORS[tier - 1]
+}
+
+/// Whether the entity moves items like a belt, the only entities tinted with the colour of their tier
+fn is_belt_like(entity: &FBEntity<i32>) -> bool {
+    matches!(
+        entity,
+        FBEntity::Belt(_)
+            | FBEntity::Underground(_)
+            | FBEntity::Splitter(_)
+            | FBEntity::SplitterPhantom(_)
+    )
+}
+
+/// Returns the positions of the belt-like entities slower than the entities around them.
+///
+/// Connected runs of belt-like entities with the same throughput are flagged as a whole if every belt-like
+/// entity feeding the run or fed by it is faster, and there is at least one on both ends.
+/// Inserters and machines are left out, their throughput is not comparable to the one of a belt.
+pub fn find_bottlenecks(
+    feeds_from_map: &RelMap<Position<i32>>,
+    grid: &EntityGrid,
+) -> HashSet<Position<i32>> {
+    let belt_like = |pos: &Position<i32>| {
+        let entity = grid.get(pos.y as usize)?.get(pos.x as usize)?.as_ref()?;
+        is_belt_like(entity).then_some(entity)
+    };
+    let throughput = |pos: &Position<i32>| belt_like(pos).map(|e| e.get_base().throughput);
+    let mut feeds_from: RelMap<Position<i32>> = HashMap::new();
+    let mut feeds_to: RelMap<Position<i32>> = HashMap::new();
+    for (pos, from) in feeds_from_map {
+        for from in from {
+            if belt_like(pos).is_none() || belt_like(from).is_none() {
+                continue;
+            }
+            feeds_from.entry(*pos).or_default().insert(*from);
+            feeds_to.entry(*from).or_default().insert(*pos);
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut bottlenecks = HashSet::new();
+    for start in feeds_from.keys().chain(feeds_to.keys()) {
+        if !visited.insert(*start) {
+            continue;
+        }
+        let Some(tier) = throughput(start) else {
+            continue;
+        };
+        /* flood the run of entities with the same throughput, collecting the entities around it */
+        let mut run = vec![*start];
+        let (mut before, mut after) = (vec![], vec![]);
+        let mut stack = vec![*start];
+        while let Some(pos) = stack.pop() {
+            let neighbours = [(&feeds_from, &mut before), (&feeds_to, &mut after)];
+            for (map, around) in neighbours {
+                for n in map.get(&pos).into_iter().flatten() {
+                    if throughput(n) != Some(tier) {
+                        around.push(*n);
+                    } else if visited.insert(*n) {
+                        run.push(*n);
+                        stack.push(*n);
+                    }
+                }
+            }
+        }
+        let faster = |around: &[Position<i32>]| {
+            !around.is_empty()
+                && around
+                    .iter()
+                    .all(|n| throughput(n).is_some_and(|other| tier < other))
+        };
+        if faster(&before) && faster(&after) {
+            bottlenecks.extend(run);
+        }
+    }
+    bottlenecks
+}
+
+/// Returns the rotation of a belt curving from the only belt-like entity feeding it, if any.
+///
+/// Inserters dropping onto the belt are not taken into account, they don't bend it.
 fn determine_belt_rotation(
     belt: &FBBelt<i32>,
     feeds_from_map: &RelMap<Position<i32>>,
     grid: &EntityGrid,
 ) -> Option<Rotation> {
-    let feeds_from = feeds_from_map.get(&belt.base.position);
-    feeds_from.and_then(|f| {
-        if f.len() != 1 {
-            None
-        } else {
-            let pos = f.iter().next().unwrap();
-            /* TODO: this is very inefficient, maybe keep a HashMap<Position<i32>, Entity<i32>> in MyApp */
-            let feeding_entity = grid
-                .iter()
-                .flatten()
-                .flatten()
-                .find(|&e| e.get_base().position == *pos)
-                .unwrap();
-            let feeding_dir = feeding_entity.get_base().direction;
-            let belt_dir = belt.base.direction;
-            if belt_dir == feeding_dir.rotate(Rotation::Anticlockwise, 1) {
-                Some(Rotation::Anticlockwise)
-            } else if belt_dir == feeding_dir.rotate(Rotation::Clockwise, 1) {
-                Some(Rotation::Clockwise)
-            } else {
-                None
-            }
-        }
-    })
+    let feeds_from = feeds_from_map.get(&belt.base.position)?;
+    /* the grid is indexed by position */
+    let mut feeders = feeds_from
+        .iter()
+        .filter_map(|pos| grid.get(pos.y as usize)?.get(pos.x as usize)?.as_ref())
+        .filter(|e| is_belt_like(e));
+    let feeding_entity = feeders.next()?;
+    if feeders.next().is_some() {
+        return None;
+    }
+    let feeding_dir = feeding_entity.get_base().direction;
+    let belt_dir = belt.base.direction;
+    if belt_dir == feeding_dir.rotate(Rotation::Anticlockwise, 1) {
+        Some(Rotation::Anticlockwise)
+    } else if belt_dir == feeding_dir.rotate(Rotation::Clockwise, 1) {
+        Some(Rotation::Clockwise)
+    } else {
+        None
+    }
 }
 
 impl MyApp {
@@ -105,29 +210,134 @@ impl MyApp {
         grid
     }
 
+    /// Draws the grid on a canvas filling the `ui`, which can be zoomed with the scroll wheel and panned by dragging.
+    ///
+    /// Only the entities visible on the canvas are drawn.
     pub fn draw_grid(&mut self, ui: &mut egui::Ui) {
-        for entity in self.grid.iter().flatten().flatten() {
-            let selection = self.draw_img(ui, entity);
-            if selection.is_some() {
-                self.selection = selection;
+        /* only sense drags, clicks are handled by the entities */
+        let (canvas, response) = ui.allocate_exact_size(ui.available_size(), Sense::drag());
+        let double_clicked = response.hovered()
+            && ui.input(|i| i.pointer.button_double_clicked(PointerButton::Primary));
+        let settings = &mut self.grid_settings;
+        settings.canvas = canvas;
+        if settings.fit_requested || double_clicked {
+            settings.fit();
+            settings.fit_requested = false;
+        }
+        if response.dragged() {
+            settings.offset += response.drag_delta();
+        }
+        if let Some(pointer) = response.hover_pos() {
+            let (scroll, zoom) = ui.input(|i| (i.scroll_delta.y, i.zoom_delta()));
+            let factor = (scroll / 200.).exp() * zoom;
+            if factor != 1. {
+                settings.zoom(factor, pointer - canvas.min);
+            }
+        }
+
+        ui.set_clip_rect(canvas.intersect(ui.clip_rect()));
+        /* visible tiles, with a margin of one tile for the entities bigger than 1x1 */
+        let s = &self.grid_settings;
+        let first_x = ((-s.offset.x / s.size).floor() as i32 - 1).max(0);
+        let last_x = ((canvas.width() - s.offset.x) / s.size).ceil() as i32 + 1;
+        let first_row = ((-s.offset.y / s.size).floor() as i32 - 1).max(0);
+        let last_row = ((canvas.height() - s.offset.y) / s.size).ceil() as i32 + 1;
+        let (first_y, last_y) = (s.max_y - last_row, s.max_y - first_row);
+        let visible = |i: i32| i.max(0) as usize;
+
+        let mut selection = None;
+        for row in self
+            .grid
+            .iter()
+            .take(visible(last_y + 1))
+            .skip(visible(first_y))
+        {
+            for entity in row
+                .iter()
+                .take(visible(last_x + 1))
+                .skip(visible(first_x))
+                .flatten()
+            {
+                selection = self.draw_img(ui, entity).or(selection);
+            }
+        }
+        if selection.is_some() {
+            self.selection = selection;
+        }
+        if self.show_tier_legend {
+            self.draw_tier_legend(ui, canvas);
+        }
+    }
+
+    /// Draws the colour of each tier of the blueprint in the top-right corner of the canvas.
+    fn draw_tier_legend(&self, ui: &mut egui::Ui, canvas: Rect) {
+        let mut tiers = self
+            .grid
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|e| is_belt_like(e))
+            .map(|e| e.get_base().throughput as i32)
+            .collect::<Vec<_>>();
+        tiers.sort();
+        tiers.dedup();
+
+        let painter = ui.painter();
+        let line_height = 18.;
+        let legend = Rect::from_min_size(
+            canvas.right_top() + Vec2::new(-90., 5.),
+            Vec2::new(85., line_height * tiers.len() as f32 + 6.),
+        );
+        painter.rect_filled(legend, 3., Color32::from_black_alpha(180));
+        for (i, tier) in tiers.into_iter().enumerate() {
+            let min = legend.min + Vec2::new(5., 3. + line_height * i as f32);
+            let swatch = Rect::from_min_size(min + Vec2::splat(2.), Vec2::splat(line_height - 4.));
+            painter.rect_filled(swatch, 2., tier_color(tier as f64));
+            painter.text(
+                swatch.right_center() + Vec2::new(6., 0.),
+                Align2::LEFT_CENTER,
+                format!("{}/s", tier),
+                FontId::proportional(12.),
+                Color32::WHITE,
+            );
+        }
+    }
+
+    /// Tints the entity with the colour of its tier and outlines it if it is a bottleneck or in the min cut.
+    fn draw_tier(&self, ui: &mut egui::Ui, rect: Rect, entity: &FBEntity<i32>) {
+        let base = entity.get_base();
+        /* the sprites are yellow, the flows of a counterexample take precedence over the tiers */
+        if self.flow_overlay.is_none()
+            && is_belt_like(entity)
+            && tier_color(base.throughput) != TIER_COLORS[0]
+        {
+            let color = tier_color(base.throughput).linear_multiply(0.55);
+            ui.painter().rect_filled(rect, 0., color);
+        }
+        if self.highlight_bottlenecks && self.bottlenecks.contains(&base.position) {
+            let stroke = Stroke::new(
+                self.grid_settings.size / 10.,
+                Color32::from_rgb(255, 0, 255),
+            );
+            ui.painter()
+                .rect_stroke(rect.shrink(stroke.width / 2.), 0., stroke);
+        }
+        if let Some(cut) = &self.min_cut {
+            if cut.entities.contains(&base.id) {
+                let stroke = Stroke::new(self.grid_settings.size / 10., Color32::RED);
+                /* inside the outline of the bottlenecks */
+                ui.painter()
+                    .rect_stroke(rect.shrink(stroke.width * 1.5), 0., stroke);
             }
         }
     }
 
     fn get_grid_rect(&self, position: Position<i32>) -> Rect {
         let s = &self.grid_settings;
-        let x_origin = s.x_offset + position.x * s.size;
-        let y_origin = s.y_offset + (s.max_y - position.y) * s.size;
-        Rect {
-            min: Pos2 {
-                x: x_origin as f32,
-                y: y_origin as f32,
-            },
-            max: Pos2 {
-                x: (x_origin + s.size) as f32,
-                y: (y_origin + s.size) as f32,
-            },
-        }
+        let min = s.canvas.min
+            + s.offset
+            + Vec2::new(position.x as f32, (s.max_y - position.y) as f32) * s.size;
+        Rect::from_min_size(min, Vec2::splat(s.size))
     }
 
     fn draw_io(&self, ui: &mut egui::Ui, mut rect: Rect, entity: &FBEntity<i32>) {
@@ -150,7 +360,7 @@ impl MyApp {
             .fit_to_fraction(Vec2::splat(0.7));
         /* if the entity is a splitter force the arrow to be drawn in the middle */
         if let FBEntity::Splitter(s) = entity {
-            let size = self.grid_settings.size as f32;
+            let size = self.grid_settings.size;
             let rot = s.base.direction.rotate(Rotation::Clockwise, 1);
             rect = rect
                 .shrink_dir(rot, size / 2.)
@@ -158,16 +368,35 @@ impl MyApp {
         }
         // draw the arrow
         ui.put(rect, img);
+
+        // draw the rate of the counterexample on top of the arrow
+        let rates = self.flow_overlay.as_ref().map(|o| {
+            if is_input {
+                &o.input_rates
+            } else {
+                &o.output_rates
+            }
+        });
+        if let Some(rate) = rates.and_then(|r| r.get(&id)) {
+            let size = self.grid_settings.size;
+            ui.painter().text(
+                rect.center(),
+                Align2::CENTER_CENTER,
+                rate,
+                FontId::proportional(size / 3.),
+                Color32::WHITE,
+            );
+        }
     }
 
     fn draw_prio(&self, ui: &mut egui::Ui, rect: Rect, splitter: &FBSplitter<i32>) {
-        let base = splitter.base;
+        let base = &splitter.base;
         let rotation = base.direction as u8 as f32 * PI / 4.;
         let color = Color32::YELLOW;
         let img = Image::new(egui::include_image!("../../imgs/arrow.svg"))
             .rotate(rotation, Vec2::splat(0.5))
             .tint(color);
-        let size = self.grid_settings.size as f32;
+        let size = self.grid_settings.size;
         for p_rect in prio_rect(splitter, rect, size) {
             ui.put(p_rect, img.clone());
         }
@@ -176,11 +405,11 @@ impl MyApp {
     fn draw_selection(&self, ui: &mut egui::Ui, rect: Rect) {
         let img = Image::new(egui::include_image!("../../imgs/selection.svg"))
             .tint(Color32::from_rgb(255, 127, 80))
-            .fit_to_exact_size(Vec2::splat(self.grid_settings.size as f32));
+            .fit_to_exact_size(Vec2::splat(self.grid_settings.size));
         ui.put(rect, img);
     }
 
-    fn get_entity_img(entity: &FBEntity<i32>, belt_rotation: Option<Rotation>) -> Image {
+    fn get_entity_img(entity: &FBEntity<i32>, belt_rotation: Option<Rotation>) -> Image<'_> {
         let base = entity.get_base();
         let rotation = base.direction as u8 as f32 * PI / 4.;
         match entity {
@@ -221,15 +450,60 @@ impl MyApp {
         .sense(Sense::click())
     }
 
+    /// Colour of the entity in the counterexample shown, given the utilisation of its flow.
+    fn flow_tint(&self, id: EntityId) -> Option<Color32> {
+        let overlay = self.flow_overlay.as_ref()?;
+        /* entities optimized away by the simplification carry no flow */
+        let color = overlay
+            .utilisation
+            .get(&id)
+            .map_or(Color32::DARK_GRAY, |u| flow_color(*u));
+        Some(color)
+    }
+
+    /// Draws an entity without a sprite as a rect in the colour of its kind, inserters and loaders with an arrow
+    /// in their direction.
+    fn draw_shape(&self, ui: &mut egui::Ui, rect: Rect, entity: &FBEntity<i32>) -> Response {
+        let size = self.grid_settings.size;
+        let base = entity.get_base();
+        let color = match entity {
+            FBEntity::Inserter(_) => Color32::from_rgb(200, 160, 40),
+            FBEntity::LongInserter(_) => Color32::from_rgb(190, 60, 40),
+            FBEntity::Loader(_) => Color32::from_rgb(120, 120, 140),
+            FBEntity::Container(c) if c.infinite => Color32::from_rgb(200, 90, 200),
+            FBEntity::Container(_) => Color32::from_rgb(150, 105, 55),
+            FBEntity::Assembler(_) => Color32::from_rgb(120, 130, 120),
+            _ => Color32::from_rgb(70, 90, 120),
+        };
+        let color = self.flow_tint(base.id).unwrap_or(color);
+        let shape = match entity {
+            FBEntity::Inserter(_) | FBEntity::LongInserter(_) => rect.shrink(size / 5.),
+            _ => rect.shrink(size / 20.),
+        };
+        ui.painter().rect_filled(shape, size / 10., color);
+        if let FBEntity::Inserter(_) | FBEntity::LongInserter(_) | FBEntity::Loader(_) = entity {
+            /* north is up on the screen, whose y-axis points down */
+            let dir = match base.direction {
+                Direction::North => Vec2::new(0., -1.),
+                Direction::East => Vec2::new(1., 0.),
+                Direction::South => Vec2::new(0., 1.),
+                Direction::West => Vec2::new(-1., 0.),
+            };
+            let stroke = Stroke::new(size / 15., Color32::BLACK);
+            ui.painter()
+                .arrow(rect.center() - dir * size / 4., dir * size / 2., stroke);
+        }
+        ui.allocate_rect(rect, Sense::click())
+    }
+
     fn draw_img(&self, ui: &mut egui::Ui, entity: &FBEntity<i32>) -> Option<FBEntity<i32>> {
-        let s = &self.grid_settings;
         let base = entity.get_base();
 
         let mut pos_rect = self.get_grid_rect(base.position);
         let mut rotation = None;
         match entity {
             FBEntity::Splitter(_) => {
-                let size = s.size as f32;
+                let size = self.grid_settings.size;
                 pos_rect.min += match base.direction {
                     Direction::North => Vec2 { x: -size, y: 0. },
                     Direction::East => Vec2 { x: 0., y: -size },
@@ -244,17 +518,31 @@ impl MyApp {
             FBEntity::Belt(b) => {
                 rotation = determine_belt_rotation(b, &self.feeds_from, &self.grid)
             }
-            FBEntity::Underground(_) => (),
-            _ => return None,
+            /* the phantoms are covered by the entity they belong to */
+            FBEntity::SplitterPhantom(_) | FBEntity::AssemblerPhantom(_) => return None,
+            /* machines occupy the 3x3 tiles around their position */
+            FBEntity::Assembler(_) | FBEntity::Beacon(_) => {
+                pos_rect = pos_rect.expand(self.grid_settings.size)
+            }
+            _ => (),
         }
-        let img = Self::get_entity_img(entity, rotation);
+        let response = if is_belt_like(entity) {
+            let mut img = Self::get_entity_img(entity, rotation);
+            if let Some(color) = self.flow_tint(base.id) {
+                img = img.tint(color);
+            }
+            ui.put(pos_rect, img)
+        } else {
+            self.draw_shape(ui, pos_rect, entity)
+        };
 
-        let ret = if ui.put(pos_rect, img).clicked() {
-            Some(*entity)
+        let ret = if response.clicked() {
+            Some(entity.clone())
         } else {
             None
         };
-        match self.selection {
+        self.draw_tier(ui, pos_rect, entity);
+        match &self.selection {
             Some(sel) if sel.get_base().id == base.id => self.draw_selection(ui, pos_rect),
             _ => (),
         }
@@ -265,3 +553,52 @@ impl MyApp {
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use verifactory_lib::entities::FBBaseEntity;
+
+    use super::*;
+
+    /// Returns a row of belts going east, with the given throughputs.
+    fn belts(throughputs: &[f64]) -> (RelMap<Position<i32>>, EntityGrid) {
+        let position = |x| Position { x, y: 0 };
+        let row = throughputs
+            .iter()
+            .enumerate()
+            .map(|(x, &throughput)| {
+                Some(FBEntity::Belt(FBBelt {
+                    base: FBBaseEntity {
+                        id: x as i32,
+                        position: position(x as i32),
+                        direction: Direction::East,
+                        throughput,
+                        name: "transport-belt".to_owned(),
+                    },
+                }))
+            })
+            .collect();
+        let feeds_from = (1..throughputs.len() as i32)
+            .map(|x| (position(x), HashSet::from([position(x - 1)])))
+            .collect();
+        (feeds_from, vec![row])
+    }
+
+    #[test]
+    fn bottleneck_runs() {
+        let (feeds_from, grid) = belts(&[45., 30., 30., 45.]);
+        let bottlenecks = find_bottlenecks(&feeds_from, &grid);
+        let expected = [1, 2].map(|x| Position { x, y: 0 });
+        assert_eq!(bottlenecks, HashSet::from(expected));
+
+        /* a slow run at the end of the line slows nothing down after it */
+        let (feeds_from, grid) = belts(&[45., 30., 30.]);
+        assert!(find_bottlenecks(&feeds_from, &grid).is_empty());
+        let (feeds_from, grid) = belts(&[30., 45., 45., 30., 45.]);
+        let expected = [Position { x: 3, y: 0 }];
+        assert_eq!(
+            find_bottlenecks(&feeds_from, &grid),
+            HashSet::from(expected)
+        );
+    }
+}
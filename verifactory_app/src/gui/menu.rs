@@ -1,6 +1,6 @@
 use std::path::Path;
 
-use egui::{Ui, Window};
+use egui::{DragValue, Ui, Window};
 use egui_file::FileDialog;
 
 use super::app::MyApp;
@@ -26,13 +26,39 @@ impl BlueprintString {
 }
 
 impl MyApp {
+    /// Draws the options computing the throughput of inserters, reloading the opened blueprint file when they change.
+    fn draw_inserter_options(&mut self, ui: &mut Ui) {
+        let mut enabled = self.import_options.inserters.is_some();
+        let mut model = self.import_options.inserters.clone().unwrap_or_default();
+        let mut changed = ui
+            .checkbox(&mut enabled, "Compute inserter throughput")
+            .changed();
+        ui.add_enabled_ui(enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Inserter capacity bonus:");
+                let bonus = DragValue::new(&mut model.capacity_bonus).clamp_range(0..=7);
+                changed |= ui.add(bonus).changed();
+            });
+            ui.horizontal(|ui| {
+                ui.label("Belt stack size:");
+                let stack_size = DragValue::new(&mut model.belt_stack_size).clamp_range(1..=4);
+                changed |= ui.add(stack_size).changed();
+            });
+        });
+        if changed {
+            if let Err(e) = self.set_inserter_model(enabled.then_some(model)) {
+                self.load_error = Some(e.to_string());
+            }
+        }
+    }
+
     pub fn draw_menu(&mut self, ctx: &egui::Context) {
         egui::TopBottomPanel::top("TOP").show(ctx, |ui| {
             self.blueprint_string.show(ui);
             if self.blueprint_string.should_load {
                 let blueprint = self.blueprint_string.blueprint.clone();
-                if self.load_string(&blueprint).is_err() {
-                    self.show_error = true;
+                if let Err(e) = self.load_string(&blueprint) {
+                    self.load_error = Some(e.to_string());
                 }
                 self.blueprint_string.should_load = false;
                 self.blueprint_string.open = false;
@@ -57,6 +83,16 @@ impl MyApp {
                             blueprint: String::new(),
                         };
                     }
+                    /* Load modded entity prototypes, opens file dialog */
+                    if ui.button("Load entity prototypes").clicked() {
+                        ui.close_menu();
+                        let mut dialog = FileDialog::open_file(None);
+                        dialog.open();
+                        self.open_file_state.prototypes_dialog = Some(dialog);
+                    }
+                    ui.separator();
+                    self.draw_inserter_options(ui);
+                    ui.separator();
                     /* Close button, terminates the application */
                     if ui.button("Close").clicked() {
                         std::process::exit(0);
@@ -72,24 +108,56 @@ impl MyApp {
                     }
                 });
                 if let Some(path) = path {
-                    if self.load_file(path).is_err() {
-                        self.show_error = true;
+                    if let Err(e) = self.load_file(path) {
+                        self.load_error = Some(e.to_string());
+                    }
+                }
+                /* Handle the "Load entity prototypes" dialog */
+                let dialog = &mut self.open_file_state.prototypes_dialog;
+                let path = dialog.as_mut().and_then(|d| {
+                    if d.show(ctx).selected() {
+                        d.path().map(Path::to_path_buf)
+                    } else {
+                        None
+                    }
+                });
+                if let Some(path) = path {
+                    if let Err(e) = self.load_prototypes(path) {
+                        self.load_error = Some(e.to_string());
                     }
                 }
                 /* View submenu */
-                /* TODO */
                 ui.menu_button("View", |ui| {
-                    let size = &mut self.grid_settings.size;
-                    ui.label(format!("Current size: {}", size));
-                    if ui.button("Increase blueprint size").clicked() {
-                        *size += 5;
-                    }
-                    if ui.button("Decrease blueprint size").clicked() {
-                        *size -= 5;
-                        if *size <= 0 {
-                            *size = 5;
+                    let settings = &mut self.grid_settings;
+                    ui.label(format!("Current zoom: {:.0}px per tile", settings.size));
+                    if ui.button("Zoom in").clicked() {
+                        settings.zoom_center(1.25);
+                    }
+                    if ui.button("Zoom out").clicked() {
+                        settings.zoom_center(0.8);
+                    }
+                    if ui.button("Fit to window").clicked() {
+                        settings.fit_requested = true;
+                    }
+                    ui.separator();
+                    ui.checkbox(&mut self.show_tier_legend, "Show tier legend");
+                    ui.checkbox(&mut self.highlight_bottlenecks, "Highlight bottlenecks");
+                    if ui.button("Highlight min cut").clicked() {
+                        self.find_min_cut();
+                    }
+                    if let Some(cut) = &self.min_cut {
+                        ui.label(format!("Min cut: {} items/s", cut.capacity));
+                        if ui.button("Hide min cut").clicked() {
+                            self.min_cut = None;
                         }
                     }
+                    ui.separator();
+                    if ui
+                        .checkbox(&mut self.model_lanes, "Model belt lanes")
+                        .changed()
+                    {
+                        self.compile(self.entities());
+                    }
                 });
 
                 ui.menu_button("I/O", |ui| {
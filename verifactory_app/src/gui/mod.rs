@@ -3,5 +3,6 @@
 mod app;
 mod grid;
 mod menu;
+mod proofs;
 
 pub use app::MyApp;
@@ -0,0 +1,321 @@
+//! Proof panel, running each proof on its own worker thread
+
+use std::{
+    collections::HashMap,
+    fs,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use verifactory_lib::{
+    backends::{
+        dsl::{CustomProperty, DslError},
+        BlueprintProofEntity, CancelToken, ProofOutcome, ProofResult, Property, SolverOptions,
+    },
+    entities::FBEntity,
+    ir::FlowGraphFun,
+};
+
+use super::app::{FlowOverlay, MyApp};
+
+/// A proof that can be started from the proof panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProofSlot {
+    Standard(Property),
+    Custom,
+}
+
+impl ProofSlot {
+    /// Name of the file the model of the proof is exported to
+    fn smt2_file(&self) -> String {
+        match self {
+            Self::Standard(property) => format!("{}.smt2", property),
+            Self::Custom => "custom.smt2".to_owned(),
+        }
+    }
+}
+
+/// Function modelling a property on the given proof, run on the worker thread
+type ProofJob = Box<dyn FnOnce(&mut BlueprintProofEntity, &[FBEntity<i32>]) -> ProofOutcome + Send>;
+
+pub enum ProofStatus {
+    /// The proof is running on a worker thread
+    Running {
+        started: Instant,
+        cancel: CancelToken,
+        handle: JoinHandle<(ProofOutcome, Option<FlowOverlay>)>,
+    },
+    /// The result of the proof and why it is unknown, if it is
+    Done(ProofResult, Option<String>),
+    Cancelled,
+    /// The custom property could not be parsed or does not fit the blueprint
+    Invalid(DslError),
+}
+
+#[derive(Default)]
+pub struct ProofState {
+    statuses: HashMap<ProofSlot, ProofStatus>,
+    /// Outcome of the last SMT-LIB2 export
+    export_message: Option<String>,
+}
+
+impl ProofState {
+    fn is_running(&self) -> bool {
+        self.statuses
+            .values()
+            .any(|s| matches!(s, ProofStatus::Running { .. }))
+    }
+
+    /// Cancels all the running proofs, their worker threads terminate in the background.
+    pub fn cancel_all(&self) {
+        for status in self.statuses.values() {
+            if let ProofStatus::Running { cancel, .. } = status {
+                cancel.cancel();
+            }
+        }
+    }
+}
+
+/// Draws a value that can be unset, `0` meaning unset.
+fn optional_value(ui: &mut egui::Ui, label: &str, value: &mut Option<u32>) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let mut raw = value.unwrap_or(0);
+        ui.add(egui::DragValue::new(&mut raw));
+        *value = (raw > 0).then_some(raw);
+    });
+}
+
+fn draw_solver_options(ui: &mut egui::Ui, options: &mut SolverOptions) {
+    optional_value(
+        ui,
+        "Timeout per proof in ms (0 for none):",
+        &mut options.timeout_ms,
+    );
+    optional_value(ui, "Resource limit (0 for none):", &mut options.rlimit);
+    optional_value(ui, "Random seed (0 for the default):", &mut options.seed);
+    ui.checkbox(&mut options.model, "Generate counterexamples");
+    ui.horizontal(|ui| {
+        ui.label("Logic (empty for automatic):");
+        let mut logic = options.logic.clone().unwrap_or_default();
+        ui.text_edit_singleline(&mut logic);
+        let logic = logic.trim();
+        options.logic = (!logic.is_empty()).then(|| logic.to_owned());
+    });
+}
+
+impl MyApp {
+    /// Starts the proof on a worker thread with its own z3 context.
+    fn start_proof(&mut self, slot: ProofSlot) {
+        let (reversed, job): (bool, ProofJob) = match slot {
+            ProofSlot::Standard(property) => (
+                property.is_reversed(),
+                Box::new(
+                    move |proof: &mut BlueprintProofEntity, entities: &[FBEntity<i32>]| {
+                        property.model(proof, entities)
+                    },
+                ),
+            ),
+            ProofSlot::Custom => {
+                let property = CustomProperty::parse(&self.custom_property).and_then(|property| {
+                    property.validate(&self.generate_graph(false))?;
+                    Ok(property)
+                });
+                match property {
+                    Ok(property) => (
+                        false,
+                        Box::new(
+                            move |proof: &mut BlueprintProofEntity, _: &[FBEntity<i32>]| {
+                                property.model(proof)
+                            },
+                        ),
+                    ),
+                    Err(e) => {
+                        self.proof_state
+                            .statuses
+                            .insert(slot, ProofStatus::Invalid(e));
+                        return;
+                    }
+                }
+            }
+        };
+
+        let graph = self.generate_graph(reversed);
+        let entities = self.entities();
+        let options = self.solver_options.clone();
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+        let handle = thread::spawn(move || {
+            let mut proof = BlueprintProofEntity::with_options(graph, options);
+            proof.set_cancel_token(token);
+            let outcome = job(&mut proof, &entities);
+            (
+                outcome,
+                FlowOverlay::from_proof(&proof, &entities, reversed),
+            )
+        });
+        let status = ProofStatus::Running {
+            started: Instant::now(),
+            cancel,
+            handle,
+        };
+        self.proof_state.statuses.insert(slot, status);
+    }
+
+    /// Writes the model of the proof to an SMT-LIB2 file in the working directory.
+    fn export_smt2(&self, slot: ProofSlot) -> anyhow::Result<String> {
+        let graph = self.generate_graph(false);
+        let smt2 = match slot {
+            ProofSlot::Standard(property) => {
+                property.to_smt2(&graph, &self.entities(), &self.solver_options)?
+            }
+            ProofSlot::Custom => CustomProperty::parse(&self.custom_property)?
+                .to_smt2(&graph, &self.solver_options)?,
+        };
+        let file = slot.smt2_file();
+        fs::write(&file, smt2)?;
+        Ok(file)
+    }
+
+    /// Collects the results of the finished proofs and shows the counterexample of the last one.
+    fn poll_proofs(&mut self) {
+        let finished = self
+            .proof_state
+            .statuses
+            .iter()
+            .filter(|(_, status)| {
+                matches!(status, ProofStatus::Running { handle, .. } if handle.is_finished())
+            })
+            .map(|(slot, _)| *slot)
+            .collect::<Vec<_>>();
+        for slot in finished {
+            let Some(ProofStatus::Running { cancel, handle, .. }) =
+                self.proof_state.statuses.remove(&slot)
+            else {
+                unreachable!()
+            };
+            let status = match handle.join() {
+                _ if cancel.is_cancelled() => ProofStatus::Cancelled,
+                Ok((outcome, overlay)) => {
+                    self.flow_overlay = overlay;
+                    ProofStatus::Done(outcome.result, outcome.reason_unknown)
+                }
+                /* z3 panicked on the worker thread */
+                Err(_) => ProofStatus::Done(ProofResult::Unknown, Some("crashed".to_owned())),
+            };
+            self.proof_state.statuses.insert(slot, status);
+        }
+    }
+
+    /// Draws the "Prove" button of the proof, or its progress whilst it is running.
+    fn proof_section(&mut self, ui: &mut egui::Ui, slot: ProofSlot) {
+        ui.horizontal(|ui| {
+            let mut start = false;
+            let mut export = false;
+            match self.proof_state.statuses.get(&slot) {
+                Some(ProofStatus::Running {
+                    started, cancel, ..
+                }) => {
+                    ui.spinner();
+                    ui.label(format!(
+                        "Proving... {:.1}s",
+                        started.elapsed().as_secs_f32()
+                    ));
+                    if ui.button("Cancel").clicked() {
+                        cancel.cancel();
+                    }
+                }
+                status => {
+                    start = ui.button("Prove").clicked();
+                    export = ui.button("Export SMT-LIB2").clicked();
+                    match status {
+                        Some(ProofStatus::Done(proof_res, None)) => {
+                            ui.label(format!("Proof result: {}", proof_res));
+                        }
+                        Some(ProofStatus::Done(proof_res, Some(reason))) => {
+                            ui.label(format!("Proof result: {} ({})", proof_res, reason));
+                        }
+                        Some(ProofStatus::Cancelled) => {
+                            ui.label("Proof cancelled");
+                        }
+                        Some(ProofStatus::Invalid(e)) => {
+                            ui.colored_label(egui::Color32::RED, e.to_string());
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            if start {
+                self.start_proof(slot);
+            }
+            if export {
+                let message = match self.export_smt2(slot) {
+                    Ok(file) => format!("Exported the model to {}", file),
+                    Err(e) => format!("Could not export the model: {}", e),
+                };
+                self.proof_state.export_message = Some(message);
+            }
+        });
+        ui.label("\n");
+    }
+
+    pub fn draw_proof_panel(&mut self, ctx: &egui::Context) {
+        self.poll_proofs();
+        if self.proof_state.is_running() {
+            /* update the elapsed time */
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+
+        egui::TopBottomPanel::top("proof_panel").show(ctx, |ui| {
+            ui.heading("Proofs");
+            egui::CollapsingHeader::new("Solver options").show(ui, |ui| {
+                draw_solver_options(ui, &mut self.solver_options);
+            });
+            ui.separator();
+
+            let standard = [
+                (Property::BeltBalancer, "Is it a belt-balancer?"),
+                (
+                    Property::EqualDrain,
+                    "Is it an equal drain belt-balancer (assumes it is a belt-balancer)?",
+                ),
+                (
+                    Property::ThroughputUnlimited,
+                    "Is it a throughput unlimited belt-balancer (assumes it is a belt-balancer)?",
+                ),
+                (Property::Universal, "Is it a universal belt-balancer?"),
+                (Property::LaneBalancer, "Is it a lane balancer?"),
+            ];
+            for (property, heading) in standard {
+                if property.needs_lanes() && !self.model_lanes {
+                    continue;
+                }
+                ui.heading(heading);
+                self.proof_section(ui, ProofSlot::Standard(property));
+            }
+
+            ui.heading("Does it satisfy a custom property?");
+            ui.add(
+                egui::TextEdit::multiline(&mut self.custom_property)
+                    .code_editor()
+                    .desired_rows(3)
+                    .hint_text("forall a in outputs, b in outputs: a == b"),
+            );
+            self.proof_section(ui, ProofSlot::Custom);
+
+            if self.flow_overlay.is_some() && ui.button("Hide counterexample").clicked() {
+                self.flow_overlay = None;
+            }
+            if let Some(message) = &self.proof_state.export_message {
+                ui.label(message);
+            }
+            if ui.button("Save svg").clicked() {
+                self.generate_graph(false).to_svg("out.svg").unwrap();
+            }
+            if ui.button("Save reversed svg").clicked() {
+                self.generate_graph(true).to_svg("out.svg").unwrap();
+            }
+            ui.label("\n");
+        });
+    }
+}
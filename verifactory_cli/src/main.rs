@@ -0,0 +1,875 @@
+//! Command-line front-end of VeriFactory, verifying blueprints without the GUI.
+//!
+//! Exits with `0` if all the properties hold, `1` if any of them does not or can't be proven
+//! and `2` if the blueprint can't be imported or the arguments are invalid.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use clap::{Args, Parser, ValueEnum};
+use serde::Serialize;
+
+use verifactory_lib::{
+    backends::{
+        max_imbalance, max_production, max_throughput, solver::Smt2Process, Counterexample,
+        Imbalance, OptimizeError, Production, ProofResult, Property, SolverOptions, Supply,
+        Throughput,
+    },
+    entities::{EntityId, FBEntity},
+    frontend::Compiler,
+    import::{string_to_blueprints_with_options, ImportOptions, ImportedBlueprint},
+    inserters::InserterModel,
+    ir::{CoalesceStrength, FlowGraph, FlowGraphFun, Node},
+    prototypes::PrototypeRegistry,
+    recipes::RecipeDatabase,
+    utils::Position,
+};
+
+/// Proves properties of the belt balancers contained in a Factorio blueprint or blueprint book
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    source: Source,
+    /// Properties to prove, all but `lane-balancer` if none is given
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    property: Vec<PropertyArg>,
+    /// Input to ignore, given by entity id (`12`) or position (`3,4`) from the top-left corner of the blueprint
+    #[arg(long, value_name = "ID|X,Y")]
+    exclude_input: Vec<Exclusion>,
+    /// Output to ignore, given by entity id (`12`) or position (`3,4`) from the top-left corner of the blueprint
+    #[arg(long, value_name = "ID|X,Y")]
+    exclude_output: Vec<Exclusion>,
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = Format::Human)]
+    format: Format,
+    /// Also write the model of each proof to an SMT-LIB2 file in the directory
+    #[arg(long, value_name = "DIR")]
+    smt2: Option<PathBuf>,
+    /// Also compute the worst-case imbalance between the outputs, over all the inputs
+    #[arg(long)]
+    imbalance: bool,
+    /// Also compute the maximum total throughput and the entities limiting it
+    #[arg(long)]
+    throughput: bool,
+    /// Also check that the blueprint produces at least RATE items/s of ITEM, e.g. `electronic-circuit:7.5`
+    #[arg(long, value_name = "ITEM:RATE")]
+    production: Option<ProductionArg>,
+    /// Item fed by an input for `--production`, optionally limited to RATE items/s, e.g. `1:iron-plate:15`
+    #[arg(long, value_name = "ID:ITEM[:RATE]")]
+    supply: Vec<SupplyArg>,
+    /// JSON file of entity prototypes added to the vanilla ones, e.g. modded belts, see `PrototypeRegistry`
+    #[arg(long, value_name = "FILE")]
+    prototypes: Option<PathBuf>,
+    /// JSON file of recipes added to the vanilla ones, see `RecipeDatabase`
+    #[arg(long, value_name = "FILE")]
+    recipes: Option<PathBuf>,
+    /// Level of the inserter capacity bonus research, from 0 to 7,
+    /// computes the throughput of inserters from what they pick up from and drop to
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u32).range(0..=7))]
+    inserter_capacity_bonus: Option<u32>,
+    /// Items per stack put on belts by the stack inserters of Factorio 2.0, from 1 to 4,
+    /// computes the throughput of inserters like `--inserter-capacity-bonus`
+    #[arg(long, value_name = "SIZE", value_parser = clap::value_parser!(u32).range(1..=4))]
+    belt_stack_size: Option<u32>,
+    #[command(flatten)]
+    solver: SolverArgs,
+}
+
+/// Options of the z3 solver, see `SolverOptions`
+#[derive(Args)]
+#[command(next_help_heading = "Solver options")]
+struct SolverArgs {
+    /// Each proof gives up after the timeout, in milliseconds
+    #[arg(long, value_name = "MS")]
+    timeout: Option<u32>,
+    /// Resource limit of each proof, a deterministic alternative to the timeout
+    #[arg(long)]
+    rlimit: Option<u32>,
+    /// Seed of the random number generator of the solver
+    #[arg(long)]
+    seed: Option<u32>,
+    /// Don't generate models, no counterexamples are printed
+    #[arg(long)]
+    no_model: bool,
+    /// Logic used by the solver, e.g. `LIRA`
+    #[arg(long)]
+    logic: Option<String>,
+    /// External SMT-LIB2 solver used instead of z3, e.g. `"cvc5 --lang=smt2"`
+    #[arg(long, value_name = "COMMAND", value_parser = parse_solver_command)]
+    solver_command: Option<Smt2Process>,
+}
+
+fn parse_solver_command(s: &str) -> Result<Smt2Process, String> {
+    Smt2Process::from_command_line(s).ok_or_else(|| "the command is empty".to_owned())
+}
+
+impl From<&SolverArgs> for SolverOptions {
+    fn from(value: &SolverArgs) -> Self {
+        Self {
+            timeout_ms: value.timeout,
+            rlimit: value.rlimit,
+            seed: value.seed,
+            model: !value.no_model,
+            logic: value.logic.clone(),
+        }
+    }
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct Source {
+    /// File containing the blueprint or blueprint book string, `-` to read it from stdin
+    file: Option<PathBuf>,
+    /// Blueprint or blueprint book string
+    #[arg(short, long, value_name = "BLUEPRINT")]
+    string: Option<String>,
+}
+
+impl Source {
+    fn read(&self) -> anyhow::Result<String> {
+        match (&self.file, &self.string) {
+            (_, Some(string)) => Ok(string.clone()),
+            (Some(file), None) if file.as_os_str() == "-" => {
+                let mut string = String::new();
+                io::stdin().read_to_string(&mut string)?;
+                Ok(string)
+            }
+            (Some(file), None) => {
+                fs::read_to_string(file).with_context(|| format!("Could not read {:?}", file))
+            }
+            (None, None) => unreachable!("clap requires one of the sources"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PropertyArg {
+    Balancer,
+    EqualDrain,
+    ThroughputUnlimited,
+    Universal,
+    LaneBalancer,
+}
+
+impl From<PropertyArg> for Property {
+    fn from(value: PropertyArg) -> Self {
+        match value {
+            PropertyArg::Balancer => Property::BeltBalancer,
+            PropertyArg::EqualDrain => Property::EqualDrain,
+            PropertyArg::ThroughputUnlimited => Property::ThroughputUnlimited,
+            PropertyArg::Universal => Property::Universal,
+            PropertyArg::LaneBalancer => Property::LaneBalancer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Human,
+    Json,
+}
+
+/// Input or output to ignore, referenced by entity id or by position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exclusion {
+    Id(EntityId),
+    Position(Position<i32>),
+}
+
+impl FromStr for Exclusion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<i32>()
+                .map_err(|_| format!("`{}` is neither an entity id nor a position", s))
+        };
+        match s.split_once(',') {
+            Some((x, y)) => Ok(Self::Position(Position {
+                x: parse(x)?,
+                y: parse(y)?,
+            })),
+            None => parse(s).map(Self::Id),
+        }
+    }
+}
+
+impl Exclusion {
+    /// Returns the id of the excluded entity.
+    ///
+    /// Positions count tiles from the leftmost column and the topmost row of the blueprint, at `0,0`,
+    /// with `y` growing downwards as in the game. They are translated to the positions of the imported
+    /// entities, which are padded and whose `y` grows upwards.
+    fn resolve(&self, entities: &[FBEntity<i32>]) -> anyhow::Result<EntityId> {
+        let pos = match self {
+            Self::Id(id) => return Ok(*id),
+            Self::Position(pos) => pos,
+        };
+        let positions = entities.iter().map(|e| e.get_base().position);
+        let min_x = positions.clone().map(|p| p.x).min().unwrap_or(0);
+        let max_y = positions.map(|p| p.y).max().unwrap_or(0);
+        let position = Position {
+            x: min_x + pos.x,
+            y: max_y - pos.y,
+        };
+        entities
+            .iter()
+            .map(|e| e.get_base())
+            .find(|base| base.position == position)
+            .map(|base| base.id)
+            .ok_or_else(|| anyhow!("No entity at position {},{}", pos.x, pos.y))
+    }
+}
+
+/// Parses a rate in items/s, a finite non-negative number.
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate >= 0.0 => Ok(rate),
+        _ => Err(format!("`{}` is not a valid rate", s)),
+    }
+}
+
+/// Production rate to check, see `--production`
+#[derive(Debug, Clone, PartialEq)]
+struct ProductionArg {
+    item: String,
+    rate: f64,
+}
+
+impl FromStr for ProductionArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (item, rate) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("`{}` is not of the form ITEM:RATE", s))?;
+        Ok(Self {
+            item: item.to_owned(),
+            rate: parse_rate(rate)?,
+        })
+    }
+}
+
+/// Item fed by an input, see `--supply`
+#[derive(Debug, Clone, PartialEq)]
+struct SupplyArg {
+    input: EntityId,
+    item: String,
+    rate: Option<f64>,
+}
+
+impl FromStr for SupplyArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (Some(input), Some(item)) = (parts.next(), parts.next()) else {
+            return Err(format!("`{}` is not of the form ID:ITEM[:RATE]", s));
+        };
+        let input = input
+            .trim()
+            .parse()
+            .map_err(|_| format!("`{}` is not an entity id", input))?;
+        let rate = parts.next().map(parse_rate).transpose()?;
+        if parts.next().is_some() {
+            return Err(format!("`{}` is not of the form ID:ITEM[:RATE]", s));
+        }
+        Ok(Self {
+            input,
+            item: item.to_owned(),
+            rate,
+        })
+    }
+}
+
+impl From<&SupplyArg> for Supply {
+    fn from(value: &SupplyArg) -> Self {
+        Self {
+            input: value.input,
+            item: value.item.clone(),
+            rate: value.rate.map(Into::into),
+        }
+    }
+}
+
+/// Result of a property, as printed by the CLI
+#[derive(Serialize)]
+struct PropertyReport {
+    property: String,
+    holds: bool,
+    result: String,
+    /// Why the solver gave up, e.g. `timeout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_unknown: Option<String>,
+    /// Throughput of the inputs and outputs violating the property, as fractions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    counterexample: Option<CounterexampleReport>,
+}
+
+#[derive(Serialize)]
+struct CounterexampleReport {
+    inputs: BTreeMap<EntityId, String>,
+    outputs: BTreeMap<EntityId, String>,
+}
+
+fn rates_to_strings<T: Display>(rates: &HashMap<EntityId, T>) -> BTreeMap<EntityId, String> {
+    rates
+        .iter()
+        .map(|(id, rate)| (*id, rate.to_string()))
+        .collect()
+}
+
+impl From<&Counterexample> for CounterexampleReport {
+    fn from(value: &Counterexample) -> Self {
+        Self {
+            inputs: rates_to_strings(&value.inputs),
+            outputs: rates_to_strings(&value.outputs),
+        }
+    }
+}
+
+/// Worst-case imbalance between the outputs, as printed by the CLI
+#[derive(Serialize)]
+struct ImbalanceReport {
+    /// Largest difference between two outputs, as a fraction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_deviation: Option<String>,
+    /// Largest ratio between two outputs, as a fraction or `unbounded`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_ratio: Option<String>,
+    /// Whether the ratio converged, otherwise it is a lower bound
+    #[serde(skip_serializing_if = "Option::is_none")]
+    converged: Option<bool>,
+    /// Throughput of the inputs and outputs reaching the largest deviation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    witness: Option<CounterexampleReport>,
+    /// Why the optimizer gave up, e.g. `timeout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_unknown: Option<String>,
+}
+
+impl From<Result<Imbalance, OptimizeError>> for ImbalanceReport {
+    fn from(value: Result<Imbalance, OptimizeError>) -> Self {
+        match value {
+            Ok(imbalance) => Self {
+                max_deviation: Some(imbalance.max_deviation.to_string()),
+                max_ratio: Some(match imbalance.max_ratio {
+                    Some(ratio) => ratio.to_string(),
+                    None => "unbounded".to_owned(),
+                }),
+                converged: Some(imbalance.converged),
+                witness: Some((&imbalance.deviation_witness).into()),
+                reason_unknown: None,
+            },
+            Err(e) => Self {
+                max_deviation: None,
+                max_ratio: None,
+                converged: None,
+                witness: None,
+                reason_unknown: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Maximum throughput of a blueprint, as printed by the CLI
+#[derive(Serialize)]
+struct ThroughputReport {
+    /// Largest total throughput of the outputs, as a fraction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<String>,
+    /// Entities running at their capacity at the maximum
+    saturated_entities: Vec<EntityId>,
+    /// Why the optimizer gave up, e.g. `timeout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_unknown: Option<String>,
+}
+
+impl From<Result<Throughput, OptimizeError>> for ThroughputReport {
+    fn from(value: Result<Throughput, OptimizeError>) -> Self {
+        match value {
+            Ok(throughput) => Self {
+                total: Some(throughput.total.to_string()),
+                saturated_entities: throughput.saturated_entities,
+                reason_unknown: None,
+            },
+            Err(e) => Self {
+                total: None,
+                saturated_entities: vec![],
+                reason_unknown: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Production rate of an item, as printed by the CLI
+#[derive(Serialize)]
+struct ProductionReport {
+    item: String,
+    /// Rate the blueprint has to reach, in items/s
+    target: f64,
+    holds: bool,
+    /// Largest rate of the item, as a fraction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate: Option<String>,
+    /// Crafts per second of each assembler at the largest rate, as fractions
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    crafts: BTreeMap<EntityId, String>,
+    /// Why the optimizer gave up, e.g. `timeout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_unknown: Option<String>,
+}
+
+impl ProductionReport {
+    fn new(target: &ProductionArg, value: Result<Production, OptimizeError>) -> Self {
+        let (holds, rate, crafts, reason_unknown) = match value {
+            Ok(production) => (
+                production.reaches(target.rate.into()),
+                Some(production.rate.to_string()),
+                rates_to_strings(&production.crafts),
+                None,
+            ),
+            Err(e) => (false, None, BTreeMap::new(), Some(e.to_string())),
+        };
+        Self {
+            item: target.item.clone(),
+            target: target.rate,
+            holds,
+            rate,
+            crafts,
+            reason_unknown,
+        }
+    }
+}
+
+/// Results of a single blueprint, as printed by the CLI
+#[derive(Serialize)]
+struct BlueprintReport {
+    label: Option<String>,
+    index: Vec<usize>,
+    skipped_entities: usize,
+    results: Vec<PropertyReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    imbalance: Option<ImbalanceReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throughput: Option<ThroughputReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    production: Option<ProductionReport>,
+}
+
+impl BlueprintReport {
+    fn all_hold(&self) -> bool {
+        self.results.iter().all(|r| r.holds) && self.production.as_ref().is_none_or(|p| p.holds)
+    }
+}
+
+/// Returns the ids of the inputs and the outputs of the graph.
+fn io_ids(graph: &FlowGraph) -> (HashSet<EntityId>, HashSet<EntityId>) {
+    let mut inputs = HashSet::new();
+    let mut outputs = HashSet::new();
+    for node in graph.node_weights() {
+        match node {
+            Node::Input(e) => inputs.insert(e.id),
+            Node::Output(e) => outputs.insert(e.id),
+            _ => continue,
+        };
+    }
+    (inputs, outputs)
+}
+
+/// Returns the name of the SMT-LIB2 file of a property, prefixed by the index of the blueprint
+/// in its book, e.g. `0-1-universal.smt2`.
+fn smt2_file_name(index: &[usize], property: &Property) -> String {
+    let prefix = index.iter().map(|i| format!("{}-", i)).collect::<String>();
+    format!("{}{}.smt2", prefix, property)
+}
+
+fn verify(
+    blueprint: &ImportedBlueprint,
+    properties: &[Property],
+    cli: &Cli,
+    registry: &PrototypeRegistry,
+    recipes: &RecipeDatabase,
+    options: &SolverOptions,
+) -> anyhow::Result<BlueprintReport> {
+    let entities = &blueprint.entities;
+    let compiler = Compiler::with_registry(entities.clone(), registry);
+    let full_graph = compiler.create_graph();
+
+    /* check that the excluded entities are inputs or outputs of the blueprint */
+    let (inputs, outputs) = io_ids(&full_graph);
+    let mut removed = vec![];
+    for (exclusions, candidates, kind) in [
+        (&cli.exclude_input, &inputs, "input"),
+        (&cli.exclude_output, &outputs, "output"),
+    ] {
+        for exclusion in exclusions {
+            let id = exclusion.resolve(entities)?;
+            if !candidates.contains(&id) {
+                bail!("Entity {} is not an {} of the blueprint", id, kind);
+            }
+            removed.push(id);
+        }
+    }
+
+    let simplify = |mut graph: FlowGraph| {
+        graph.simplify(&removed, CoalesceStrength::Aggressive);
+        graph
+    };
+    let graph = simplify(full_graph);
+    /* lanes are only modelled if a property needs them */
+    let lane_graph = if properties.iter().any(Property::needs_lanes) {
+        Some(simplify(compiler.create_lane_graph()?))
+    } else {
+        None
+    };
+    let mut results = vec![];
+    for p in properties {
+        let graph = match &lane_graph {
+            Some(lane_graph) if p.needs_lanes() => lane_graph,
+            _ => &graph,
+        };
+        if let Some(dir) = &cli.smt2 {
+            let path = dir.join(smt2_file_name(&blueprint.index, p));
+            fs::write(&path, p.to_smt2(graph, entities, options)?)
+                .with_context(|| format!("Could not write {:?}", path))?;
+        }
+        let outcome = match &cli.solver.solver_command {
+            Some(solver) => p.prove_on(&mut solver.clone(), graph, entities, options),
+            None => p.prove_with(graph, entities, options),
+        };
+        results.push(PropertyReport {
+            property: p.to_string(),
+            holds: matches!(outcome.result, ProofResult::Sat),
+            result: outcome.result.to_string(),
+            reason_unknown: outcome.reason_unknown,
+            counterexample: outcome.counterexample.as_ref().map(Into::into),
+        });
+    }
+    let imbalance = cli.imbalance.then(|| max_imbalance(&graph, options).into());
+    let throughput = cli
+        .throughput
+        .then(|| max_throughput(&graph, None, None, options).into());
+    let production = cli.production.as_ref().map(|target| {
+        let graph = simplify(compiler.create_production_graph(recipes));
+        let supplies = cli.supply.iter().map(Into::into).collect::<Vec<_>>();
+        let production = max_production(&graph, &target.item, &supplies, options);
+        ProductionReport::new(target, production)
+    });
+    Ok(BlueprintReport {
+        label: blueprint.label.clone(),
+        index: blueprint.index.clone(),
+        skipped_entities: blueprint.report.skipped.len(),
+        results,
+        imbalance,
+        throughput,
+        production,
+    })
+}
+
+fn print_counterexample(counterexample: &CounterexampleReport) {
+    for (name, rates) in [
+        ("inputs", &counterexample.inputs),
+        ("outputs", &counterexample.outputs),
+    ] {
+        println!("      {}:", name);
+        for (id, rate) in rates {
+            println!("        {}: {}", id, rate);
+        }
+    }
+}
+
+fn print_human(reports: &[BlueprintReport]) {
+    for report in reports {
+        let name = report.label.as_deref().unwrap_or("Blueprint");
+        if report.index.is_empty() {
+            println!("{}", name);
+        } else {
+            println!("{} {:?}", name, report.index);
+        }
+        if report.skipped_entities > 0 {
+            println!("  skipped {} unsupported entities", report.skipped_entities);
+        }
+        for result in &report.results {
+            match &result.reason_unknown {
+                Some(reason) => println!("  {}: {} ({})", result.property, result.result, reason),
+                None => println!("  {}: {}", result.property, result.result),
+            }
+            if let Some(counterexample) = &result.counterexample {
+                println!("    counterexample:");
+                print_counterexample(counterexample);
+            }
+        }
+        if let Some(imbalance) = &report.imbalance {
+            if let Some(reason) = &imbalance.reason_unknown {
+                println!("  imbalance: unknown ({})", reason);
+            }
+            if let (Some(deviation), Some(ratio)) = (&imbalance.max_deviation, &imbalance.max_ratio)
+            {
+                let bound = match imbalance.converged {
+                    Some(false) => " (lower bound, not converged)",
+                    _ => "",
+                };
+                println!(
+                    "  imbalance: max deviation {}, max ratio {}{}",
+                    deviation, ratio, bound
+                );
+            }
+            if let Some(witness) = &imbalance.witness {
+                println!("    witness:");
+                print_counterexample(witness);
+            }
+        }
+        if let Some(throughput) = &report.throughput {
+            match (&throughput.total, &throughput.reason_unknown) {
+                (_, Some(reason)) => println!("  max throughput: unknown ({})", reason),
+                (Some(total), None) => println!("  max throughput: {}", total),
+                (None, None) => (),
+            }
+            if !throughput.saturated_entities.is_empty() {
+                println!(
+                    "    saturated entities: {:?}",
+                    throughput.saturated_entities
+                );
+            }
+        }
+        if let Some(production) = &report.production {
+            let name = format!("production of {} >= {}", production.item, production.target);
+            match (&production.rate, &production.reason_unknown) {
+                (_, Some(reason)) => println!("  {}: unknown ({})", name, reason),
+                (Some(rate), None) => {
+                    let result = if production.holds { "holds" } else { "fails" };
+                    println!("  {}: {} (max {})", name, result, rate)
+                }
+                (None, None) => (),
+            }
+            for (id, crafts) in &production.crafts {
+                println!("    assembler {}: {} crafts/s", id, crafts);
+            }
+        }
+    }
+}
+
+fn run(cli: &Cli) -> anyhow::Result<bool> {
+    let blueprint_string = cli.source.read()?;
+    let mut import_options = ImportOptions::default();
+    if let Some(file) = &cli.prototypes {
+        let file = file.to_string_lossy();
+        let modded = PrototypeRegistry::from_file(&file)
+            .with_context(|| format!("Could not load the prototypes of {}", file))?;
+        import_options.registry.extend(modded);
+    }
+    if cli.inserter_capacity_bonus.is_some() || cli.belt_stack_size.is_some() {
+        let mut model = InserterModel::vanilla();
+        model.capacity_bonus = cli.inserter_capacity_bonus.unwrap_or(0);
+        model.belt_stack_size = cli.belt_stack_size.unwrap_or(1);
+        import_options.inserters = Some(model);
+    }
+    let blueprints = string_to_blueprints_with_options(blueprint_string.trim(), &import_options)?;
+    let properties: Vec<_> = if cli.property.is_empty() {
+        Property::ALL
+            .into_iter()
+            .filter(|p| !p.needs_lanes())
+            .collect()
+    } else {
+        cli.property.iter().map(|&p| p.into()).collect()
+    };
+
+    let mut recipes = RecipeDatabase::vanilla();
+    if let Some(file) = &cli.recipes {
+        let file = file.to_string_lossy();
+        let modded = RecipeDatabase::from_file(&file)
+            .with_context(|| format!("Could not load the recipes of {}", file))?;
+        recipes.extend(modded);
+    }
+
+    let options = SolverOptions::from(&cli.solver);
+    let reports = blueprints
+        .iter()
+        .map(|blueprint| {
+            verify(
+                blueprint,
+                &properties,
+                cli,
+                &import_options.registry,
+                &recipes,
+                &options,
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    match cli.format {
+        Format::Human => print_human(&reports),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&reports)?),
+    }
+    Ok(reports.iter().all(BlueprintReport::all_hold))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use verifactory_lib::{
+        entities::{FBBaseEntity, FBBelt},
+        utils::Direction,
+    };
+
+    #[test]
+    fn parse_exclusions() {
+        assert_eq!("12".parse(), Ok(Exclusion::Id(12)));
+        assert_eq!(
+            "3, -4".parse(),
+            Ok(Exclusion::Position(Position { x: 3, y: -4 }))
+        );
+        assert!("3,4,5".parse::<Exclusion>().is_err());
+        assert!("output".parse::<Exclusion>().is_err());
+    }
+
+    #[test]
+    fn resolve_positions() {
+        let belt = |id, x, y| {
+            FBEntity::Belt(FBBelt {
+                base: FBBaseEntity {
+                    id,
+                    position: Position { x, y },
+                    direction: Direction::North,
+                    throughput: 15.0,
+                    name: "transport-belt".to_owned(),
+                },
+            })
+        };
+        /* an L of belts, imported with a padding of 2 */
+        let entities = [belt(1, 2, 4), belt(2, 2, 3), belt(3, 3, 3), belt(4, 2, 2)];
+        let resolve = |x, y| Exclusion::Position(Position { x, y }).resolve(&entities);
+        assert_eq!(resolve(0, 0).unwrap(), 1);
+        assert_eq!(resolve(1, 1).unwrap(), 3);
+        assert_eq!(resolve(0, 2).unwrap(), 4);
+        assert!(resolve(1, 0).is_err());
+        assert_eq!(Exclusion::Id(7).resolve(&entities).unwrap(), 7);
+    }
+
+    #[test]
+    fn smt2_file_names() {
+        assert_eq!(smt2_file_name(&[], &Property::Universal), "universal.smt2");
+        assert_eq!(
+            smt2_file_name(&[0, 1], &Property::BeltBalancer),
+            "0-1-belt-balancer.smt2"
+        );
+    }
+
+    #[test]
+    fn cli_args() {
+        let cli = Cli::try_parse_from([
+            "verifactory_cli",
+            "balancer.txt",
+            "-p",
+            "balancer,universal",
+            "--exclude-input",
+            "3",
+            "--format",
+            "json",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.property,
+            vec![PropertyArg::Balancer, PropertyArg::Universal]
+        );
+        assert_eq!(cli.exclude_input, vec![Exclusion::Id(3)]);
+        assert_eq!(cli.format, Format::Json);
+        assert_eq!(SolverOptions::from(&cli.solver), SolverOptions::default());
+
+        let cli = Cli::try_parse_from([
+            "verifactory_cli",
+            "balancer.txt",
+            "--timeout",
+            "5000",
+            "--no-model",
+        ])
+        .unwrap();
+        let options = SolverOptions::from(&cli.solver);
+        assert_eq!(options.timeout_ms, Some(5000));
+        assert!(!options.model);
+        assert!(cli.solver.solver_command.is_none());
+
+        let cli = Cli::try_parse_from([
+            "verifactory_cli",
+            "balancer.txt",
+            "--solver-command",
+            "cvc5 --lang=smt2",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.solver.solver_command,
+            Some(Smt2Process::new("cvc5", &["--lang=smt2"]))
+        );
+        assert!(
+            Cli::try_parse_from(["verifactory_cli", "balancer.txt", "--solver-command", " "])
+                .is_err()
+        );
+
+        let cli = Cli::try_parse_from([
+            "verifactory_cli",
+            "block.txt",
+            "--production",
+            "electronic-circuit:7.5",
+            "--supply",
+            "1:iron-plate",
+            "--supply",
+            "2:copper-cable:22.5",
+        ])
+        .unwrap();
+        let production = cli.production.unwrap();
+        assert_eq!(production.item, "electronic-circuit");
+        assert_eq!(production.rate, 7.5);
+        assert_eq!(cli.supply[0].rate, None);
+        assert_eq!(cli.supply[1].input, 2);
+        assert_eq!(cli.supply[1].rate, Some(22.5));
+        for invalid in [
+            "--production=circuit",
+            "--supply=iron-plate",
+            "--supply=1:a:2:3",
+            "--production=circuit:inf",
+            "--supply=1:iron-plate:NaN",
+            "--supply=1:iron-plate:-1",
+        ] {
+            assert!(Cli::try_parse_from(["verifactory_cli", "block.txt", invalid]).is_err());
+        }
+
+        let cli = Cli::try_parse_from([
+            "verifactory_cli",
+            "block.txt",
+            "--inserter-capacity-bonus",
+            "7",
+        ])
+        .unwrap();
+        assert_eq!(cli.inserter_capacity_bonus, Some(7));
+        assert_eq!(cli.belt_stack_size, None);
+        for invalid in ["--inserter-capacity-bonus=8", "--belt-stack-size=0"] {
+            assert!(Cli::try_parse_from(["verifactory_cli", "block.txt", invalid]).is_err());
+        }
+
+        /* exactly one source is required */
+        assert!(Cli::try_parse_from(["verifactory_cli"]).is_err());
+        assert!(Cli::try_parse_from(["verifactory_cli", "file", "-s", "0eNq"]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Abstract syntax tree of the property language
+
+use std::fmt::Display;
+
+use fraction::GenericFraction;
+
+use crate::entities::EntityId;
+
+/// Inputs or outputs of the blueprint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoKind {
+    Input,
+    Output,
+}
+
+impl Display for IoKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Input => "input",
+            Self::Output => "output",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Reference to an entity, either by its id or by a label declared with `label name = id`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityRef {
+    Id(EntityId),
+    Label(String),
+}
+
+/// `+`, `-`, `*` and `/`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// `==`, `!=`, `<`, `<=`, `>` and `>=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// `and`, `or` and `=>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicOp {
+    And,
+    Or,
+    Implies,
+}
+
+/// `forall` and `exists`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantifier {
+    Forall,
+    Exists,
+}
+
+/// Expression of the property language
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// `true` or `false`
+    Bool(bool),
+    /// Non-negative rational literal, e.g. `7.5`
+    Number(GenericFraction<u128>),
+    /// Input or output of the blueprint, e.g. `input 3` or `output main`
+    Io(IoKind, EntityRef),
+    /// Variable bound by a quantifier
+    Var(String),
+    /// `-e`
+    Neg(Box<Expr>),
+    Arith(ArithOp, Box<Expr>, Box<Expr>),
+    Compare(CmpOp, Box<Expr>, Box<Expr>),
+    /// `not e`
+    Not(Box<Expr>),
+    Logic(LogicOp, Box<Expr>, Box<Expr>),
+    /// `blocked(e)`, where `e` is an input or output
+    Blocked(Box<Expr>),
+    /// `forall o in outputs: e`, the variable ranges over all the inputs or outputs of the blueprint
+    Quantified {
+        quantifier: Quantifier,
+        var: String,
+        domain: IoKind,
+        body: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// Calls `f` on this expression and all of its subexpressions.
+    pub fn visit<F: FnMut(&Expr)>(&self, f: &mut F) {
+        f(self);
+        match self {
+            Self::Neg(e) | Self::Not(e) | Self::Blocked(e) => e.visit(f),
+            Self::Arith(_, a, b) | Self::Compare(_, a, b) | Self::Logic(_, a, b) => {
+                a.visit(f);
+                b.visit(f);
+            }
+            Self::Quantified { body, .. } => body.visit(f),
+            Self::Bool(_) | Self::Number(_) | Self::Io(..) | Self::Var(_) => (),
+        }
+    }
+}
+
+/// A parsed property: the declared labels and the expressions that all have to hold
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub labels: Vec<(String, EntityId)>,
+    pub exprs: Vec<Expr>,
+}
@@ -0,0 +1,142 @@
+//! Type checker of the property language
+
+use std::{collections::HashMap, fmt::Display};
+
+use fraction::GenericFraction;
+
+use crate::entities::EntityId;
+
+use super::{
+    ast::{ArithOp, EntityRef, Expr},
+    DslError,
+};
+
+/// Type of an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Bool,
+    /// Throughput in items/s
+    Rate,
+    /// An input or output, can be used as a rate
+    Io,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Bool => "a boolean",
+            Self::Rate => "a rate",
+            Self::Io => "an input or output",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Resolves a reference to an entity using the declared labels.
+pub fn resolve(
+    labels: &HashMap<String, EntityId>,
+    entity: &EntityRef,
+) -> Result<EntityId, DslError> {
+    match entity {
+        EntityRef::Id(id) => Ok(*id),
+        EntityRef::Label(label) => labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| DslError::UnknownLabel(label.clone())),
+    }
+}
+
+/// Evaluates an expression only made of numbers, returns `None` otherwise.
+pub fn const_value(expr: &Expr) -> Option<GenericFraction<u128>> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::Neg(e) => const_value(e).map(|v| -v),
+        Expr::Arith(op, a, b) => {
+            let (a, b) = (const_value(a)?, const_value(b)?);
+            Some(match op {
+                ArithOp::Add => a + b,
+                ArithOp::Sub => a - b,
+                ArithOp::Mul => a * b,
+                ArithOp::Div => a / b,
+            })
+        }
+        _ => None,
+    }
+}
+
+pub struct Checker<'p> {
+    labels: &'p HashMap<String, EntityId>,
+    /// Variables bound by the enclosing quantifiers
+    scope: Vec<String>,
+}
+
+impl<'p> Checker<'p> {
+    pub fn new(labels: &'p HashMap<String, EntityId>) -> Self {
+        Self {
+            labels,
+            scope: vec![],
+        }
+    }
+
+    fn expect(&mut self, expr: &Expr, expected: Type) -> Result<(), DslError> {
+        let found = self.check(expr)?;
+        /* inputs and outputs are used as their rate in arithmetic */
+        if found == expected || (found, expected) == (Type::Io, Type::Rate) {
+            Ok(())
+        } else {
+            Err(DslError::TypeMismatch { expected, found })
+        }
+    }
+
+    /// Returns the type of the expression, or the first error found in it.
+    pub fn check(&mut self, expr: &Expr) -> Result<Type, DslError> {
+        match expr {
+            Expr::Bool(_) => Ok(Type::Bool),
+            Expr::Number(_) => Ok(Type::Rate),
+            Expr::Io(_, entity) => resolve(self.labels, entity).map(|_| Type::Io),
+            Expr::Var(name) => {
+                if self.scope.contains(name) {
+                    Ok(Type::Io)
+                } else {
+                    Err(DslError::UnboundVariable(name.clone()))
+                }
+            }
+            Expr::Neg(e) => self.expect(e, Type::Rate).map(|_| Type::Rate),
+            Expr::Arith(op, a, b) => {
+                self.expect(a, Type::Rate)?;
+                self.expect(b, Type::Rate)?;
+                /* only linear arithmetic is supported */
+                let is_linear = match op {
+                    ArithOp::Add | ArithOp::Sub => true,
+                    ArithOp::Mul => const_value(a).is_some() || const_value(b).is_some(),
+                    ArithOp::Div => const_value(b).is_some(),
+                };
+                if !is_linear {
+                    return Err(DslError::NonLinear);
+                }
+                if *op == ArithOp::Div && const_value(b) == Some(0.into()) {
+                    return Err(DslError::DivisionByZero);
+                }
+                Ok(Type::Rate)
+            }
+            Expr::Compare(_, a, b) => {
+                self.expect(a, Type::Rate)?;
+                self.expect(b, Type::Rate)?;
+                Ok(Type::Bool)
+            }
+            Expr::Not(e) => self.expect(e, Type::Bool).map(|_| Type::Bool),
+            Expr::Logic(_, a, b) => {
+                self.expect(a, Type::Bool)?;
+                self.expect(b, Type::Bool)?;
+                Ok(Type::Bool)
+            }
+            Expr::Blocked(e) => self.expect(e, Type::Io).map(|_| Type::Bool),
+            Expr::Quantified { var, body, .. } => {
+                self.scope.push(var.clone());
+                let res = self.expect(body, Type::Bool);
+                self.scope.pop();
+                res.map(|_| Type::Bool)
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Lowering of type-checked properties to solver-independent formulas
+
+use std::collections::HashMap;
+
+use fraction::GenericFraction;
+use petgraph::prelude::NodeIndex;
+
+use crate::{
+    backends::solver::{self, GraphModel},
+    entities::EntityId,
+};
+
+use super::{
+    ast::{ArithOp, CmpOp, Expr, IoKind, LogicOp, Quantifier},
+    check::{const_value, resolve},
+};
+
+/// Value of a lowered expression
+enum Value {
+    Bool(solver::Expr),
+    Rate(solver::Expr),
+    Io(IoKind, EntityId),
+}
+
+pub struct Lowering<'a, 'p> {
+    model: &'p GraphModel<'a>,
+    labels: &'p HashMap<String, EntityId>,
+    blocked_inputs: HashMap<EntityId, solver::Expr>,
+    blocked_outputs: HashMap<EntityId, solver::Expr>,
+    /// Inputs and outputs bound by the enclosing quantifiers
+    scope: Vec<(String, IoKind, EntityId)>,
+}
+
+/// Groups the blocked variables by entity, an entity is blocked if any of its lanes is.
+fn blocked_entities(
+    model: &GraphModel,
+    map: &HashMap<NodeIndex, solver::Var>,
+) -> HashMap<EntityId, solver::Expr> {
+    let mut grouped: HashMap<EntityId, Vec<solver::Expr>> = HashMap::new();
+    for (idx, is_blocked) in map {
+        grouped
+            .entry(model.graph[*idx].get_id())
+            .or_default()
+            .push(is_blocked.expr());
+    }
+    grouped
+        .into_iter()
+        .map(|(id, vars)| (id, solver::Expr::Or(vars)))
+        .collect()
+}
+
+impl<'a, 'p> Lowering<'a, 'p> {
+    pub fn new(model: &'p GraphModel<'a>, labels: &'p HashMap<String, EntityId>) -> Self {
+        Self {
+            model,
+            labels,
+            blocked_inputs: blocked_entities(model, &model.blocked_input_map),
+            blocked_outputs: blocked_entities(model, &model.blocked_output_map),
+            scope: vec![],
+        }
+    }
+
+    /// Lowers a boolean expression.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expression is not type-checked or references an input or output missing from the graph.
+    pub fn lower_bool(&mut self, expr: &Expr) -> solver::Expr {
+        match self.lower(expr) {
+            Value::Bool(b) => b,
+            _ => unreachable!("expression is not type-checked"),
+        }
+    }
+
+    fn lower_rate(&mut self, expr: &Expr) -> solver::Expr {
+        match self.lower(expr) {
+            Value::Rate(r) => r,
+            Value::Io(kind, id) => self.rate(kind, id),
+            Value::Bool(_) => unreachable!("expression is not type-checked"),
+        }
+    }
+
+    /// Lowers the product of a constant and a rate.
+    fn scale(&mut self, factor: GenericFraction<u128>, expr: &Expr) -> solver::Expr {
+        solver::Expr::Scale(factor, Box::new(self.lower_rate(expr)))
+    }
+
+    fn rate(&self, kind: IoKind, id: EntityId) -> solver::Expr {
+        let rate = match kind {
+            IoKind::Input => self.model.input_entity_map.get(&id),
+            IoKind::Output => self.model.output_entity_map.get(&id),
+        };
+        rate.cloned()
+            .unwrap_or_else(|| panic!("{} {} is not part of the graph", kind, id))
+    }
+
+    /// Returns the ids of all the inputs or outputs, sorted to keep the lowering deterministic.
+    fn domain(&self, kind: IoKind) -> Vec<EntityId> {
+        let mut ids = match kind {
+            IoKind::Input => self
+                .model
+                .input_entity_map
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            IoKind::Output => self
+                .model
+                .output_entity_map
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+        };
+        ids.sort();
+        ids
+    }
+
+    fn lower(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Bool(b) => Value::Bool(solver::Expr::Bool(*b)),
+            Expr::Number(n) => Value::Rate(solver::Expr::Real(*n)),
+            Expr::Io(kind, entity) => {
+                let id = resolve(self.labels, entity).expect("expression is not type-checked");
+                Value::Io(*kind, id)
+            }
+            Expr::Var(name) => {
+                let (_, kind, id) = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|(var, _, _)| var == name)
+                    .expect("expression is not type-checked");
+                Value::Io(*kind, *id)
+            }
+            Expr::Neg(e) => Value::Rate(self.scale(-GenericFraction::from(1), e)),
+            Expr::Arith(op, a, b) => {
+                /* the type checker ensures one of the factors and the divisor are constant */
+                let rate = match op {
+                    ArithOp::Add => solver::Expr::Add(vec![self.lower_rate(a), self.lower_rate(b)]),
+                    ArithOp::Sub => self.lower_rate(a) - self.lower_rate(b),
+                    ArithOp::Mul => match const_value(a) {
+                        Some(factor) => self.scale(factor, b),
+                        None => {
+                            let factor = const_value(b).expect("expression is not type-checked");
+                            self.scale(factor, a)
+                        }
+                    },
+                    ArithOp::Div => {
+                        let divisor = const_value(b).expect("expression is not type-checked");
+                        self.scale(GenericFraction::from(1) / divisor, a)
+                    }
+                };
+                Value::Rate(rate)
+            }
+            Expr::Compare(op, a, b) => {
+                let (a, b) = (self.lower_rate(a), self.lower_rate(b));
+                let cmp = match op {
+                    CmpOp::Eq => a._eq(b),
+                    CmpOp::Ne => !a._eq(b),
+                    CmpOp::Lt => a.lt(b),
+                    CmpOp::Le => a.le(b),
+                    CmpOp::Gt => b.lt(a),
+                    CmpOp::Ge => a.ge(b),
+                };
+                Value::Bool(cmp)
+            }
+            Expr::Not(e) => Value::Bool(!self.lower_bool(e)),
+            Expr::Logic(op, a, b) => {
+                let (a, b) = (self.lower_bool(a), self.lower_bool(b));
+                let logic = match op {
+                    LogicOp::And => solver::Expr::And(vec![a, b]),
+                    LogicOp::Or => solver::Expr::Or(vec![a, b]),
+                    LogicOp::Implies => a.implies(b),
+                };
+                Value::Bool(logic)
+            }
+            Expr::Blocked(e) => {
+                let Value::Io(kind, id) = self.lower(e) else {
+                    unreachable!("expression is not type-checked")
+                };
+                let blocked = match kind {
+                    IoKind::Input => &self.blocked_inputs,
+                    IoKind::Output => &self.blocked_outputs,
+                };
+                let blocked = blocked.get(&id).cloned();
+                Value::Bool(blocked.unwrap_or_else(|| panic!("{} {} is not blockable", kind, id)))
+            }
+            Expr::Quantified {
+                quantifier,
+                var,
+                domain,
+                body,
+            } => {
+                let bodies = self
+                    .domain(*domain)
+                    .into_iter()
+                    .map(|id| {
+                        self.scope.push((var.clone(), *domain, id));
+                        let body = self.lower_bool(body);
+                        self.scope.pop();
+                        body
+                    })
+                    .collect::<Vec<_>>();
+                let quantified = match quantifier {
+                    Quantifier::Forall => solver::Expr::And(bodies),
+                    Quantifier::Exists => solver::Expr::Or(bodies),
+                };
+                Value::Bool(quantified)
+            }
+        }
+    }
+}
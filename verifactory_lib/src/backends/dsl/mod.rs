@@ -0,0 +1,419 @@
+//! Language to express custom properties of the flows of a blueprint.
+//!
+//! A property is a list of expressions separated by `;`, all of which have to hold for every possible
+//! combination of inputs. Labels can be given to entities with `label name = id`:
+//! ```text
+//! label splitter = 12;
+//! # the output of the splitter always gets at least half of input 3
+//! output splitter >= input 3 / 2;
+//! forall o in outputs: not blocked(o) => o <= 15
+//! ```
+//!
+//! Expressions, from the lowest to the highest precedence:
+//!  - quantifiers over the inputs or outputs of the blueprint: `forall i in inputs, o in outputs: ...`, `exists o in outputs: ...`
+//!  - implication `a => b`, disjunction `a or b`, conjunction `a and b` and negation `not a`
+//!  - comparison of rates: `==`, `!=`, `<`, `<=`, `>` and `>=`
+//!  - linear arithmetic over rates: `+`, `-`, `*` and `/`, one of the factors and the divisor have to be constant
+//!  - numbers like `15` or `7.5`, `true`, `false`, `(...)`, inputs and outputs by id `input 3` or label `output splitter`,
+//!    variables bound by a quantifier and `blocked(x)` for an input or output `x`
+//!
+//! Inputs and outputs stand for their rate in items/s, summing all of their lanes.
+//! Using `blocked` requires modelling the graph with [`ModelFlags::Blocked`], see [`CustomProperty::flags`].
+
+mod ast;
+mod check;
+mod lower;
+mod parser;
+
+use std::{collections::HashMap, fmt::Display};
+
+#[cfg(feature = "z3")]
+use z3::ast::Bool;
+
+use crate::{
+    entities::EntityId,
+    ir::{FlowGraph, Node},
+};
+
+pub use self::{ast::IoKind, check::Type};
+
+use self::{
+    ast::Expr,
+    check::{resolve, Checker},
+    lower::Lowering,
+};
+
+use super::{
+    solver::{self, EncodeError, GraphModel, Problem, SolverBackend},
+    ModelFlags, ProofOutcome, ProofResult, SolverOptions,
+};
+
+#[cfg(feature = "z3")]
+use super::{BlueprintProofEntity, ProofPrimitives};
+
+/// Errors of parsing, type-checking or validating a [`CustomProperty`]
+#[derive(Debug)]
+pub enum DslError {
+    /// A character that is not part of the language
+    UnexpectedChar { position: usize, found: char },
+    /// A token that is not expected at this position
+    UnexpectedToken {
+        position: usize,
+        found: String,
+        expected: &'static str,
+    },
+    /// The property ends too early
+    UnexpectedEnd { expected: &'static str },
+    /// A number literal or entity id that can't be parsed
+    InvalidNumber { position: usize, literal: String },
+    /// The property does not contain any expression
+    EmptyProperty,
+    /// The same label is declared twice
+    DuplicateLabel(String),
+    /// The label is not declared
+    UnknownLabel(String),
+    /// The variable is not bound by a quantifier
+    UnboundVariable(String),
+    /// An expression does not have the expected type, e.g. `blocked(1)`
+    TypeMismatch { expected: Type, found: Type },
+    /// Multiplication of two rates or division by a rate
+    NonLinear,
+    /// Division by a constant that is zero
+    DivisionByZero,
+    /// The referenced input or output is not part of the graph
+    UnknownEntity { kind: IoKind, id: EntityId },
+    /// The lowered property can't be written for a solver, e.g. because of an infinite number
+    Encode(EncodeError),
+}
+
+impl Display for DslError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedChar { position, found } => {
+                write!(f, "Unexpected character `{}` at {}", found, position)
+            }
+            Self::UnexpectedToken {
+                position,
+                found,
+                expected,
+            } => write!(f, "Expected {} at {}, found {}", expected, position, found),
+            Self::UnexpectedEnd { expected } => {
+                write!(f, "Expected {}, found the end of the property", expected)
+            }
+            Self::InvalidNumber { position, literal } => {
+                write!(f, "Invalid number `{}` at {}", literal, position)
+            }
+            Self::EmptyProperty => write!(f, "The property does not contain any expression"),
+            Self::DuplicateLabel(label) => write!(f, "Label `{}` is declared twice", label),
+            Self::UnknownLabel(label) => write!(f, "Unknown label `{}`", label),
+            Self::UnboundVariable(var) => write!(f, "Unknown variable `{}`", var),
+            Self::TypeMismatch { expected, found } => {
+                write!(f, "Expected {}, found {}", expected, found)
+            }
+            Self::NonLinear => write!(
+                f,
+                "Only linear arithmetic is supported, multiply or divide by constants"
+            ),
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::UnknownEntity { kind, id } => {
+                write!(f, "Entity {} is not an {} of the blueprint", id, kind)
+            }
+            Self::Encode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DslError {}
+
+/// A property written in the property language, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct CustomProperty {
+    labels: HashMap<String, EntityId>,
+    exprs: Vec<Expr>,
+}
+
+impl CustomProperty {
+    /// Parses and type-checks a property.
+    pub fn parse(source: &str) -> Result<Self, DslError> {
+        let program = parser::parse(source)?;
+        let mut labels = HashMap::new();
+        for (label, id) in program.labels {
+            if labels.insert(label.clone(), id).is_some() {
+                return Err(DslError::DuplicateLabel(label));
+            }
+        }
+        let mut checker = Checker::new(&labels);
+        for expr in &program.exprs {
+            let found = checker.check(expr)?;
+            if found != Type::Bool {
+                return Err(DslError::TypeMismatch {
+                    expected: Type::Bool,
+                    found,
+                });
+            }
+        }
+        Ok(Self {
+            labels,
+            exprs: program.exprs,
+        })
+    }
+
+    /// Returns the flags needed to model the graph for this property.
+    pub fn flags(&self) -> ModelFlags {
+        let mut uses_blocked = false;
+        for expr in &self.exprs {
+            expr.visit(&mut |e| uses_blocked |= matches!(e, Expr::Blocked(_)));
+        }
+        if uses_blocked {
+            ModelFlags::Blocked
+        } else {
+            ModelFlags::empty()
+        }
+    }
+
+    /// Checks that all the inputs and outputs referenced by id or label are part of the graph.
+    pub fn validate(&self, graph: &FlowGraph) -> Result<(), DslError> {
+        let mut references = vec![];
+        for expr in &self.exprs {
+            expr.visit(&mut |e| {
+                if let Expr::Io(kind, entity) = e {
+                    references.push((*kind, entity.clone()));
+                }
+            });
+        }
+        for (kind, entity) in references {
+            let id = resolve(&self.labels, &entity)?;
+            let exists = graph.node_weights().any(|node| match (kind, node) {
+                (IoKind::Input, Node::Input(i)) => i.id == id,
+                (IoKind::Output, Node::Output(o)) => o.id == id,
+                _ => false,
+            });
+            if !exists {
+                return Err(DslError::UnknownEntity { kind, id });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the negation of the property on the `model`, whose solutions are counter-examples.
+    ///
+    /// The model has to be created with the [`CustomProperty::flags`] of the property.
+    ///
+    /// # Precondition
+    ///
+    /// The property has been validated against the graph, see [`CustomProperty::validate`].
+    pub fn negation(&self, model: &GraphModel) -> solver::Expr {
+        let mut lowering = Lowering::new(model, &self.labels);
+        let conditions = self
+            .exprs
+            .iter()
+            .map(|expr| lowering.lower_bool(expr))
+            .collect();
+        // Correct model and NOT property
+        let mut negation = model.blocking_constraint.clone();
+        negation.extend(model.model_constraint.iter().cloned());
+        negation.push(!solver::Expr::And(conditions));
+        solver::Expr::And(negation)
+    }
+
+    /// Validates the property and proves it on a simplified graph.
+    #[cfg(feature = "z3")]
+    pub fn prove(&self, graph: &FlowGraph) -> Result<ProofOutcome, DslError> {
+        self.prove_with(graph, &SolverOptions::default())
+    }
+
+    /// Validates the property and proves it on a simplified graph with a solver configured by the `options`,
+    /// see [`CustomProperty::prove`].
+    #[cfg(feature = "z3")]
+    pub fn prove_with(
+        &self,
+        graph: &FlowGraph,
+        options: &SolverOptions,
+    ) -> Result<ProofOutcome, DslError> {
+        self.validate(graph)?;
+        let mut proof = BlueprintProofEntity::with_options(graph.clone(), options.clone());
+        Ok(self.model(&mut proof))
+    }
+
+    /// Models the property on the graph of the `proof`, with the [`CustomProperty::flags`] it needs.
+    ///
+    /// # Precondition
+    ///
+    /// The property has been validated against the graph, see [`CustomProperty::validate`].
+    #[cfg(feature = "z3")]
+    pub fn model(&self, proof: &mut BlueprintProofEntity) -> ProofOutcome {
+        proof.model(custom_property_f(self.clone()), self.flags())
+    }
+
+    /// Validates the property and proves it on a simplified graph with the given backend.
+    ///
+    /// Errors of the backend result in [`ProofResult::Unknown`], with the error as reason.
+    pub fn prove_on(
+        &self,
+        backend: &mut dyn SolverBackend,
+        graph: &FlowGraph,
+        options: &SolverOptions,
+    ) -> Result<ProofOutcome, DslError> {
+        self.validate(graph)?;
+        let model = GraphModel::new(graph, self.flags());
+        let problem = Problem {
+            assertions: vec![self.negation(&model)],
+        };
+        let outcome = match backend.check(&problem, options) {
+            Ok(solution) => ProofOutcome {
+                result: solution.result.not(),
+                counterexample: solution.model.map(|values| model.counterexample(&values)),
+                reason_unknown: solution.reason_unknown,
+            },
+            Err(e) => ProofOutcome {
+                result: ProofResult::Unknown,
+                counterexample: None,
+                reason_unknown: Some(e.to_string()),
+            },
+        };
+        Ok(outcome)
+    }
+
+    /// Validates the property and returns the constraints checked by [`CustomProperty::prove`]
+    /// in the SMT-LIB2 format.
+    pub fn to_smt2(&self, graph: &FlowGraph, options: &SolverOptions) -> Result<String, DslError> {
+        self.validate(graph)?;
+        let model = GraphModel::new(graph, self.flags());
+        let problem = Problem {
+            assertions: vec![self.negation(&model)],
+        };
+        problem.export(options).map_err(DslError::Encode)
+    }
+}
+
+/// Function to prove if a given z3 model satisfies a [`CustomProperty`], see [`CustomProperty::negation`]
+///
+/// # Precondition
+///
+/// The property has been validated against the graph, see [`CustomProperty::validate`],
+/// and the graph is modelled with its [`CustomProperty::flags`].
+#[cfg(feature = "z3")]
+pub(crate) fn custom_property_f<'a>(
+    property: CustomProperty,
+) -> impl Fn(ProofPrimitives<'a>) -> Result<Bool<'a>, EncodeError> {
+    move |p: ProofPrimitives<'a>| p.lower(&property.negation(&p.model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::graph;
+
+    #[cfg(feature = "z3")]
+    fn prove(graph: &FlowGraph, source: &str) -> ProofResult {
+        let property = CustomProperty::parse(source).unwrap();
+        property.prove(graph).unwrap().result
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn belt_properties() {
+        /* belts 1 to 3 going south */
+        let holds = [
+            "output 3 == input 1",
+            "label last = 3; output last >= input 1 / 2",
+            "forall i in inputs, o in outputs: 2 * o - i == i",
+            "exists o in outputs: o <= 15",
+        ];
+        let graph = graph("tests/simple_belt", &[]);
+        for source in holds {
+            assert!(matches!(prove(&graph, source), ProofResult::Sat));
+        }
+        let outcome = CustomProperty::parse("output 3 < input 1")
+            .unwrap()
+            .prove(&graph)
+            .unwrap();
+        assert!(matches!(outcome.result, ProofResult::Unsat));
+        assert!(outcome.counterexample.is_some());
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn balancer_properties() {
+        let balanced = "forall a in outputs, b in outputs: a == b";
+        let graph_4_4 = graph("tests/4-4", &[3]);
+        assert!(matches!(prove(&graph_4_4, balanced), ProofResult::Sat));
+        let graph_3_2 = graph("tests/3-2-broken", &[4, 5, 6]);
+        assert!(matches!(prove(&graph_3_2, balanced), ProofResult::Unsat));
+
+        let options = SolverOptions {
+            model: false,
+            ..Default::default()
+        };
+        let outcome = CustomProperty::parse(balanced)
+            .unwrap()
+            .prove_with(&graph_3_2, &options)
+            .unwrap();
+        assert!(matches!(outcome.result, ProofResult::Unsat));
+        /* no model, no counterexample */
+        assert!(outcome.counterexample.is_none());
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn blocked_properties() {
+        let property =
+            CustomProperty::parse("forall o in outputs: blocked(o) or not blocked(o)").unwrap();
+        assert!(property.flags().contains(ModelFlags::Blocked));
+        let outcome = property.prove(&graph("tests/4-4-tu", &[])).unwrap();
+        assert!(matches!(outcome.result, ProofResult::Sat));
+    }
+
+    #[test]
+    fn export_smt2() {
+        let graph = graph("tests/simple_belt", &[]);
+        let property = CustomProperty::parse("output 3 == input 1").unwrap();
+        let smt2 = property.to_smt2(&graph, &SolverOptions::default()).unwrap();
+        assert!(smt2.contains("output_3"));
+        assert!(smt2.contains("(check-sat)"));
+        let property = CustomProperty::parse("output 42 > 0").unwrap();
+        assert!(property.to_smt2(&graph, &SolverOptions::default()).is_err());
+    }
+
+    #[test]
+    fn semantic_errors() {
+        let error = |source| CustomProperty::parse(source).unwrap_err();
+        assert!(matches!(error("output x == 1"), DslError::UnknownLabel(_)));
+        assert!(matches!(
+            error("label a = 1; label a = 2; input a > 0"),
+            DslError::DuplicateLabel(_)
+        ));
+        assert!(matches!(error("o > 0"), DslError::UnboundVariable(_)));
+        assert!(matches!(
+            error("input 1 * input 2 > 0"),
+            DslError::NonLinear
+        ));
+        assert!(matches!(
+            error("input 1 / (2 - 2) > 0"),
+            DslError::DivisionByZero
+        ));
+        assert!(matches!(
+            error("blocked(1)"),
+            DslError::TypeMismatch {
+                expected: Type::Io,
+                found: Type::Rate
+            }
+        ));
+        assert!(matches!(
+            error("input 1 + 1"),
+            DslError::TypeMismatch {
+                expected: Type::Bool,
+                ..
+            }
+        ));
+
+        let property = CustomProperty::parse("output 42 > 0").unwrap();
+        assert!(matches!(
+            property.validate(&graph("tests/simple_belt", &[])),
+            Err(DslError::UnknownEntity {
+                kind: IoKind::Output,
+                id: 42
+            })
+        ));
+    }
+}
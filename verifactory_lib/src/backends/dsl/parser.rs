@@ -0,0 +1,459 @@
+//! Tokenizer and recursive descent parser of the property language
+
+use std::fmt::Display;
+
+use fraction::GenericFraction;
+
+use super::{
+    ast::{ArithOp, CmpOp, EntityRef, Expr, IoKind, LogicOp, Program, Quantifier},
+    DslError,
+};
+
+const KEYWORDS: [&str; 14] = [
+    "label", "forall", "exists", "in", "inputs", "outputs", "input", "output", "blocked", "not",
+    "and", "or", "true", "false",
+];
+
+/// Symbols, longest first so that e.g. `<=` is not read as `<`
+const SYMBOLS: [&str; 17] = [
+    "=>", "==", "!=", "<=", ">=", "(", ")", ":", ";", ",", "+", "-", "*", "/", "<", ">", "=",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ident(s) | Self::Number(s) => write!(f, "`{}`", s),
+            Self::Symbol(s) => write!(f, "`{}`", s),
+        }
+    }
+}
+
+/// Splits the source into tokens together with their byte position.
+///
+/// Comments start with `#` and last until the end of the line.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, DslError> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(position, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+        } else if c.is_ascii_digit() {
+            let mut literal = String::new();
+            while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '.') {
+                literal.push(c);
+            }
+            tokens.push((position, Token::Number(literal)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some((_, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_') {
+                ident.push(c);
+            }
+            tokens.push((position, Token::Ident(ident)));
+        } else {
+            let rest = &source[position..];
+            let symbol = SYMBOLS
+                .into_iter()
+                .find(|s| rest.starts_with(s))
+                .ok_or(DslError::UnexpectedChar { position, found: c })?;
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((position, Token::Symbol(symbol)));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses a non-negative decimal literal, e.g. `15` or `7.5`.
+fn parse_number(position: usize, literal: &str) -> Result<GenericFraction<u128>, DslError> {
+    let invalid = || DslError::InvalidNumber {
+        position,
+        literal: literal.to_owned(),
+    };
+    let (integer, decimals) = literal.split_once('.').unwrap_or((literal, ""));
+    if integer.is_empty() || decimals.contains('.') {
+        return Err(invalid());
+    }
+    let numer = format!("{}{}", integer, decimals)
+        .parse::<u128>()
+        .map_err(|_| invalid())?;
+    let denom = 10u128
+        .checked_pow(decimals.len() as u32)
+        .ok_or_else(invalid)?;
+    Ok(GenericFraction::new(numer, denom))
+}
+
+/// Parses a whole property.
+pub fn parse(source: &str) -> Result<Program, DslError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: source.len(),
+    };
+    parser.program()
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Position of the end of the source
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    /// Returns the position of the next token.
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(s)) if s == keyword)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn unexpected(&self, expected: &'static str) -> DslError {
+        match self.peek() {
+            Some(token) => DslError::UnexpectedToken {
+                position: self.position(),
+                found: token.to_string(),
+                expected,
+            },
+            None => DslError::UnexpectedEnd { expected },
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<(), DslError> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(symbol))
+        }
+    }
+
+    /// Parses an identifier that is not a keyword.
+    fn ident(&mut self, expected: &'static str) -> Result<String, DslError> {
+        match self.peek() {
+            Some(Token::Ident(s)) if !KEYWORDS.contains(&s.as_str()) => {
+                let ident = s.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn entity_id(&mut self) -> Result<i32, DslError> {
+        let position = self.position();
+        match self.peek() {
+            Some(Token::Number(literal)) => {
+                let id = literal.parse().map_err(|_| DslError::InvalidNumber {
+                    position,
+                    literal: literal.clone(),
+                })?;
+                self.pos += 1;
+                Ok(id)
+            }
+            _ => Err(self.unexpected("an entity id")),
+        }
+    }
+
+    /// program := (`label` ident `=` id | expr) (`;` program)?
+    fn program(&mut self) -> Result<Program, DslError> {
+        let mut labels = vec![];
+        let mut exprs = vec![];
+        while self.peek().is_some() {
+            if self.eat_keyword("label") {
+                let name = self.ident("a label name")?;
+                self.expect_symbol("=")?;
+                labels.push((name, self.entity_id()?));
+            } else {
+                exprs.push(self.expr()?);
+            }
+            if !self.eat_symbol(";") {
+                break;
+            }
+        }
+        if self.peek().is_some() {
+            return Err(self.unexpected("`;` or the end of the property"));
+        }
+        if exprs.is_empty() {
+            return Err(DslError::EmptyProperty);
+        }
+        Ok(Program { labels, exprs })
+    }
+
+    /// expr := quantified | or (`=>` expr)?
+    fn expr(&mut self) -> Result<Expr, DslError> {
+        if let Some(quantified) = self.quantified()? {
+            return Ok(quantified);
+        }
+        let lhs = self.or()?;
+        if self.eat_symbol("=>") {
+            let rhs = self.expr()?;
+            return Ok(Expr::Logic(LogicOp::Implies, Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    /// quantified := (`forall` | `exists`) ident `in` (`inputs` | `outputs`) (`,` ...)* `:` expr
+    fn quantified(&mut self) -> Result<Option<Expr>, DslError> {
+        let quantifier = if self.eat_keyword("forall") {
+            Quantifier::Forall
+        } else if self.eat_keyword("exists") {
+            Quantifier::Exists
+        } else {
+            return Ok(None);
+        };
+        let mut bindings = vec![];
+        loop {
+            let var = self.ident("a variable name")?;
+            if !self.eat_keyword("in") {
+                return Err(self.unexpected("`in`"));
+            }
+            let domain = if self.eat_keyword("inputs") {
+                IoKind::Input
+            } else if self.eat_keyword("outputs") {
+                IoKind::Output
+            } else {
+                return Err(self.unexpected("`inputs` or `outputs`"));
+            };
+            bindings.push((var, domain));
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(":")?;
+        let body = self.expr()?;
+        let quantified = bindings
+            .into_iter()
+            .rev()
+            .fold(body, |body, (var, domain)| Expr::Quantified {
+                quantifier,
+                var,
+                domain,
+                body: Box::new(body),
+            });
+        Ok(Some(quantified))
+    }
+
+    /// or := and (`or` and)*
+    fn or(&mut self) -> Result<Expr, DslError> {
+        let mut lhs = self.and()?;
+        while self.eat_keyword("or") {
+            let rhs = self.and()?;
+            lhs = Expr::Logic(LogicOp::Or, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    /// and := not (`and` not)*
+    fn and(&mut self) -> Result<Expr, DslError> {
+        let mut lhs = self.not()?;
+        while self.eat_keyword("and") {
+            let rhs = self.not()?;
+            lhs = Expr::Logic(LogicOp::And, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    /// not := `not` not | comparison
+    fn not(&mut self) -> Result<Expr, DslError> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    /// comparison := sum (cmp sum)?
+    fn comparison(&mut self) -> Result<Expr, DslError> {
+        let lhs = self.sum()?;
+        let ops = [
+            ("==", CmpOp::Eq),
+            ("!=", CmpOp::Ne),
+            ("<=", CmpOp::Le),
+            (">=", CmpOp::Ge),
+            ("<", CmpOp::Lt),
+            (">", CmpOp::Gt),
+        ];
+        for (symbol, op) in ops {
+            if self.eat_symbol(symbol) {
+                let rhs = self.sum()?;
+                return Ok(Expr::Compare(op, Box::new(lhs), Box::new(rhs)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    /// sum := term ((`+` | `-`) term)*
+    fn sum(&mut self) -> Result<Expr, DslError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                ArithOp::Add
+            } else if self.eat_symbol("-") {
+                ArithOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.term()?;
+            lhs = Expr::Arith(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    /// term := unary ((`*` | `/`) unary)*
+    fn term(&mut self) -> Result<Expr, DslError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                ArithOp::Mul
+            } else if self.eat_symbol("/") {
+                ArithOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.unary()?;
+            lhs = Expr::Arith(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    /// unary := `-` unary | atom
+    fn unary(&mut self) -> Result<Expr, DslError> {
+        if self.eat_symbol("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    /// atom := number | `true` | `false` | `(` expr `)` | (`input` | `output`) (id | label)
+    ///       | `blocked` `(` expr `)` | quantified | variable
+    fn atom(&mut self) -> Result<Expr, DslError> {
+        let position = self.position();
+        if let Some(Token::Number(literal)) = self.peek() {
+            let number = parse_number(position, literal)?;
+            self.pos += 1;
+            return Ok(Expr::Number(number));
+        }
+        if self.eat_symbol("(") {
+            let expr = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+        if self.eat_keyword("true") {
+            return Ok(Expr::Bool(true));
+        }
+        if self.eat_keyword("false") {
+            return Ok(Expr::Bool(false));
+        }
+        for (keyword, kind) in [("input", IoKind::Input), ("output", IoKind::Output)] {
+            if self.eat_keyword(keyword) {
+                let entity = match self.peek() {
+                    Some(Token::Number(_)) => EntityRef::Id(self.entity_id()?),
+                    _ => EntityRef::Label(self.ident("an entity id or label")?),
+                };
+                return Ok(Expr::Io(kind, entity));
+            }
+        }
+        if self.eat_keyword("blocked") {
+            self.expect_symbol("(")?;
+            let expr = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(Expr::Blocked(Box::new(expr)));
+        }
+        if let Some(quantified) = self.quantified()? {
+            return Ok(quantified);
+        }
+        Ok(Expr::Var(self.ident("an expression")?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        let program = parse("label main = 12; output main >= input 3 / 2 and not false").unwrap();
+        assert_eq!(program.labels, vec![("main".to_owned(), 12)]);
+        let output = Expr::Io(IoKind::Output, EntityRef::Label("main".to_owned()));
+        let half = Expr::Arith(
+            ArithOp::Div,
+            Box::new(Expr::Io(IoKind::Input, EntityRef::Id(3))),
+            Box::new(Expr::Number(2.into())),
+        );
+        let expected = Expr::Logic(
+            LogicOp::And,
+            Box::new(Expr::Compare(CmpOp::Ge, Box::new(output), Box::new(half))),
+            Box::new(Expr::Not(Box::new(Expr::Bool(false)))),
+        );
+        assert_eq!(program.exprs, vec![expected]);
+    }
+
+    #[test]
+    fn numbers_and_comments() {
+        let program = parse("# half a yellow belt\n7.5 == 15 / 2").unwrap();
+        let Expr::Compare(_, lhs, _) = &program.exprs[0] else {
+            panic!()
+        };
+        assert_eq!(**lhs, Expr::Number(GenericFraction::new(15u128, 2u128)));
+        assert!(matches!(
+            parse("1.2.3 > 0"),
+            Err(DslError::InvalidNumber { position: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(matches!(
+            parse("output 3 >="),
+            Err(DslError::UnexpectedEnd { .. })
+        ));
+        assert!(matches!(
+            parse("forall o outputs: o > 0"),
+            Err(DslError::UnexpectedToken { position: 9, .. })
+        ));
+        assert!(matches!(
+            parse("output 3 ? 1"),
+            Err(DslError::UnexpectedChar {
+                position: 9,
+                found: '?'
+            })
+        ));
+        assert!(matches!(
+            parse("label a = 1;"),
+            Err(DslError::EmptyProperty)
+        ));
+    }
+}
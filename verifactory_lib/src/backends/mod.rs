@@ -1,11 +1,35 @@
-//! Back-end used to convert the IR into a z3 model
-mod model_entities;
+//! Back-end used to convert the IR into formulas for a solver, see [`solver`]
+//!
+//! The z3 bindings are only available with the `z3` feature, enabled by default.
+//! Without it, properties are proven with an external solver, see [`solver::Smt2Process`].
+pub mod dsl;
+#[cfg(feature = "z3")]
 mod model_graph;
+#[cfg(feature = "z3")]
+mod optimize;
+#[cfg(feature = "z3")]
+mod production;
 mod proofs;
+pub mod solver;
 
-pub use self::proofs::{BlueprintProofEntity, ProofResult};
+pub use self::proofs::{
+    CancelToken, Counterexample, ProofOutcome, ProofResult, Property, SolverOptions,
+};
+
+#[cfg(feature = "z3")]
+pub use self::proofs::{
+    verify_blueprints, verify_book, BlueprintProofEntity, BlueprintVerification,
+};
+
+#[cfg(feature = "z3")]
+pub use self::optimize::{max_imbalance, max_throughput, Imbalance, OptimizeError, Throughput};
+
+#[cfg(feature = "z3")]
+pub use self::production::max_production;
+
+pub use self::solver::{ModelFlags, Production, Supply};
 
+#[cfg(feature = "z3")]
 pub use model_graph::{
-    belt_balancer_f, equal_drain_f, model_f, throughput_unlimited, universal_balancer, ModelFlags,
-    ProofPrimitives,
+    belt_balancer_f, equal_drain_f, lane_balancer_f, model_f, universal_balancer, ProofPrimitives,
 };
@@ -1,336 +1,139 @@
-use bitflags::bitflags;
-use petgraph::prelude::{EdgeIndex, NodeIndex};
-use std::{collections::HashMap, mem};
-use z3::{
-    ast::{exists_const, forall_const, Ast, Bool, Int, Real},
-    Context, Solver,
-};
+//! Proofs with the z3 bindings, on a [`GraphModel`] lowered to z3
+//!
+//! The properties are the ones of the [`GraphModel`], these functions only lower them.
 
-use crate::{entities::FBEntity, ir::FlowGraph};
+use std::collections::HashMap;
 
-use super::proofs::ProofResult;
+use z3::{
+    ast::{Bool, Int, Real},
+    Context, Model,
+};
 
-use super::model_entities::{Z3Edge, Z3Node};
+use crate::{entities::EntityId, ir::FlowGraph};
 
-#[derive(Default)]
-pub struct Z3QuantHelper<'a> {
-    pub edge_map: HashMap<EdgeIndex, Real<'a>>,
-    pub input_map: HashMap<NodeIndex, Int<'a>>,
-    pub output_map: HashMap<NodeIndex, Real<'a>>,
-    pub input_const: Vec<Bool<'a>>,
-    pub others: Vec<Bool<'a>>,
-    pub blocked_edge_map: HashMap<EdgeIndex, Bool<'a>>,
-    pub blocked_input_map: HashMap<NodeIndex, Bool<'a>>,
-    pub blocked_output_map: HashMap<NodeIndex, Bool<'a>>,
-    pub blocking: Vec<Bool<'a>>,
-}
+use super::proofs::{Counterexample, ProofOutcome, ProofResult, SolverOptions};
+use super::solver::{new_solver, EncodeError, Expr, GraphModel, Lowering, ModelFlags};
 
+/// A [`GraphModel`] lowered to z3
 #[derive(Debug, Clone)]
 pub struct ProofPrimitives<'a> {
     /// Z3 context
     pub ctx: &'a Context,
-    /// Flowgraph associated with the proof
-    pub graph: &'a FlowGraph,
-    /// `Vec` of all the input throughput variables in z3
-    pub input_bounds: Vec<Int<'a>>,
-    /// Map from `NodeIndex` to the associated throughput variable in z3
-    pub input_map: HashMap<NodeIndex, Int<'a>>,
-    /// `Vec` of all the output throughput variables in z3
-    pub output_bounds: Vec<Real<'a>>,
-    /// Map from `NodeIndex` to the associated throughput variable in z3
-    pub output_map: HashMap<NodeIndex, Real<'a>>,
-    /// Map from `NodeIndex` to the associated input blocked variable in z3
-    pub blocked_input_map: HashMap<NodeIndex, Bool<'a>>,
-    /// Map from `NodeIndex` to the associated output blocked variable in z3
-    pub blocked_output_map: HashMap<NodeIndex, Bool<'a>>,
-    /// min. and max. throughput of an edge constraint
-    pub edge_bounds: Vec<Real<'a>>,
+    /// Variables and constraints of the graph, see [`ProofPrimitives::lower`]
+    pub model: GraphModel<'a>,
+    /// Map from `EntityId` to the total throughput of all the inputs of the entity, e.g. both lanes of a belt
+    pub input_entity_map: HashMap<EntityId, Int<'a>>,
+    /// Map from `EntityId` to the total throughput of all the outputs of the entity, e.g. both lanes of a belt
+    pub output_entity_map: HashMap<EntityId, Real<'a>>,
     /// constraints like kirchhoffs law or implementation of splitters
     pub model_constraint: Bool<'a>,
-    /// blocking constraints
-    pub blocking_constraint: Vec<Bool<'a>>,
 }
 
-bitflags! {
-    #[derive(Clone, Copy)]
-    pub struct ModelFlags: u8 {
-        const Relaxed = 1;
-        const Blocked = 1 << 1;
+impl<'a> ProofPrimitives<'a> {
+    /// Lowers the variables and constraints of the model to z3.
+    pub fn new(ctx: &'a Context, model: GraphModel<'a>) -> Result<Self, EncodeError> {
+        let lowering = Lowering::new(ctx);
+        let input_entity_map = model
+            .input_entity_map
+            .iter()
+            .map(|(id, input)| Ok((*id, lowering.int(input)?)))
+            .collect::<Result<_, _>>()?;
+        let output_entity_map = model
+            .output_entity_map
+            .iter()
+            .map(|(id, output)| Ok((*id, lowering.real(output)?)))
+            .collect::<Result<_, _>>()?;
+        let model_constraint = lowering.bool(&Expr::And(model.model_constraint.clone()))?;
+        Ok(Self {
+            ctx,
+            model,
+            input_entity_map,
+            output_entity_map,
+            model_constraint,
+        })
+    }
+
+    /// Lowers a formula over the variables of the model to z3.
+    pub fn lower(&self, expr: &Expr) -> Result<Bool<'a>, EncodeError> {
+        Lowering::new(self.ctx).bool(expr)
+    }
+
+    /// Reads the values of the inputs, outputs and edges in a model found by z3.
+    pub fn counterexample(&self, model: &Model<'a>) -> Counterexample {
+        let values = Lowering::new(self.ctx).values(model, self.model.variables());
+        self.model.counterexample(&values)
     }
 }
 
+/// Models the graph in z3 and checks whether the property `f` holds.
+///
+/// If it doesn't, the values of the model found by z3 are returned as a [`Counterexample`].
+/// The result is [`ProofResult::Unknown`] if the model or the property can't be lowered, with the error as reason.
 pub fn model_f<'a, F>(
     graph: &'a FlowGraph,
     ctx: &'a Context,
     f: F,
     flags: ModelFlags,
-) -> ProofResult
+    options: &SolverOptions,
+) -> ProofOutcome
 where
-    F: FnOnce(ProofPrimitives<'a>) -> Bool<'a>,
+    F: FnOnce(ProofPrimitives<'a>) -> Result<Bool<'a>, EncodeError>,
 {
-    let solver = Solver::new(ctx);
-
-    let mut helper = Z3QuantHelper::default();
-    // encode edges as variables in z3
-    for edge_idx in graph.edge_indices() {
-        let edge = graph[edge_idx];
-        edge.model(graph, edge_idx, ctx, &mut helper, flags);
-    }
-    // encode nodes as equations
-    for node_idx in graph.node_indices() {
-        let node = &graph[node_idx];
-        node.model(graph, node_idx, ctx, &mut helper, flags);
-    }
-
-    // add stuff to solver
-    let input_map = mem::take(&mut helper.input_map);
-    let input_bounds = input_map.values().cloned().collect::<Vec<_>>();
-
-    let output_map = mem::take(&mut helper.output_map);
-    let output_bounds = output_map.values().cloned().collect::<Vec<_>>();
-
-    let blocked_input_map = mem::take(&mut helper.blocked_input_map);
-    let blocked_output_map = mem::take(&mut helper.blocked_output_map);
-
-    let edge_map = mem::take(&mut helper.edge_map);
-    let edge_bounds = edge_map.values().cloned().collect::<Vec<_>>();
-
-    let model_constraint = vec_and(ctx, &helper.others);
-
-    let blocking_constraint = helper.blocking;
-
-    let primitives = ProofPrimitives {
-        ctx,
-        graph,
-        input_bounds,
-        input_map,
-        output_bounds,
-        output_map,
-        blocked_input_map,
-        blocked_output_map,
-        edge_bounds,
-        model_constraint,
-        blocking_constraint,
+    let unknown = |reason: String| ProofOutcome {
+        result: ProofResult::Unknown,
+        counterexample: None,
+        reason_unknown: Some(reason),
     };
-
-    solver.assert(&f(primitives.clone()));
+    let solver = match new_solver(ctx, options) {
+        Ok(solver) => solver,
+        Err(e) => return unknown(e.to_string()),
+    };
+    let lowered = ProofPrimitives::new(ctx, GraphModel::new(graph, flags))
+        .and_then(|primitives| Ok((f(primitives.clone())?, primitives)));
+    let (property, primitives) = match lowered {
+        Ok(lowered) => lowered,
+        Err(e) => return unknown(e.to_string()),
+    };
+    solver.assert(&property);
     let res: ProofResult = solver.check().into();
+    let reason_unknown = match res {
+        ProofResult::Unknown => solver.get_reason_unknown(),
+        _ => None,
+    };
     // TODO: move to tracing
     // println!("Solver:\n{:?}", solver);
     // println!("Model:\n{:?}", solver.get_model());
-    if let Some(model) = solver.get_model() {
-        for input in primitives.input_bounds {
-            let a = model.eval(&input, true);
-            println!("{:?}: {:?}", &input, a);
-        }
+    let counterexample = match res {
+        ProofResult::Sat if options.model => solver
+            .get_model()
+            .map(|model| primitives.counterexample(&model)),
+        _ => None,
+    };
+    ProofOutcome {
+        result: res.not(),
+        counterexample,
+        reason_unknown,
     }
-    res.not()
 }
 
-/// Conjunction of a slice of `Bool`s.
-pub fn vec_and<'a>(ctx: &'a Context, vec: &[Bool<'a>]) -> Bool<'a> {
-    let slice = vec.iter().collect::<Vec<_>>();
-    Bool::and(ctx, &slice)
+/// Function to prove if a given z3 model is a valid belt balancer, see [`GraphModel::belt_balancer`]
+pub fn belt_balancer_f(p: ProofPrimitives<'_>) -> Result<Bool<'_>, EncodeError> {
+    p.lower(&p.model.belt_balancer())
 }
 
-/// Equality of a slice of `Ast`s.
-pub fn equality<'a, T>(ctx: &'a Context, values: &[T]) -> Bool<'a>
-where
-    T: Ast<'a> + Sized,
-{
-    let pairwise_eq = values
-        .windows(2)
-        .map(|w| w[0]._eq(&w[1]))
-        .collect::<Vec<_>>();
-    let slice = pairwise_eq.iter().collect::<Vec<_>>();
-    Bool::and(ctx, &slice)
+/// Function to prove if a given z3 model is a lane balancer, see [`GraphModel::lane_balancer`]
+pub fn lane_balancer_f(p: ProofPrimitives<'_>) -> Result<Bool<'_>, EncodeError> {
+    p.lower(&p.model.lane_balancer()?)
 }
 
-/// Function to prove if a given z3 model is a valid belt balancer
-///
-/// # Definiton
-///
-/// Belt balancer: Blueprint that taking every possible combination of inputs produces equal outputs.
-///
-/// The `balancer_condition` states that all the outputs have the same value.
-/// Finding values s.t. the model is satisfied and output equality is not achieve, constitues a counter-example.
-pub fn belt_balancer_f(p: ProofPrimitives<'_>) -> Bool<'_> {
-    let balancer_condition = equality(p.ctx, &p.output_bounds);
-    // Correct model and NOT output equality
-    Bool::and(p.ctx, &[&balancer_condition.not(), &p.model_constraint])
+/// Function to prove if a given z3 model is an equal drain belt balancer, see [`GraphModel::equal_drain`]
+pub fn equal_drain_f(p: ProofPrimitives<'_>) -> Result<Bool<'_>, EncodeError> {
+    p.lower(&p.model.equal_drain())
 }
 
-/// Function to prove if a given z3 model is an equal drain belt balancer
-///
-/// # Definiton
-///
-/// Equal drain: When operating all the inputs are consumed equally, not resulting in any imbalances.
-/// E.g. [this](https://fbe.teoxoy.com/?source=0eJyd0ttqwzAMBuB30bVTkjSliy/3GqUUJ1WLwFGMrYyFkHefnYxRGPR0aVv/JyN7gsYO6DyxgJ6A2p4D6MMEga5sbNqT0SFoIMEOFLDp0upigmTiDQfXe8katAKzAuIzfoMu5qMCZCEhXLnbWHCWRNBHzfUh1vSc+sRcrmAEneWbXbTO5LFdD/NfbTzx0DUxGTuo6d5d/tEJXfXisV6+qr+Cb9/FnxhL9fZYnsB36VWXb6Bvfo2CL/RhiZQfRbWvy31V1vW2LhRYE7vG6s+/6nn+AWY2ztQ=) is a 2-2 equal drain belt balancer;
-/// [this](https://fbe.teoxoy.com/?source=0eJyVktFqwzAMRf9Fz/ZI0pQuftxvlFGcVh0GRza2MhZC/n1yOkbHYGuejC3dc7myZuj9iDE5YjAzuHOgDOY4Q3ZvZH154ykiGHCMAyggO5Tb1WbWnCzlGBLrHj3DosDRBT/A1MurAiR27PCG+0OmIIYsnYGKm6h19bRXMIGRU5gXl/B8K1df1OlE49BjKk7qJzxH75il9AtbrUz9ALQpQeLIJ5lLSFIRuMdrSbgtyYYguzv2wwPS9f/gdgN4C3df/nhdCnO3QwreMeVV0jzX7aFrDm3TdbuuVuCtuEr3y3f3snwCz/TTyA==) is only a 2-2 belt balancer.
-///
-/// # Precondition
-///
-/// Assumes that the model is a valid belt balancer.
-/// Uses a reversed graph.
-///
-/// The `model_condition` states that the z3 model is modelled correctly and that equality of inputs does NOT imply equality of outputs.
-/// This is used to find a counter-example.
-pub fn equal_drain_f(p: ProofPrimitives<'_>) -> Bool<'_> {
-    let input_eq = equality(p.ctx, &p.input_bounds);
-    let output_eq = equality(p.ctx, &p.output_bounds);
-    // Correct model and equality of inputs does NOT imply equality of outputs
-    Bool::and(
-        p.ctx,
-        &[&p.model_constraint, &input_eq.implies(&output_eq).not()],
-    )
-}
-
-// TODO: figure out lifetimes and fix code duplication
-fn capacity_bound<'a, 'b>(
-    p: &'a ProofPrimitives<'a>,
-    entities: &[FBEntity<i32>],
-    iter: impl Iterator<Item = (&'b NodeIndex, &'a Real<'a>)>,
-) -> Bool<'a> {
-    let zero = Real::from_real(p.ctx, 0, 1);
-    let conditions = iter
-        .map(|(idx, v)| {
-            let lower = v.ge(&zero);
-
-            let entity_id = p.graph[*idx].get_id();
-            let capacity = entities
-                .iter()
-                .find(|e| e.get_base().id == entity_id)
-                .unwrap()
-                .get_base()
-                .throughput as i64;
-            let upper_const = Real::from_int(&Int::from_i64(p.ctx, capacity));
-            let upper = v.le(&upper_const);
-            Bool::and(p.ctx, &[&lower, &upper])
-        })
-        .collect::<Vec<_>>();
-    vec_and(p.ctx, &conditions)
-}
-
-/// Function that generates a function to prove if a given z3 model is a throughput unlimited belt balancer
-///
-/// # Definition
-///
-/// Throughput unlimited:
-///
-/// # Precondition
-///
-/// Assumes that the model is a valid belt balancer.
-///
-/// To prove:
-/// ```text
-/// forall inputs, outputs. in_out_eq -> exist edges. model holds
-/// ```
-/// Find a counterexample:
-/// ```text
-/// not forall inputs, outputs. in_out_eq -> exist edges. model holds
-/// not forall inputs, outputs. not in_out_eq or exist edges. model holds
-/// exist inputs, outputs. in_out_eq and not exist edges. model holds
-/// inputs, outputs. in_out_eq and forall edges. model does NOT hold
-/// ```
-pub fn throughput_unlimited<'a>(
-    entities: Vec<FBEntity<i32>>,
-) -> impl Fn(ProofPrimitives<'a>) -> Bool<'a> {
-    let i = move |p: ProofPrimitives<'a>| {
-        let zero = Int::from_i64(p.ctx, 0);
-        // `input_condition` adds the following constraint to all inputs (0 <= input <= capacity)
-        let input_constraints = p
-            .input_map
-            .iter()
-            .map(|(idx, v)| {
-                let lower = v.ge(&zero);
-
-                let entity_id = p.graph[*idx].get_id();
-                let capacity = entities
-                    .iter()
-                    .find(|e| e.get_base().id == entity_id)
-                    .unwrap()
-                    .get_base()
-                    .throughput as i64;
-                let upper_const = Int::from_i64(p.ctx, capacity);
-                let upper = v.le(&upper_const);
-                Bool::and(p.ctx, &[&lower, &upper])
-            })
-            .collect::<Vec<_>>();
-        let input_condition = vec_and(p.ctx, &input_constraints);
-
-        let zero = Real::from_int(&zero);
-        // `output_condition` adds the following constraint to all outputs (0 <= output <= capacity)
-        let output_constraints = p
-            .output_map
-            .iter()
-            .map(|(idx, v)| {
-                let lower = v.ge(&zero);
-
-                let entity_id = p.graph[*idx].get_id();
-                let capacity = entities
-                    .iter()
-                    .find(|e| e.get_base().id == entity_id)
-                    .unwrap()
-                    .get_base()
-                    .throughput as i64;
-                let upper_const = Real::from_int(&Int::from_i64(p.ctx, capacity));
-                let upper = v.le(&upper_const);
-                Bool::and(p.ctx, &[&lower, &upper])
-            })
-            .collect::<Vec<_>>();
-        let output_condition = vec_and(p.ctx, &output_constraints);
-
-        let outputs = p.output_map.values().collect::<Vec<_>>();
-        let output_sum = if !outputs.is_empty() {
-            Real::add(p.ctx, &outputs)
-        } else {
-            zero.clone()
-        };
-
-        let inputs = p.input_map.values().collect::<Vec<_>>();
-        let input_sum = if !inputs.is_empty() {
-            Real::from_int(&Int::add(p.ctx, &inputs))
-        } else {
-            zero
-        };
-
-        let in_out_eq = input_sum._eq(&output_sum);
-
-        // Model edge throughput as existentially quantified variables
-        let cast_edge_bounds = p
-            .edge_bounds
-            .iter()
-            .map(|r| r as &dyn Ast)
-            .collect::<Vec<_>>();
-
-        let no_model = forall_const(p.ctx, &cast_edge_bounds, &[], &p.model_constraint.not());
-
-        Bool::and(
-            p.ctx,
-            &[&input_condition, &output_condition, &in_out_eq, &no_model],
-        )
-    };
-    i
-}
-
-/// input, output, blocked. BLOCKING, MODEL and not OUT_EQ
-pub fn universal_balancer(p: ProofPrimitives<'_>) -> Bool<'_> {
-    let eq_value = Real::new_const(p.ctx, "output_value");
-    let outputs_eq_value = p
-        .output_map
-        .iter()
-        .map(|(idx, output)| {
-            let is_blocked = p.blocked_output_map.get(idx).unwrap();
-            is_blocked.not().implies(&output._eq(&eq_value))
-        })
-        .collect::<Vec<_>>();
-    let out_eq = vec_and(p.ctx, &outputs_eq_value);
-    let out_eq_condition = exists_const(p.ctx, &[&eq_value], &[], &out_eq);
-    let blocking_p = vec_and(p.ctx, &p.blocking_constraint);
-    Bool::and(
-        p.ctx,
-        &[&blocking_p, &p.model_constraint, &out_eq_condition.not()],
-    )
+/// Function to prove if a given z3 model is a universal balancer, see [`GraphModel::universal`]
+pub fn universal_balancer(p: ProofPrimitives<'_>) -> Result<Bool<'_>, EncodeError> {
+    p.lower(&p.model.universal())
 }
 
 #[cfg(test)]
@@ -349,11 +152,47 @@ mod tests {
         graph.simplify(&[4, 5, 6], CoalesceStrength::Aggressive);
         let cfg = Config::new();
         let ctx = Context::new(&cfg);
-        let res = model_f(&graph, &ctx, belt_balancer_f, ModelFlags::empty());
+        let res = model_f(
+            &graph,
+            &ctx,
+            belt_balancer_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        )
+        .result;
         println!("Result: {}", res);
         assert!(matches!(res, ProofResult::Unsat));
     }
 
+    #[test]
+    fn counterexample_3_2_broken() {
+        let entities = file_to_entities("tests/3-2-broken").unwrap();
+        let mut graph = Compiler::new(entities).create_graph();
+        graph.simplify(&[4, 5, 6], CoalesceStrength::Aggressive);
+        let cfg = Config::new();
+        let ctx = Context::new(&cfg);
+        let outcome = model_f(
+            &graph,
+            &ctx,
+            belt_balancer_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        );
+        let counterexample = outcome.counterexample.unwrap();
+        println!("Counterexample:\n{}", counterexample);
+        assert_eq!(counterexample.edges.len(), graph.edge_count());
+        assert!(counterexample.blocked_edges.is_empty());
+        /* the outputs are not balanced */
+        let outputs = counterexample.outputs.values().collect::<Vec<_>>();
+        assert!(outputs.windows(2).any(|w| w[0] != w[1]));
+        /* the flows are traced back to the entities of the blueprint */
+        let flows = counterexample.entity_flows(&graph);
+        assert!(!flows.is_empty());
+        for (entity_id, flow) in &counterexample.outputs {
+            assert!(flows[entity_id] >= *flow);
+        }
+    }
+
     #[test]
     fn is_balancer_4_4() {
         let entities = file_to_entities("tests/4-4").unwrap();
@@ -361,11 +200,75 @@ mod tests {
         graph.simplify(&[3], CoalesceStrength::Aggressive);
         let cfg = Config::new();
         let ctx = Context::new(&cfg);
-        let res = model_f(&graph, &ctx, belt_balancer_f, ModelFlags::empty());
+        let outcome = model_f(
+            &graph,
+            &ctx,
+            belt_balancer_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        );
+        println!("Result: {}", outcome.result);
+        assert!(matches!(outcome.result, ProofResult::Sat));
+        assert!(outcome.counterexample.is_none());
+    }
+
+    #[test]
+    fn is_balancer_4_4_lanes() {
+        let entities = file_to_entities("tests/4-4").unwrap();
+        let mut graph = Compiler::new(entities).create_lane_graph().unwrap();
+        graph.simplify(&[3], CoalesceStrength::Aggressive);
+        let cfg = Config::new();
+        let ctx = Context::new(&cfg);
+        let res = model_f(
+            &graph,
+            &ctx,
+            belt_balancer_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        )
+        .result;
+        println!("Result: {}", res);
+        assert!(matches!(res, ProofResult::Sat));
+    }
+
+    #[test]
+    fn is_lane_balancer() {
+        let entities = file_to_entities("tests/lane_balancer").unwrap();
+        let mut graph = Compiler::new(entities).create_lane_graph().unwrap();
+        graph.simplify(&[], CoalesceStrength::Aggressive);
+        let cfg = Config::new();
+        let ctx = Context::new(&cfg);
+        let res = model_f(
+            &graph,
+            &ctx,
+            lane_balancer_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        )
+        .result;
         println!("Result: {}", res);
         assert!(matches!(res, ProofResult::Sat));
     }
 
+    #[test]
+    fn not_lane_balancer_4_4() {
+        let entities = file_to_entities("tests/4-4").unwrap();
+        let mut graph = Compiler::new(entities).create_lane_graph().unwrap();
+        graph.simplify(&[3], CoalesceStrength::Aggressive);
+        let cfg = Config::new();
+        let ctx = Context::new(&cfg);
+        let res = model_f(
+            &graph,
+            &ctx,
+            lane_balancer_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        )
+        .result;
+        println!("Result: {}", res);
+        assert!(matches!(res, ProofResult::Unsat));
+    }
+
     #[test]
     fn is_throughput_unlimited_4_4() {
         let entities = file_to_entities("tests/4-4-tu").unwrap();
@@ -376,9 +279,11 @@ mod tests {
         let res = model_f(
             &graph,
             &ctx,
-            throughput_unlimited(entities),
+            |p: ProofPrimitives| p.lower(&p.model.throughput_unlimited(&entities).unwrap()),
             ModelFlags::Relaxed,
-        );
+            &SolverOptions::default(),
+        )
+        .result;
         println!("Result: {}", res);
         assert!(matches!(res, ProofResult::Sat));
     }
@@ -393,9 +298,11 @@ mod tests {
         let res = model_f(
             &graph,
             &ctx,
-            throughput_unlimited(entities),
+            |p: ProofPrimitives| p.lower(&p.model.throughput_unlimited(&entities).unwrap()),
             ModelFlags::Relaxed,
-        );
+            &SolverOptions::default(),
+        )
+        .result;
         println!("Result: {}", res);
         assert!(matches!(res, ProofResult::Unsat));
     }
@@ -410,9 +317,11 @@ mod tests {
         let res = model_f(
             &graph,
             &ctx,
-            throughput_unlimited(entities),
+            |p: ProofPrimitives| p.lower(&p.model.throughput_unlimited(&entities).unwrap()),
             ModelFlags::Relaxed,
-        );
+            &SolverOptions::default(),
+        )
+        .result;
         println!("Result: {}", res);
         assert!(matches!(res, ProofResult::Sat));
     }
@@ -427,9 +336,11 @@ mod tests {
         let res = model_f(
             &graph,
             &ctx,
-            throughput_unlimited(entities),
+            |p: ProofPrimitives| p.lower(&p.model.throughput_unlimited(&entities).unwrap()),
             ModelFlags::Relaxed,
-        );
+            &SolverOptions::default(),
+        )
+        .result;
         println!("Result: {}", res);
         assert!(matches!(res, ProofResult::Unsat));
     }
@@ -444,7 +355,14 @@ mod tests {
         );
         let cfg = Config::new();
         let ctx = Context::new(&cfg);
-        let res = model_f(&graph, &ctx, universal_balancer, ModelFlags::Blocked);
+        let res = model_f(
+            &graph,
+            &ctx,
+            universal_balancer,
+            ModelFlags::Blocked,
+            &SolverOptions::default(),
+        )
+        .result;
         println!("Result: {}", res);
         assert!(matches!(res, ProofResult::Sat));
     }
@@ -456,9 +374,18 @@ mod tests {
         graph.simplify(&[], CoalesceStrength::Aggressive);
         let cfg = Config::new();
         let ctx = Context::new(&cfg);
-        let res = model_f(&graph, &ctx, universal_balancer, ModelFlags::Blocked);
-        println!("Result: {}", res);
-        assert!(matches!(res, ProofResult::Unsat));
+        let outcome = model_f(
+            &graph,
+            &ctx,
+            universal_balancer,
+            ModelFlags::Blocked,
+            &SolverOptions::default(),
+        );
+        println!("Result: {}", outcome.result);
+        assert!(matches!(outcome.result, ProofResult::Unsat));
+        let counterexample = outcome.counterexample.unwrap();
+        assert_eq!(counterexample.blocked_outputs.len(), 4);
+        assert_eq!(counterexample.blocked_edges.len(), graph.edge_count());
     }
 
     #[test]
@@ -468,7 +395,14 @@ mod tests {
         graph.simplify(&[], CoalesceStrength::Aggressive);
         let cfg = Config::new();
         let ctx = Context::new(&cfg);
-        let res = model_f(&graph, &ctx, belt_balancer_f, ModelFlags::empty());
+        let res = model_f(
+            &graph,
+            &ctx,
+            belt_balancer_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        )
+        .result;
         assert!(matches!(res, ProofResult::Sat));
     }
 
@@ -479,7 +413,14 @@ mod tests {
         graph.simplify(&[], CoalesceStrength::Aggressive);
         let cfg = Config::new();
         let ctx = Context::new(&cfg);
-        let res = model_f(&graph, &ctx, equal_drain_f, ModelFlags::empty());
+        let res = model_f(
+            &graph,
+            &ctx,
+            equal_drain_f,
+            ModelFlags::empty(),
+            &SolverOptions::default(),
+        )
+        .result;
         assert!(matches!(res, ProofResult::Sat));
     }
 
@@ -493,9 +434,11 @@ mod tests {
         let res = model_f(
             &graph,
             &ctx,
-            throughput_unlimited(entities),
+            |p: ProofPrimitives| p.lower(&p.model.throughput_unlimited(&entities).unwrap()),
             ModelFlags::Relaxed,
-        );
+            &SolverOptions::default(),
+        )
+        .result;
         assert!(matches!(res, ProofResult::Sat));
     }
 
@@ -506,7 +449,14 @@ mod tests {
         graph.simplify(&[], CoalesceStrength::Aggressive);
         let cfg = Config::new();
         let ctx = Context::new(&cfg);
-        let res = model_f(&graph, &ctx, equal_drain_f, ModelFlags::Blocked);
+        let res = model_f(
+            &graph,
+            &ctx,
+            equal_drain_f,
+            ModelFlags::Blocked,
+            &SolverOptions::default(),
+        )
+        .result;
         assert!(matches!(res, ProofResult::Sat));
     }
 }
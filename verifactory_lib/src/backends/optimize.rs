@@ -0,0 +1,504 @@
+//! Quantitative analysis of a blueprint with the optimizer of z3
+//!
+//! Instead of proving a property, the model constraints are used to find the worst case of a metric
+//! over all the feasible inputs, together with the inputs reaching it.
+
+use std::fmt::Display;
+
+use fraction::GenericFraction;
+use z3::{
+    ast::{Ast, Bool, Int, Real},
+    Context, Model, Optimize, SatResult,
+};
+
+use petgraph::prelude::EdgeIndex;
+
+use crate::{entities::EntityId, ir::FlowGraph};
+
+use super::{
+    solver::{z3_number, EncodeError, Expr, GraphModel, Lowering},
+    Counterexample, ModelFlags, ProofPrimitives, SolverOptions,
+};
+
+/// Maximum number of refinements of the ratio, see [`max_imbalance`]
+const MAX_RATIO_STEPS: usize = 32;
+
+/// Errors preventing an optimization from finding the optimum
+#[derive(Debug, Clone)]
+pub enum OptimizeError {
+    /// z3 gave up, e.g. because of a timeout
+    Unknown(Option<String>),
+    /// The logic of the [`SolverOptions`] is not supported by the optimizer
+    UnsupportedLogic(String),
+    /// An entity restricting the optimization is not an input or output of the graph
+    UnknownEntity(EntityId),
+    /// The model can't be lowered to z3, e.g. because of an infinite rate
+    Encode(EncodeError),
+}
+
+impl Display for OptimizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(Some(reason)) => write!(f, "The optimum is unknown ({})", reason),
+            Self::Unknown(None) => write!(f, "The optimum is unknown"),
+            Self::UnsupportedLogic(logic) => {
+                write!(
+                    f,
+                    "The optimizer does not support setting the logic {}",
+                    logic
+                )
+            }
+            Self::UnknownEntity(id) => {
+                write!(f, "Entity {} is not an input or output of the graph", id)
+            }
+            Self::Encode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for OptimizeError {}
+
+/// Worst-case imbalance between the outputs of a blueprint, see [`max_imbalance`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Imbalance {
+    /// Largest difference between the throughput of two outputs, in items/s
+    pub max_deviation: GenericFraction<u128>,
+    /// Inputs and resulting outputs reaching the largest deviation
+    pub deviation_witness: Counterexample,
+    /// Largest ratio between the most and the least used output.
+    ///
+    /// `None` if an output can be starved whilst another one is not, making the ratio unbounded.
+    pub max_ratio: Option<GenericFraction<u128>>,
+    /// Inputs and resulting outputs reaching the largest ratio
+    pub ratio_witness: Counterexample,
+    /// Whether the ratio converged, otherwise `max_ratio` is only a lower bound, see [`max_imbalance`]
+    pub converged: bool,
+}
+
+impl Imbalance {
+    /// Returns `true` if all the outputs always get the same throughput.
+    pub fn is_balanced(&self) -> bool {
+        self.max_deviation == GenericFraction::from(0)
+    }
+}
+
+/// Maximum throughput of a blueprint, see [`max_throughput`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throughput {
+    /// Largest total throughput of the counted outputs, in items/s
+    pub total: GenericFraction<u128>,
+    /// Inputs, outputs and edges reaching the total
+    pub witness: Counterexample,
+    /// Edges carrying their full capacity in the witness, sorted
+    pub saturated_edges: Vec<EdgeIndex>,
+    /// Entities carried by the saturated edges, sorted, see [`crate::ir::Edge::entities`]
+    pub saturated_entities: Vec<EntityId>,
+}
+
+/// Outputs selected by the optimizer, as the most and the least used one
+struct Selection<'a> {
+    max_output: Real<'a>,
+    min_output: Real<'a>,
+    constraints: Vec<Bool<'a>>,
+}
+
+impl<'a> Selection<'a> {
+    /// Lets z3 pick one output as the most used one and one as the least used one.
+    ///
+    /// The outputs are sorted by id, keeping the encoding deterministic.
+    fn new(ctx: &'a Context, outputs: &[(EntityId, Real<'a>)]) -> Self {
+        let zero = Real::from_real(ctx, 0, 1);
+        let (one_int, zero_int) = (Int::from_i64(ctx, 1), Int::from_i64(ctx, 0));
+        let mut constraints = vec![];
+        let mut select = |name: &str| {
+            let selectors = outputs
+                .iter()
+                .map(|(id, _)| Bool::new_const(ctx, format!("{}_{}", name, id)))
+                .collect::<Vec<_>>();
+            /* exactly one output is selected */
+            let count = selectors
+                .iter()
+                .map(|s| s.ite(&one_int, &zero_int))
+                .collect::<Vec<_>>();
+            constraints.push(Int::add(ctx, &count.iter().collect::<Vec<_>>())._eq(&one_int));
+            let selected = selectors
+                .iter()
+                .zip(outputs)
+                .map(|(s, (_, output))| s.ite(output, &zero))
+                .collect::<Vec<_>>();
+            Real::add(ctx, &selected.iter().collect::<Vec<_>>())
+        };
+        let max_output = select("max_output");
+        let min_output = select("min_output");
+        Self {
+            max_output,
+            min_output,
+            constraints,
+        }
+    }
+}
+
+/// Evaluates a real in the model.
+pub(super) fn eval_real<'a>(model: &Model<'a>, v: &Real<'a>) -> Option<GenericFraction<u128>> {
+    z3_number(&model.eval(v, true)?)
+}
+
+/// Graph modelled in z3, with a selection of its outputs
+struct ImbalanceModel<'a> {
+    ctx: &'a Context,
+    primitives: ProofPrimitives<'a>,
+    selection: Selection<'a>,
+}
+
+impl<'a> ImbalanceModel<'a> {
+    /// Returns an optimizer constrained by the model, the selection of the outputs and `constraints`.
+    fn optimizer(&self, constraints: &[&Bool<'a>]) -> Optimize<'a> {
+        let optimize = new_optimizer(self.ctx);
+        optimize.assert(&self.primitives.model_constraint);
+        for constraint in self
+            .selection
+            .constraints
+            .iter()
+            .chain(constraints.iter().copied())
+        {
+            optimize.assert(constraint);
+        }
+        optimize
+    }
+
+    /// Finds the largest difference between the selected outputs.
+    fn max_deviation(&self) -> Result<(GenericFraction<u128>, Counterexample), OptimizeError> {
+        let selection = &self.selection;
+        let deviation = Real::sub(self.ctx, &[&selection.max_output, &selection.min_output]);
+        let optimize = self.optimizer(&[]);
+        optimize.maximize(&deviation);
+        let model = optimum(&optimize)?;
+        let max_deviation = eval_real(&model, &deviation).unwrap_or_else(|| 0.into());
+        Ok((max_deviation, self.primitives.counterexample(&model)))
+    }
+
+    /// Finds the largest ratio between the selected outputs in at most `max_steps` refinements,
+    /// returning whether it converged, see [`max_imbalance`].
+    fn max_ratio(
+        &self,
+        max_steps: usize,
+    ) -> Result<(Option<GenericFraction<u128>>, Counterexample, bool), OptimizeError> {
+        let ctx = self.ctx;
+        let zero = Real::from_real(ctx, 0, 1);
+        let (max_output, min_output) = (&self.selection.max_output, &self.selection.min_output);
+
+        /* an output starved whilst another one is not makes the ratio unbounded */
+        let starved = self.optimizer(&[&min_output._eq(&zero), &max_output.gt(&zero)]);
+        if let Some(model) = solve(&starved)? {
+            return Ok((None, self.primitives.counterexample(&model), true));
+        }
+
+        let min_positive = min_output.gt(&zero);
+        let mut ratio = GenericFraction::from(1);
+        let mut witness = Counterexample::default();
+        for _ in 0..max_steps {
+            let optimize = self.optimizer(&[&min_positive]);
+            let factor = Lowering::new(ctx)
+                .real(&Expr::Real(ratio))
+                .map_err(OptimizeError::Encode)?;
+            let scaled_min = Real::mul(ctx, &[&factor, min_output]);
+            optimize.maximize(&Real::sub(ctx, &[max_output, &scaled_min]));
+            /* no output carries anything for any inputs */
+            let Some(model) = solve(&optimize)? else {
+                return Ok((Some(ratio), witness, true));
+            };
+            let (Some(max), Some(min)) =
+                (eval_real(&model, max_output), eval_real(&model, min_output))
+            else {
+                return Ok((Some(ratio), witness, true));
+            };
+            witness = self.primitives.counterexample(&model);
+            if min == GenericFraction::from(0) || max / min <= ratio {
+                return Ok((Some(ratio), witness, true));
+            }
+            ratio = max / min;
+        }
+        Ok((Some(ratio), witness, false))
+    }
+}
+
+/// Finds the worst-case imbalance between the outputs over all the inputs the graph accepts.
+///
+/// The deviation is the largest difference between two outputs, found by maximizing it directly.
+/// The ratio is found with Dinkelbach's method: `max - ratio * min` is maximized,
+/// updating the ratio with the optimum, until no inputs improve on it.
+/// It is a lower bound if it does not converge within a fixed number of steps, see [`Imbalance::converged`].
+///
+/// Outputs are compared by entity, summing their lanes. Graphs with fewer than two outputs are balanced.
+pub fn max_imbalance(
+    graph: &FlowGraph,
+    options: &SolverOptions,
+) -> Result<Imbalance, OptimizeError> {
+    imbalance(graph, options, MAX_RATIO_STEPS)
+}
+
+/// Finds the worst-case imbalance, refining the ratio at most `max_steps` times, see [`max_imbalance`].
+fn imbalance(
+    graph: &FlowGraph,
+    options: &SolverOptions,
+    max_steps: usize,
+) -> Result<Imbalance, OptimizeError> {
+    if let Some(logic) = &options.logic {
+        return Err(OptimizeError::UnsupportedLogic(logic.clone()));
+    }
+    let ctx = Context::new(&options.config());
+    let primitives = ProofPrimitives::new(&ctx, GraphModel::new(graph, ModelFlags::empty()))
+        .map_err(OptimizeError::Encode)?;
+
+    let mut outputs = primitives
+        .output_entity_map
+        .iter()
+        .map(|(id, output)| (*id, output.clone()))
+        .collect::<Vec<_>>();
+    outputs.sort_by_key(|(id, _)| *id);
+    if outputs.len() < 2 {
+        return Ok(Imbalance {
+            max_deviation: 0.into(),
+            deviation_witness: Counterexample::default(),
+            max_ratio: Some(1.into()),
+            ratio_witness: Counterexample::default(),
+            converged: true,
+        });
+    }
+    let model = ImbalanceModel {
+        ctx: &ctx,
+        selection: Selection::new(&ctx, &outputs),
+        primitives,
+    };
+    let (max_deviation, deviation_witness) = model.max_deviation()?;
+    let (max_ratio, ratio_witness, converged) = model.max_ratio(max_steps)?;
+    Ok(Imbalance {
+        max_deviation,
+        deviation_witness,
+        max_ratio,
+        ratio_witness,
+        converged,
+    })
+}
+
+/// Finds the largest total throughput of the outputs of the graph.
+///
+/// If `inputs` is given, the other inputs are idle. If `outputs` is given, only these outputs are counted,
+/// the other ones still take what the graph sends to them.
+/// The edges running at their capacity in the optimum are the bottlenecks of the blueprint.
+/// Other optima may saturate other edges.
+pub fn max_throughput(
+    graph: &FlowGraph,
+    inputs: Option<&[EntityId]>,
+    outputs: Option<&[EntityId]>,
+    options: &SolverOptions,
+) -> Result<Throughput, OptimizeError> {
+    if let Some(logic) = &options.logic {
+        return Err(OptimizeError::UnsupportedLogic(logic.clone()));
+    }
+    let ctx = Context::new(&options.config());
+    let primitives = ProofPrimitives::new(&ctx, GraphModel::new(graph, ModelFlags::empty()))
+        .map_err(OptimizeError::Encode)?;
+
+    let optimize = new_optimizer(&ctx);
+    optimize.assert(&primitives.model_constraint);
+    if let Some(inputs) = inputs {
+        if let Some(id) = inputs
+            .iter()
+            .find(|id| !primitives.input_entity_map.contains_key(id))
+        {
+            return Err(OptimizeError::UnknownEntity(*id));
+        }
+        let idle = Int::from_i64(&ctx, 0);
+        for (id, input) in &primitives.input_entity_map {
+            if !inputs.contains(id) {
+                optimize.assert(&input._eq(&idle));
+            }
+        }
+    }
+    let counted = match outputs {
+        Some(outputs) => outputs
+            .iter()
+            .map(|id| {
+                primitives
+                    .output_entity_map
+                    .get(id)
+                    .ok_or(OptimizeError::UnknownEntity(*id))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => primitives.output_entity_map.values().collect(),
+    };
+    let total = if counted.is_empty() {
+        Real::from_real(&ctx, 0, 1)
+    } else {
+        Real::add(&ctx, &counted)
+    };
+    optimize.maximize(&total);
+
+    let model = optimum(&optimize)?;
+    let witness = primitives.counterexample(&model);
+    let mut saturated_edges = witness
+        .edges
+        .iter()
+        .filter(|(edge_idx, flow)| **flow == graph[**edge_idx].capacity)
+        .map(|(edge_idx, _)| *edge_idx)
+        .collect::<Vec<_>>();
+    saturated_edges.sort();
+    let mut saturated_entities = saturated_edges
+        .iter()
+        .flat_map(|edge_idx| graph[*edge_idx].entities.iter().copied())
+        .collect::<Vec<_>>();
+    saturated_entities.sort();
+    saturated_entities.dedup();
+    Ok(Throughput {
+        total: eval_real(&model, &total).unwrap_or_else(|| 0.into()),
+        witness,
+        saturated_edges,
+        saturated_entities,
+    })
+}
+
+/// Creates an optimizer, its options are set on the context with [`SolverOptions::config`].
+pub(super) fn new_optimizer(ctx: &Context) -> Optimize<'_> {
+    Optimize::new(ctx)
+}
+
+/// Checks the constraints of the optimizer, returning the optimal model if they are satisfiable.
+pub(super) fn solve<'a>(optimize: &Optimize<'a>) -> Result<Option<Model<'a>>, OptimizeError> {
+    match optimize.check(&[]) {
+        SatResult::Sat => optimize
+            .get_model()
+            .map(Some)
+            .ok_or_else(|| OptimizeError::Unknown(Some("z3 did not return a model".to_owned()))),
+        SatResult::Unsat => Ok(None),
+        SatResult::Unknown => Err(OptimizeError::Unknown(optimize.get_reason_unknown())),
+    }
+}
+
+/// Returns the optimal model of constraints which hold without any flow, like the model constraints.
+pub(super) fn optimum<'a>(optimize: &Optimize<'a>) -> Result<Model<'a>, OptimizeError> {
+    solve(optimize)?
+        .ok_or_else(|| OptimizeError::Unknown(Some("the constraints are unsatisfiable".to_owned())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::graph;
+
+    #[test]
+    fn balanced_4_4() {
+        let imbalance =
+            max_imbalance(&graph("tests/4-4", &[3]), &SolverOptions::default()).unwrap();
+        assert!(imbalance.is_balanced());
+        assert_eq!(imbalance.max_ratio, Some(1.into()));
+        assert!(imbalance.converged);
+    }
+
+    #[test]
+    fn unbalanced_3_2() {
+        let imbalance = max_imbalance(
+            &graph("tests/3-2-broken", &[4, 5, 6]),
+            &SolverOptions::default(),
+        )
+        .unwrap();
+        assert!(!imbalance.is_balanced());
+
+        /* the witness reaches the deviation */
+        let outputs = imbalance
+            .deviation_witness
+            .outputs
+            .values()
+            .collect::<Vec<_>>();
+        let max = outputs.iter().copied().max().unwrap();
+        let min = outputs.iter().copied().min().unwrap();
+        assert_eq!(*max - *min, imbalance.max_deviation);
+        assert!(!imbalance.deviation_witness.inputs.is_empty());
+
+        /* an output can be starved whilst another one is not */
+        assert_eq!(imbalance.max_ratio, None);
+        assert!(imbalance.converged);
+    }
+
+    #[test]
+    fn ratio_step_cap() {
+        let graph = graph("tests/4-4-broken", &[]);
+        let options = SolverOptions::default();
+        let exact = max_imbalance(&graph, &options).unwrap();
+        assert!(exact.converged);
+        assert_eq!(exact.max_ratio, Some(3.into()));
+
+        /* a single refinement finds an unbalanced witness, but can't tell whether it is the worst one */
+        let capped = imbalance(&graph, &options, 1).unwrap();
+        assert!(!capped.converged);
+        assert!(matches!(
+            capped.max_ratio,
+            Some(ratio) if ratio > GenericFraction::from(1) && ratio <= GenericFraction::from(3)
+        ));
+    }
+
+    #[test]
+    fn throughput_4_4() {
+        let graph = graph("tests/4-4", &[3]);
+        let throughput = max_throughput(&graph, None, None, &SolverOptions::default()).unwrap();
+        let outputs = throughput
+            .witness
+            .outputs
+            .values()
+            .fold(GenericFraction::from(0), |sum, rate| sum + *rate);
+        assert_eq!(throughput.total, outputs);
+        assert!(throughput.total > GenericFraction::from(0));
+        assert!(!throughput.saturated_edges.is_empty());
+        for edge_idx in &throughput.saturated_edges {
+            assert_eq!(
+                throughput.witness.edges[edge_idx],
+                graph[*edge_idx].capacity
+            );
+        }
+    }
+
+    #[test]
+    fn throughput_subsets() {
+        let graph = graph("tests/4-4", &[3]);
+        let options = SolverOptions::default();
+        let all = max_throughput(&graph, None, None, &options).unwrap();
+        let input = *all.witness.inputs.keys().min().unwrap();
+        let output = *all.witness.outputs.keys().min().unwrap();
+
+        let one_input = max_throughput(&graph, Some(&[input]), None, &options).unwrap();
+        assert!(one_input.total <= all.total);
+        assert!(one_input
+            .witness
+            .inputs
+            .iter()
+            .all(|(id, rate)| *id == input || *rate == GenericFraction::from(0)));
+
+        let one_output = max_throughput(&graph, None, Some(&[output]), &options).unwrap();
+        assert_eq!(one_output.total, one_output.witness.outputs[&output]);
+
+        assert!(matches!(
+            max_throughput(&graph, Some(&[output]), None, &options),
+            Err(OptimizeError::UnknownEntity(id)) if id == output
+        ));
+    }
+
+    #[test]
+    fn throughput_simple_belt() {
+        let throughput = max_throughput(
+            &graph("tests/simple_belt", &[]),
+            None,
+            None,
+            &SolverOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(throughput.total, GenericFraction::from(15));
+        assert!(throughput.saturated_entities.contains(&1));
+    }
+
+    #[test]
+    fn single_output() {
+        let imbalance =
+            max_imbalance(&graph("tests/simple_belt", &[]), &SolverOptions::default()).unwrap();
+        assert!(imbalance.is_balanced());
+    }
+}
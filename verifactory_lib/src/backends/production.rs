@@ -0,0 +1,110 @@
+//! Largest production rate of blueprints whose assemblers craft recipes
+//!
+//! The item flows are encoded by [`ProductionModel`], any [`super::solver::SolverBackend`] can check
+//! whether a rate is reachable with [`ProductionModel::problem`]. Finding the largest rate needs an optimizer,
+//! which only z3 provides: the model is lowered to it like the other optimizations, see [`super::max_throughput`].
+
+use z3::Context;
+
+use super::{
+    optimize::{new_optimizer, optimum},
+    solver::{EncodeError, Expr, Lowering, Production, ProductionModel, Supply},
+    OptimizeError, SolverOptions,
+};
+use crate::ir::FlowGraph;
+
+/// Finds the largest rate at which `item` leaves the outputs of the graph, given the `supplies` of its inputs.
+///
+/// Inputs without a supply are idle. The graph should be created with
+/// [`crate::frontend::Compiler::create_production_graph`], so that assemblers consume their ingredients
+/// in the ratios of their recipe.
+/// Splitters route the items freely, as with [`super::ModelFlags::Relaxed`], so the rate is the best one
+/// the blueprint can reach: it is an upper bound if the blueprint relies on splitters to sort items.
+pub fn max_production(
+    graph: &FlowGraph,
+    item: &str,
+    supplies: &[Supply],
+    options: &SolverOptions,
+) -> Result<Production, OptimizeError> {
+    if let Some(logic) = &options.logic {
+        return Err(OptimizeError::UnsupportedLogic(logic.clone()));
+    }
+    let model = ProductionModel::new(graph, item, supplies).map_err(|e| match e {
+        EncodeError::UnknownEntity(id) | EncodeError::UnknownInput(id) => {
+            OptimizeError::UnknownEntity(id)
+        }
+        e => OptimizeError::Encode(e),
+    })?;
+    let ctx = Context::new(&options.config());
+    let lowering = Lowering::new(&ctx);
+    let optimize = new_optimizer(&ctx);
+    let constraint = lowering
+        .bool(&Expr::And(model.model_constraint.clone()))
+        .map_err(OptimizeError::Encode)?;
+    let produced = lowering
+        .real(&model.produced)
+        .map_err(OptimizeError::Encode)?;
+    optimize.assert(&constraint);
+    optimize.maximize(&produced);
+
+    let optimum = optimum(&optimize)?;
+    Ok(model.production(&lowering.values(&optimum, model.variables())))
+}
+
+#[cfg(test)]
+mod tests {
+    use fraction::GenericFraction;
+
+    use super::*;
+    use crate::{recipes::RecipeDatabase, test_utils::production_graph};
+
+    fn supply(item: &str, rate: Option<GenericFraction<u128>>) -> Vec<Supply> {
+        vec![Supply {
+            input: 1,
+            item: item.to_owned(),
+            rate,
+        }]
+    }
+
+    #[test]
+    fn gear_column() {
+        let graph = production_graph("tests/gear_column", &RecipeDatabase::vanilla());
+        let options = SolverOptions::default();
+        let gears = |supplies: &[Supply]| {
+            max_production(&graph, "iron-gear-wheel", supplies, &options).unwrap()
+        };
+
+        /* each fast inserter moves 2.31 plates/s, i.e. 1.155 gears/s */
+        let production = gears(&supply("iron-plate", None));
+        assert_eq!(production.rate, GenericFraction::from(2.31));
+        assert!(production.reaches(GenericFraction::from(2)));
+        assert!(!production.reaches(GenericFraction::from(3)));
+        assert_eq!(production.crafts.len(), 2);
+        assert!(production.inputs[&1] >= GenericFraction::from(4.62));
+
+        /* two plates per gear */
+        let production = gears(&supply("iron-plate", Some(2.into())));
+        assert_eq!(production.rate, GenericFraction::from(1));
+
+        /* the assemblers only accept the ingredients of their recipe */
+        let production = gears(&supply("copper-plate", None));
+        assert_eq!(production.rate, GenericFraction::from(0));
+        assert!(production.crafts.values().all(|c| *c == 0.into()));
+    }
+
+    #[test]
+    fn unknown_input() {
+        let graph = production_graph("tests/gear_column", &RecipeDatabase::vanilla());
+        let mut supplies = supply("iron-plate", None);
+        supplies[0].input = 9;
+        assert!(matches!(
+            max_production(
+                &graph,
+                "iron-gear-wheel",
+                &supplies,
+                &SolverOptions::default()
+            ),
+            Err(OptimizeError::UnknownEntity(9))
+        ));
+    }
+}
@@ -1,10 +1,40 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use z3::{ast::Bool, Config, Context, SatResult};
+use fraction::GenericFraction;
+use petgraph::prelude::EdgeIndex;
 
-use crate::ir::FlowGraph;
+use crate::{
+    entities::{EntityId, FBEntity},
+    ir::{FlowGraph, Reversable},
+};
 
-use super::{model_f, ModelFlags, ProofPrimitives};
+use super::{
+    solver::{EncodeError, Expr, GraphModel},
+    ModelFlags,
+};
+
+#[cfg(feature = "z3")]
+use std::{thread, time::Duration};
+
+#[cfg(feature = "z3")]
+use z3::{ast::Bool, Config, Context, Params, SatResult};
+
+#[cfg(feature = "z3")]
+use crate::{
+    frontend::Compiler,
+    import::{string_to_blueprints, ImportError, ImportedBlueprint},
+    ir::{CoalesceStrength, FlowGraphFun},
+};
+
+#[cfg(feature = "z3")]
+use super::{model_f, ProofPrimitives};
 
 #[derive(Debug, Clone, Copy)]
 pub enum ProofResult {
@@ -23,6 +53,7 @@ impl ProofResult {
     }
 }
 
+#[cfg(feature = "z3")]
 impl From<SatResult> for ProofResult {
     fn from(value: SatResult) -> Self {
         match value {
@@ -44,15 +75,177 @@ impl Display for ProofResult {
     }
 }
 
+/// Values of a model found by z3 that violates a property
+///
+/// Inputs and outputs are the ones of the proven graph, i.e. they are swapped for reversed graphs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Counterexample {
+    /// Throughput of each input entity, summing all of its lanes
+    pub inputs: HashMap<EntityId, GenericFraction<u128>>,
+    /// Throughput of each output entity, summing all of its lanes
+    pub outputs: HashMap<EntityId, GenericFraction<u128>>,
+    /// Flow through each edge of the graph
+    pub edges: HashMap<EdgeIndex, GenericFraction<u128>>,
+    /// Blocked inputs, only set when modelling with [`ModelFlags::Blocked`]
+    pub blocked_inputs: HashMap<EntityId, bool>,
+    /// Blocked outputs, only set when modelling with [`ModelFlags::Blocked`]
+    pub blocked_outputs: HashMap<EntityId, bool>,
+    /// Blocked edges, only set when modelling with [`ModelFlags::Blocked`]
+    pub blocked_edges: HashMap<EdgeIndex, bool>,
+}
+
+impl Counterexample {
+    /// Traces the flow of each edge back to the entities it came from, see [`crate::ir::Edge::entities`].
+    ///
+    /// The flow of an entity is the sum of all the edges carrying it, e.g. both of its lanes.
+    /// Entities not carried by any edge of the simplified graph are missing.
+    /// The `graph` has to be the one the counterexample was found on.
+    pub fn entity_flows(&self, graph: &FlowGraph) -> HashMap<EntityId, GenericFraction<u128>> {
+        let mut flows = HashMap::new();
+        for (edge_idx, flow) in &self.edges {
+            for entity_id in &graph[*edge_idx].entities {
+                *flows.entry(*entity_id).or_insert_with(|| 0.into()) += *flow;
+            }
+        }
+        flows
+    }
+}
+
+impl Display for Counterexample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut write_rates = |name: &str,
+                               rates: &HashMap<EntityId, GenericFraction<u128>>,
+                               blocked: &HashMap<EntityId, bool>|
+         -> std::fmt::Result {
+            writeln!(f, "{}:", name)?;
+            let mut ids = rates.keys().collect::<Vec<_>>();
+            ids.sort();
+            for id in ids {
+                let blocked = if blocked.get(id).copied().unwrap_or(false) {
+                    " (blocked)"
+                } else {
+                    ""
+                };
+                writeln!(f, "  {}: {}{}", id, rates[id], blocked)?;
+            }
+            Ok(())
+        };
+        write_rates("inputs", &self.inputs, &self.blocked_inputs)?;
+        write_rates("outputs", &self.outputs, &self.blocked_outputs)
+    }
+}
+
+/// Result of a proof, together with a [`Counterexample`] if the property does not hold
+#[derive(Debug, Clone)]
+pub struct ProofOutcome {
+    pub result: ProofResult,
+    /// Only set if z3 found a model violating the property
+    pub counterexample: Option<Counterexample>,
+    /// Why z3 gave up if the result is [`ProofResult::Unknown`], e.g. `timeout` or `canceled`
+    pub reason_unknown: Option<String>,
+}
+
+/// Configuration of the z3 solver used by a proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SolverOptions {
+    /// The solver gives up after the timeout, in milliseconds
+    pub timeout_ms: Option<u32>,
+    /// Resource limit of the solver, a deterministic alternative to the timeout
+    pub rlimit: Option<u32>,
+    /// Seed of the random number generator of the solver
+    pub seed: Option<u32>,
+    /// Whether to generate models, without them no [`Counterexample`] is found
+    pub model: bool,
+    /// Logic used by the solver, e.g. `LIRA` or `NIRA`, z3 picks one if `None`.
+    ///
+    /// Inputs are modelled as integers and some properties use quantifiers, so the logic has to support them.
+    pub logic: Option<String>,
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        Self {
+            timeout_ms: None,
+            rlimit: None,
+            seed: None,
+            model: true,
+            logic: None,
+        }
+    }
+}
+
+#[cfg(feature = "z3")]
+impl SolverOptions {
+    /// Returns the parameters of the solver corresponding to these options.
+    pub fn params<'a>(&self, ctx: &'a Context) -> Params<'a> {
+        let mut params = Params::new(ctx);
+        if let Some(timeout) = self.timeout_ms {
+            params.set_u32("timeout", timeout);
+        }
+        if let Some(rlimit) = self.rlimit {
+            params.set_u32("rlimit", rlimit);
+        }
+        if let Some(seed) = self.seed {
+            params.set_u32("random_seed", seed);
+        }
+        params.set_bool("model", self.model);
+        params
+    }
+
+    /// Returns the configuration of a context for the optimizer, which does not take parameters.
+    ///
+    /// The optimum is read from the model, so models are always generated. The seed is not used.
+    pub fn config(&self) -> Config {
+        let mut cfg = Config::new();
+        if let Some(timeout) = self.timeout_ms {
+            cfg.set_timeout_msec(timeout.into());
+        }
+        if let Some(rlimit) = self.rlimit {
+            cfg.set_param_value("rlimit", &rlimit.to_string());
+        }
+        cfg.set_model_generation(true);
+        cfg
+    }
+}
+
+/// Token used to cancel a proof running on another thread, see [`BlueprintProofEntity::set_cancel_token`]
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interrupts z3, the proof results in [`ProofResult::Unknown`].
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "z3")]
 pub struct BlueprintProofEntity {
     _cfg: Config,
     ctx: Context,
     graph: FlowGraph,
     result: Option<ProofResult>,
+    counterexample: Option<Counterexample>,
+    cancel: Option<CancelToken>,
+    options: SolverOptions,
 }
 
+#[cfg(feature = "z3")]
 impl BlueprintProofEntity {
     pub fn new(graph: FlowGraph) -> Self {
+        Self::with_options(graph, SolverOptions::default())
+    }
+
+    /// Creates a proof whose solver is configured with the `options`.
+    pub fn with_options(graph: FlowGraph, options: SolverOptions) -> Self {
         let _cfg = Config::new();
         let ctx = Context::new(&_cfg);
         Self {
@@ -60,21 +253,396 @@ impl BlueprintProofEntity {
             ctx,
             graph,
             result: None,
+            counterexample: None,
+            cancel: None,
+            options,
         }
     }
 
-    pub fn model<'a, F>(&'a mut self, f: F, flags: ModelFlags) -> ProofResult
+    /// Allows interrupting the proofs of this entity from another thread with `token`.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = Some(token);
+    }
+
+    pub fn model<'a, F>(&'a mut self, f: F, flags: ModelFlags) -> ProofOutcome
     where
-        F: FnOnce(ProofPrimitives<'a>) -> Bool<'a>,
+        F: FnOnce(ProofPrimitives<'a>) -> Result<Bool<'a>, EncodeError>,
     {
-        let res = model_f(&self.graph, &self.ctx, f, flags).into();
-        self.result = Some(res);
-        res
+        let outcome = match &self.cancel {
+            None => model_f(&self.graph, &self.ctx, f, flags, &self.options),
+            Some(token) if token.is_cancelled() => ProofOutcome {
+                result: ProofResult::Unknown,
+                counterexample: None,
+                reason_unknown: Some("canceled".to_owned()),
+            },
+            Some(token) => {
+                let handle = self.ctx.handle();
+                let done = AtomicBool::new(false);
+                thread::scope(|s| {
+                    /* keep interrupting until the proof is done, z3 ignores interrupts whilst not solving */
+                    s.spawn(|| {
+                        while !done.load(Ordering::Relaxed) {
+                            if token.is_cancelled() {
+                                handle.interrupt();
+                            }
+                            thread::sleep(Duration::from_millis(10));
+                        }
+                    });
+                    let outcome = model_f(&self.graph, &self.ctx, f, flags, &self.options);
+                    done.store(true, Ordering::Relaxed);
+                    outcome
+                })
+            }
+        };
+        self.result = Some(outcome.result);
+        self.counterexample = outcome.counterexample.clone();
+        outcome
     }
 
     pub fn result(&self) -> Option<ProofResult> {
         self.result
     }
+
+    pub fn counterexample(&self) -> Option<&Counterexample> {
+        self.counterexample.as_ref()
+    }
+
+    /// Returns the graph the proof is modelled on.
+    pub fn graph(&self) -> &FlowGraph {
+        &self.graph
+    }
+}
+
+/// The standard properties that can be proven on a blueprint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Property {
+    /// See [`GraphModel::belt_balancer`]
+    BeltBalancer,
+    /// See [`GraphModel::equal_drain`]
+    EqualDrain,
+    /// See [`GraphModel::throughput_unlimited`]
+    ThroughputUnlimited,
+    /// See [`GraphModel::universal`]
+    Universal,
+    /// See [`GraphModel::lane_balancer`]
+    LaneBalancer,
+}
+
+impl Property {
+    /// All the standard properties
+    pub const ALL: [Self; 5] = [
+        Self::BeltBalancer,
+        Self::EqualDrain,
+        Self::ThroughputUnlimited,
+        Self::Universal,
+        Self::LaneBalancer,
+    ];
+
+    /// Returns `true` if the property has to be proven on the reversed graph.
+    pub fn is_reversed(&self) -> bool {
+        matches!(self, Self::EqualDrain)
+    }
+
+    /// Returns `true` if the property has to be proven on a graph modelling lanes,
+    /// see [`Compiler::create_lane_graph`].
+    pub fn needs_lanes(&self) -> bool {
+        matches!(self, Self::LaneBalancer)
+    }
+
+    /// Returns the flags used to model the graph for this property.
+    pub fn flags(&self) -> ModelFlags {
+        match self {
+            Self::BeltBalancer | Self::EqualDrain | Self::LaneBalancer => ModelFlags::empty(),
+            Self::ThroughputUnlimited => ModelFlags::Relaxed,
+            Self::Universal => ModelFlags::Blocked,
+        }
+    }
+
+    /// Returns the graph the property is proven on, reversed if needed.
+    pub fn oriented(&self, graph: &FlowGraph) -> FlowGraph {
+        if self.is_reversed() {
+            Reversable::reverse(graph)
+        } else {
+            graph.clone()
+        }
+    }
+
+    /// Returns the negation of the property on the `model`, whose solutions are counter-examples.
+    ///
+    /// The `entities` of the blueprint are needed to retrieve the throughput of inputs and outputs.
+    pub fn negation(
+        &self,
+        model: &GraphModel,
+        entities: &[FBEntity<i32>],
+    ) -> Result<Expr, EncodeError> {
+        Ok(match self {
+            Self::BeltBalancer => model.belt_balancer(),
+            Self::EqualDrain => model.equal_drain(),
+            Self::ThroughputUnlimited => model.throughput_unlimited(entities)?,
+            Self::Universal => model.universal(),
+            Self::LaneBalancer => model.lane_balancer()?,
+        })
+    }
+
+    /// Proves the property on a simplified graph.
+    ///
+    /// The graph is reversed if needed, see [`Property::is_reversed`].
+    /// The `entities` of the blueprint are needed to retrieve the throughput of inputs and outputs.
+    #[cfg(feature = "z3")]
+    pub fn prove(&self, graph: &FlowGraph, entities: &[FBEntity<i32>]) -> ProofOutcome {
+        self.prove_with(graph, entities, &SolverOptions::default())
+    }
+
+    /// Proves the property on a simplified graph with a solver configured by the `options`, see [`Property::prove`].
+    #[cfg(feature = "z3")]
+    pub fn prove_with(
+        &self,
+        graph: &FlowGraph,
+        entities: &[FBEntity<i32>],
+        options: &SolverOptions,
+    ) -> ProofOutcome {
+        let mut proof = BlueprintProofEntity::with_options(self.oriented(graph), options.clone());
+        self.model(&mut proof, entities)
+    }
+
+    /// Returns the constraints checked by [`Property::prove`] in the SMT-LIB2 format, see [`super::solver::Problem::export`].
+    ///
+    /// Variables are named after the entities they model, e.g. `edge_<src>_<dst>_<idx>` or `input_<id>`.
+    pub fn to_smt2(
+        &self,
+        graph: &FlowGraph,
+        entities: &[FBEntity<i32>],
+        options: &SolverOptions,
+    ) -> Result<String, EncodeError> {
+        let graph = self.oriented(graph);
+        let model = GraphModel::new(&graph, self.flags());
+        self.problem(&model, entities)?.export(options)
+    }
+
+    /// Models the property on the graph of the `proof`, which has to be reversed if needed.
+    ///
+    /// The result is [`ProofResult::Unknown`] if the property can't be encoded, with the error as reason.
+    #[cfg(feature = "z3")]
+    pub fn model(
+        &self,
+        proof: &mut BlueprintProofEntity,
+        entities: &[FBEntity<i32>],
+    ) -> ProofOutcome {
+        let negation = match self.negation(&GraphModel::new(&proof.graph, self.flags()), entities) {
+            Ok(negation) => negation,
+            Err(e) => {
+                proof.result = Some(ProofResult::Unknown);
+                proof.counterexample = None;
+                return ProofOutcome {
+                    result: ProofResult::Unknown,
+                    counterexample: None,
+                    reason_unknown: Some(e.to_string()),
+                };
+            }
+        };
+        proof.model(|p: ProofPrimitives| p.lower(&negation), self.flags())
+    }
+}
+
+impl Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::BeltBalancer => "belt-balancer",
+            Self::EqualDrain => "equal-drain",
+            Self::ThroughputUnlimited => "throughput-unlimited",
+            Self::Universal => "universal",
+            Self::LaneBalancer => "lane-balancer",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Results of the verification of a single blueprint, see [`verify_blueprints`].
+#[cfg(feature = "z3")]
+#[derive(Debug, Clone)]
+pub struct BlueprintVerification {
+    /// Label of the verified blueprint
+    pub label: Option<String>,
+    /// Index of the blueprint inside of its book, see [`ImportedBlueprint::index`]
+    pub index: Vec<usize>,
+    /// Result for each of the proven properties
+    pub results: Vec<(Property, ProofResult)>,
+}
+
+/// Compiles and verifies each blueprint against all the given properties.
+///
+/// Inputs and outputs are the ones detected automatically by the [`Compiler`].
+/// The graph modelling lanes is only compiled if one of the properties needs it, these properties are
+/// [`ProofResult::Unknown`] if the blueprint can't be modelled with lanes, see [`Compiler::create_lane_graph`].
+#[cfg(feature = "z3")]
+pub fn verify_blueprints(
+    blueprints: &[ImportedBlueprint],
+    properties: &[Property],
+) -> Vec<BlueprintVerification> {
+    blueprints
+        .iter()
+        .map(|blueprint| {
+            let entities = &blueprint.entities;
+            let compiler = Compiler::new(entities.clone());
+            let simplify = |mut graph: FlowGraph| {
+                graph.simplify(&[], CoalesceStrength::Aggressive);
+                graph
+            };
+            let graph = simplify(compiler.create_graph());
+            let mut lane_graph = None;
+            let results = properties
+                .iter()
+                .map(|p| {
+                    let graph = if p.needs_lanes() {
+                        let lanes = lane_graph
+                            .get_or_insert_with(|| compiler.create_lane_graph().ok().map(simplify));
+                        /* the blueprint can't be modelled with lanes */
+                        let Some(lanes) = lanes else {
+                            return (*p, ProofResult::Unknown);
+                        };
+                        lanes
+                    } else {
+                        &graph
+                    };
+                    (*p, p.prove(graph, entities).result)
+                })
+                .collect();
+            BlueprintVerification {
+                label: blueprint.label.clone(),
+                index: blueprint.index.clone(),
+                results,
+            }
+        })
+        .collect()
+}
+
+/// Parses a blueprint or blueprint book string and verifies every blueprint it contains.
+///
+/// Returns one [`BlueprintVerification`] per blueprint, see [`verify_blueprints`].
+#[cfg(feature = "z3")]
+pub fn verify_book(
+    blueprint_string: &str,
+    properties: &[Property],
+) -> Result<Vec<BlueprintVerification>, ImportError> {
+    let blueprints = string_to_blueprints(blueprint_string)?;
+    Ok(verify_blueprints(&blueprints, properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frontend::Compiler, test_utils::load_graph};
+
+    fn simple_belt() -> (FlowGraph, Vec<FBEntity<i32>>) {
+        load_graph("tests/simple_belt", &[], Compiler::create_graph)
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn cancelled_proof() {
+        let (graph, entities) = simple_belt();
+        let options = SolverOptions {
+            timeout_ms: Some(10_000),
+            ..Default::default()
+        };
+        let mut proof = BlueprintProofEntity::with_options(graph, options);
+        let token = CancelToken::new();
+        proof.set_cancel_token(token.clone());
+        let outcome = Property::BeltBalancer.model(&mut proof, &entities);
+        assert!(matches!(outcome.result, ProofResult::Sat));
+
+        token.cancel();
+        let outcome = Property::BeltBalancer.model(&mut proof, &entities);
+        assert!(matches!(outcome.result, ProofResult::Unknown));
+        assert!(outcome.counterexample.is_none());
+        assert_eq!(outcome.reason_unknown.as_deref(), Some("canceled"));
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn solver_options() {
+        let (graph, entities) = load_graph("tests/3-2-broken", &[4, 5, 6], Compiler::create_graph);
+        let options = SolverOptions {
+            seed: Some(42),
+            model: false,
+            ..Default::default()
+        };
+        let outcome = Property::BeltBalancer.prove_with(&graph, &entities, &options);
+        assert!(matches!(outcome.result, ProofResult::Unsat));
+        /* no model, no counterexample */
+        assert!(outcome.counterexample.is_none());
+        assert!(outcome.reason_unknown.is_none());
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn uneven_assembler_column() {
+        /* the first inserter takes what it can before the second one */
+        let (graph, entities) = load_graph("tests/assembler_column", &[6], Compiler::create_graph);
+        let outcome =
+            Property::BeltBalancer.prove_with(&graph, &entities, &SolverOptions::default());
+        assert!(matches!(outcome.result, ProofResult::Unsat));
+        let outputs = outcome.counterexample.unwrap().outputs;
+        assert_ne!(outputs[&9], outputs[&10]);
+    }
+
+    #[test]
+    fn export_smt2() {
+        let (graph, entities) = simple_belt();
+        let smt2 = Property::Universal
+            .to_smt2(&graph, &entities, &SolverOptions::default())
+            .unwrap();
+        assert!(smt2.starts_with("(set-option :produce-models true)"));
+        assert!(smt2.contains("(declare-fun input_1 () Int)"));
+        assert!(smt2.contains("output_3"));
+        assert!(smt2.contains("blocked_"));
+        assert!(smt2.ends_with("(check-sat)\n(get-model)\n"));
+    }
+
+    #[test]
+    fn missing_entities() {
+        let (graph, _) = simple_belt();
+        let smt2 = Property::ThroughputUnlimited.to_smt2(&graph, &[], &SolverOptions::default());
+        assert!(matches!(smt2, Err(EncodeError::UnknownEntity(_))));
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn missing_entities_are_unknown() {
+        let (graph, _) = simple_belt();
+        let outcome = Property::ThroughputUnlimited.prove(&graph, &[]);
+        assert!(matches!(outcome.result, ProofResult::Unknown));
+        assert!(outcome
+            .reason_unknown
+            .unwrap()
+            .contains("not part of the blueprint"));
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn verify_nested_book() {
+        let book = std::fs::read_to_string("tests/book").unwrap();
+        let results = verify_book(&book, &Property::ALL).unwrap();
+        assert_eq!(results.len(), 2);
+
+        let simple_belt = &results[0];
+        assert_eq!(simple_belt.index, vec![0]);
+        assert_eq!(simple_belt.results.len(), Property::ALL.len());
+        assert!(matches!(
+            simple_belt.results[0],
+            (Property::BeltBalancer, ProofResult::Sat)
+        ));
+
+        let balancer = &results[1];
+        assert_eq!(balancer.index, vec![1, 0]);
+        for (property, result) in &balancer.results {
+            match property {
+                Property::ThroughputUnlimited => assert!(matches!(result, ProofResult::Sat)),
+                Property::Universal => assert!(matches!(result, ProofResult::Unsat)),
+                _ => (),
+            }
+        }
+    }
 }
 
 // TODO: decide what to do with these tests
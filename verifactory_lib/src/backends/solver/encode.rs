@@ -0,0 +1,508 @@
+//! Encoding of a flow graph and of the standard properties as solver-independent formulas
+//!
+//! This is the only encoding of the graph, backends lower it to their solver, see [`super::SolverBackend`].
+//! Production graphs, carrying several items, are encoded by [`super::ProductionModel`] with the same edge bounds.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use bitflags::bitflags;
+
+use petgraph::{
+    prelude::{EdgeIndex, NodeIndex},
+    Direction::Outgoing,
+};
+
+use crate::{
+    backends::Counterexample,
+    entities::{EntityId, FBEntity},
+    ir::{FlowGraph, GraphHelper, Node, Splitter},
+    utils::Side,
+};
+
+use super::formula::{Expr, Sort, Value, Var};
+
+bitflags! {
+    #[derive(Clone, Copy)]
+    pub struct ModelFlags: u8 {
+        const Relaxed = 1;
+        const Blocked = 1 << 1;
+    }
+}
+
+/// Errors preventing a property from being encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// An input or output of the graph is not one of the entities of the blueprint
+    UnknownEntity(EntityId),
+    /// An entity feeding items to the graph is not one of its inputs
+    UnknownInput(EntityId),
+    /// A number of the formulas is infinite or not a number, e.g. a rate parsed from `inf`
+    NonFiniteNumber,
+    /// An output does not model a lane, the property needs a graph modelling lanes
+    NoLane(EntityId),
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownEntity(id) => {
+                write!(f, "Entity {} of the graph is not part of the blueprint", id)
+            }
+            Self::UnknownInput(id) => write!(f, "Entity {} is not an input of the graph", id),
+            Self::NonFiniteNumber => write!(f, "Numbers have to be finite"),
+            Self::NoLane(id) => write!(f, "Output {} does not model a lane", id),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// Variables and constraints of a graph
+#[derive(Debug, Clone)]
+pub struct GraphModel<'a> {
+    /// Flowgraph associated with the model
+    pub graph: &'a FlowGraph,
+    /// Map from `NodeIndex` to the associated integer throughput variable
+    pub input_map: HashMap<NodeIndex, Var>,
+    /// Map from `NodeIndex` to the associated throughput variable
+    pub output_map: HashMap<NodeIndex, Var>,
+    /// Map from `EntityId` to the total throughput of all the inputs of the entity, e.g. both lanes of a belt
+    pub input_entity_map: HashMap<EntityId, Expr>,
+    /// Map from `EntityId` to the total throughput of all the outputs of the entity, e.g. both lanes of a belt
+    pub output_entity_map: HashMap<EntityId, Expr>,
+    /// Map from `EdgeIndex` to the flow through the edge
+    pub edge_map: HashMap<EdgeIndex, Var>,
+    /// Blocked edges, only set when modelling with [`ModelFlags::Blocked`]
+    pub blocked_edge_map: HashMap<EdgeIndex, Var>,
+    /// Map from `NodeIndex` to the associated input blocked variable
+    pub blocked_input_map: HashMap<NodeIndex, Var>,
+    /// Map from `NodeIndex` to the associated output blocked variable
+    pub blocked_output_map: HashMap<NodeIndex, Var>,
+    /// Bounds of the edges, kirchhoffs law and the implementation of splitters
+    pub model_constraint: Vec<Expr>,
+    /// blocking constraints
+    pub blocking_constraint: Vec<Expr>,
+}
+
+impl<'a> GraphModel<'a> {
+    /// Encodes the edges and nodes of the graph.
+    pub fn new(graph: &'a FlowGraph, flags: ModelFlags) -> Self {
+        let mut model = Self {
+            graph,
+            input_map: HashMap::new(),
+            output_map: HashMap::new(),
+            input_entity_map: HashMap::new(),
+            output_entity_map: HashMap::new(),
+            edge_map: HashMap::new(),
+            blocked_edge_map: HashMap::new(),
+            blocked_input_map: HashMap::new(),
+            blocked_output_map: HashMap::new(),
+            model_constraint: vec![],
+            blocking_constraint: vec![],
+        };
+        for idx in graph.edge_indices() {
+            model.encode_edge(idx, flags);
+        }
+        for idx in graph.node_indices() {
+            model.encode_node(idx, flags);
+        }
+        model.input_entity_map = entity_map(graph, &model.input_map);
+        model.output_entity_map = entity_map(graph, &model.output_map);
+        model
+    }
+
+    /// Returns the free variables of the model constraints, which include all the variables of the maps.
+    pub fn variables(&self) -> BTreeSet<Var> {
+        let mut vars = BTreeSet::new();
+        for constraint in &self.model_constraint {
+            constraint.free_vars(&mut vars);
+        }
+        vars
+    }
+
+    fn edge(&self, idx: EdgeIndex) -> Expr {
+        self.edge_map[&idx].expr()
+    }
+
+    fn blocked(&self, idx: EdgeIndex) -> Expr {
+        self.blocked_edge_map[&idx].expr()
+    }
+
+    fn encode_edge(&mut self, idx: EdgeIndex, flags: ModelFlags) {
+        let (src, dst) = self.graph.edge_endpoints(idx).unwrap();
+        let (src_id, dst_id) = (self.graph[src].get_str(), self.graph[dst].get_str());
+
+        let name = format!("edge_{}_{}_{}", src_id, dst_id, idx.index());
+        let edge = Var::new(name, Sort::Real);
+        self.model_constraint
+            .extend(edge_bounds(self.graph, idx, edge.expr()));
+
+        if flags.contains(ModelFlags::Blocked) {
+            let name = format!("blocked_{}_{}_{}", src_id, dst_id, idx.index());
+            let blocked = Var::new(name, Sort::Bool);
+            let blocked_capacity = blocked.expr().implies(edge.expr()._eq(Expr::zero()));
+            self.model_constraint.push(blocked_capacity);
+            self.blocked_edge_map.insert(idx, blocked);
+        }
+        self.edge_map.insert(idx, edge);
+    }
+
+    fn kirchhoff_law(&mut self, idx: NodeIndex) {
+        let sum =
+            |edges: Vec<EdgeIndex>| Expr::Add(edges.into_iter().map(|e| self.edge(e)).collect());
+        let ast = sum(self.graph.in_edge_idx(idx))._eq(sum(self.graph.out_edge_idx(idx)));
+        self.model_constraint.push(ast);
+    }
+
+    fn encode_node(&mut self, idx: NodeIndex, flags: ModelFlags) {
+        let graph = self.graph;
+        let blocked = flags.contains(ModelFlags::Blocked);
+        match &graph[idx] {
+            Node::Input(input) => {
+                let name = format!("input_{}{}", input.id, input.lane.lane_suffix());
+                let var = Var::new(name, Sort::Int);
+                let out_idx = graph.out_edge_idx(idx)[0];
+                let ast = var.expr()._eq(self.edge(out_idx));
+                self.model_constraint.push(ast);
+                self.input_map.insert(idx, var);
+                if blocked {
+                    let blocked = self.blocked_edge_map[&out_idx].clone();
+                    self.blocked_input_map.insert(idx, blocked);
+                }
+            }
+            Node::Output(output) => {
+                let name = format!("output_{}{}", output.id, output.lane.lane_suffix());
+                let var = Var::new(name, Sort::Real);
+                let in_idx = graph.in_edge_idx(idx)[0];
+                let ast = var.expr()._eq(self.edge(in_idx));
+                self.model_constraint.push(ast);
+                self.output_map.insert(idx, var);
+                if blocked {
+                    let blocked = self.blocked_edge_map[&in_idx].clone();
+                    self.blocked_output_map.insert(idx, blocked);
+                }
+            }
+            Node::Connector(_) => {
+                self.kirchhoff_law(idx);
+                if blocked {
+                    // input blocked iff. output blocked
+                    let blocked_in = self.blocked(graph.in_edge_idx(idx)[0]);
+                    let blocked_out = self.blocked(graph.out_edge_idx(idx)[0]);
+                    self.blocking_constraint.push(blocked_in._eq(blocked_out));
+                }
+            }
+            Node::Merger(_) => {
+                self.kirchhoff_law(idx);
+                if blocked {
+                    let in_idx = graph.in_edge_idx(idx);
+                    let (blocked_in_1, blocked_in_2) =
+                        (self.blocked(in_idx[0]), self.blocked(in_idx[1]));
+                    let blocked_out = self.blocked(graph.out_edge_idx(idx)[0]);
+                    // if output is blocked, block both inputs
+                    // otherwise, don't block the inputs
+                    let ast = blocked_out.ite(
+                        Expr::And(vec![blocked_in_1.clone(), blocked_in_2.clone()]),
+                        !Expr::Or(vec![blocked_in_1, blocked_in_2]),
+                    );
+                    self.blocking_constraint.push(ast);
+                }
+            }
+            Node::Splitter(splitter) => {
+                self.kirchhoff_law(idx);
+                let splitter_cond = self.splitter_cond(splitter, idx);
+                if flags.contains(ModelFlags::Relaxed) {
+                    // skip the splitter condition
+                } else if blocked {
+                    let blocked_in = self.blocked(graph.in_edge_idx(idx)[0]);
+                    let out_idx = graph.out_edge_idx(idx);
+                    let (blocked_out_1, blocked_out_2) =
+                        (self.blocked(out_idx[0]), self.blocked(out_idx[1]));
+                    // remove splitter condition if at least one of the outputs is blocked
+                    let ast = (!Expr::Or(vec![blocked_out_1.clone(), blocked_out_2.clone()]))
+                        .implies(splitter_cond);
+                    self.model_constraint.push(ast);
+                    // if both outputs are blocked, block the input
+                    // otherwise, don't block the input
+                    let ast = Expr::And(vec![blocked_out_1, blocked_out_2])
+                        .ite(blocked_in.clone(), !blocked_in);
+                    self.blocking_constraint.push(ast);
+                } else {
+                    self.model_constraint.push(splitter_cond);
+                }
+            }
+            /* items change type in a craft, their amounts are not related */
+            Node::Craft(_) => (),
+        }
+    }
+
+    /// See `Splitter::get_splitter_cond`.
+    fn splitter_cond(&self, splitter: &Splitter, idx: NodeIndex) -> Expr {
+        let graph = self.graph;
+        let in_var = self.edge(graph.in_edge_idx(idx)[0]);
+        let side = splitter.output_priority;
+        if side.is_none() {
+            let out_idxs = graph.out_edge_idx(idx);
+            let (a_idx, b_idx) = (out_idxs[0], out_idxs[1]);
+            let (min_idx, max_idx) = if graph[a_idx].capacity <= graph[b_idx].capacity {
+                (a_idx, b_idx)
+            } else {
+                (b_idx, a_idx)
+            };
+            let min_cap = graph[min_idx].capacity;
+            in_var.le(Expr::Real(min_cap * 2)).ite(
+                self.edge(min_idx)._eq(self.edge(max_idx)),
+                self.edge(min_idx)._eq(Expr::Real(min_cap)),
+            )
+        } else {
+            let prio_idx = graph.get_edge(idx, Outgoing, side);
+            let other_idx = graph.get_edge(idx, Outgoing, -side);
+            let prio_cap = Expr::Real(graph[prio_idx].capacity);
+            in_var.le(prio_cap.clone()).ite(
+                self.edge(other_idx)._eq(Expr::zero()),
+                self.edge(prio_idx)._eq(prio_cap),
+            )
+        }
+    }
+
+    /// Reads the [`Counterexample`] out of the values of the variables in a model.
+    pub fn counterexample(&self, values: &HashMap<String, Value>) -> Counterexample {
+        fn eval_map<K, V, T>(
+            map: &HashMap<K, V>,
+            expr: impl Fn(&V) -> Expr,
+            values: &HashMap<String, Value>,
+            value: impl Fn(Value) -> Option<T>,
+        ) -> HashMap<K, T>
+        where
+            K: Copy + Eq + std::hash::Hash,
+        {
+            map.iter()
+                .filter_map(|(key, v)| Some((*key, value(expr(v).eval(values)?)?)))
+                .collect()
+        }
+        let number = |v| match v {
+            Value::Number(n) => Some(n),
+            Value::Bool(_) => None,
+        };
+        let boolean = |v| match v {
+            Value::Bool(b) => Some(b),
+            Value::Number(_) => None,
+        };
+        let blocked_per_entity = |map: &HashMap<NodeIndex, Var>| {
+            let mut grouped: HashMap<EntityId, bool> = HashMap::new();
+            for (idx, flag) in eval_map(map, Var::expr, values, boolean) {
+                *grouped.entry(self.graph[idx].get_id()).or_default() |= flag;
+            }
+            grouped
+        };
+        Counterexample {
+            inputs: eval_map(&self.input_entity_map, Clone::clone, values, number),
+            outputs: eval_map(&self.output_entity_map, Clone::clone, values, number),
+            edges: eval_map(&self.edge_map, Var::expr, values, number),
+            blocked_inputs: blocked_per_entity(&self.blocked_input_map),
+            blocked_outputs: blocked_per_entity(&self.blocked_output_map),
+            blocked_edges: eval_map(&self.blocked_edge_map, Var::expr, values, boolean),
+        }
+    }
+
+    /// Negation of the belt balancer property
+    ///
+    /// # Definiton
+    ///
+    /// Belt balancer: Blueprint that taking every possible combination of inputs produces equal outputs.
+    ///
+    /// The `balancer_condition` states that all the outputs have the same value.
+    /// Finding values s.t. the model is satisfied and output equality is not achieve, constitues a counter-example.
+    /// When lanes are modelled the total of both lanes of each output is compared.
+    pub fn belt_balancer(&self) -> Expr {
+        let outputs = self.output_entity_map.values().cloned().collect::<Vec<_>>();
+        let balancer_condition = Expr::equality(&outputs);
+        self.with_model(!balancer_condition)
+    }
+
+    /// Negation of the equal drain property
+    ///
+    /// # Definiton
+    ///
+    /// Equal drain: When operating all the inputs are consumed equally, not resulting in any imbalances.
+    /// E.g. [this](https://fbe.teoxoy.com/?source=0eJyd0ttqwzAMBuB30bVTkjSliy/3GqUUJ1WLwFGMrYyFkHefnYxRGPR0aVv/JyN7gsYO6DyxgJ6A2p4D6MMEga5sbNqT0SFoIMEOFLDp0upigmTiDQfXe8katAKzAuIzfoMu5qMCZCEhXLnbWHCWRNBHzfUh1vSc+sRcrmAEneWbXbTO5LFdD/NfbTzx0DUxGTuo6d5d/tEJXfXisV6+qr+Cb9/FnxhL9fZYnsB36VWXb6Bvfo2CL/RhiZQfRbWvy31V1vW2LhRYE7vG6s+/6nn+AWY2ztQ=) is a 2-2 equal drain belt balancer;
+    /// [this](https://fbe.teoxoy.com/?source=0eJyVktFqwzAMRf9Fz/ZI0pQuftxvlFGcVh0GRza2MhZC/n1yOkbHYGuejC3dc7myZuj9iDE5YjAzuHOgDOY4Q3ZvZH154ykiGHCMAyggO5Tb1WbWnCzlGBLrHj3DosDRBT/A1MurAiR27PCG+0OmIIYsnYGKm6h19bRXMIGRU5gXl/B8K1df1OlE49BjKk7qJzxH75il9AtbrUz9ALQpQeLIJ5lLSFIRuMdrSbgtyYYguzv2wwPS9f/gdgN4C3df/nhdCnO3QwreMeVV0jzX7aFrDm3TdbuuVuCtuEr3y3f3snwCz/TTyA==) is only a 2-2 belt balancer.
+    ///
+    /// # Precondition
+    ///
+    /// Assumes that the model is a valid belt balancer.
+    /// Uses a reversed graph.
+    ///
+    /// The model is correct and equality of inputs does NOT imply equality of outputs, which is a counter-example.
+    pub fn equal_drain(&self) -> Expr {
+        let inputs = self.input_entity_map.values().cloned().collect::<Vec<_>>();
+        let outputs = self.output_entity_map.values().cloned().collect::<Vec<_>>();
+        let drain_condition = Expr::equality(&inputs).implies(Expr::equality(&outputs));
+        self.with_model(!drain_condition)
+    }
+
+    /// Negation of the lane balancer property
+    ///
+    /// # Definiton
+    ///
+    /// Lane balancer: Blueprint that taking every possible combination of inputs, and of their lanes, produces equal amounts on the two lanes of each output.
+    /// This also covers a single lane of an input ending up on both lanes of every output.
+    ///
+    /// # Precondition
+    ///
+    /// Uses a graph modelling lanes, see [`crate::frontend::Compiler::create_lane_graph`].
+    /// A lane of an output that was removed during simplification, because nothing can reach it, carries 0.
+    /// Fails if an output does not model a lane, the property would hold vacuously.
+    ///
+    /// The `lane_condition` states that the left and right lane of each output have the same value.
+    /// Finding values s.t. the model is satisfied and the lanes differ, constitues a counter-example.
+    pub fn lane_balancer(&self) -> Result<Expr, EncodeError> {
+        let mut lanes: HashMap<EntityId, (Expr, Expr)> = HashMap::new();
+        for (idx, output) in &self.output_map {
+            let node = &self.graph[*idx];
+            let lane = match node {
+                Node::Output(o) => o.lane,
+                _ => Side::None,
+            };
+            let entry = lanes
+                .entry(node.get_id())
+                .or_insert_with(|| (Expr::zero(), Expr::zero()));
+            match lane {
+                Side::Left => entry.0 = output.expr(),
+                Side::Right => entry.1 = output.expr(),
+                Side::None => return Err(EncodeError::NoLane(node.get_id())),
+            }
+        }
+        let lane_condition = lanes
+            .into_values()
+            .map(|(left, right)| left._eq(right))
+            .collect();
+        Ok(self.with_model(!Expr::And(lane_condition)))
+    }
+
+    /// Negation of the throughput unlimited property
+    ///
+    /// # Precondition
+    ///
+    /// Assumes that the model is a valid belt balancer, modelled with [`ModelFlags::Relaxed`].
+    ///
+    /// To prove:
+    /// ```text
+    /// forall inputs, outputs. in_out_eq -> exist edges. model holds
+    /// ```
+    /// Find a counterexample:
+    /// ```text
+    /// not forall inputs, outputs. in_out_eq -> exist edges. model holds
+    /// not forall inputs, outputs. not in_out_eq or exist edges. model holds
+    /// exist inputs, outputs. in_out_eq and not exist edges. model holds
+    /// inputs, outputs. in_out_eq and forall edges. model does NOT hold
+    /// ```
+    ///
+    /// Fails if an input or output is missing from the `entities`, their throughput bounds the flow.
+    pub fn throughput_unlimited(&self, entities: &[FBEntity<i32>]) -> Result<Expr, EncodeError> {
+        let entity = |entity_id: EntityId| {
+            entities
+                .iter()
+                .map(FBEntity::get_base)
+                .find(|base| base.id == entity_id)
+                .ok_or(EncodeError::UnknownEntity(entity_id))
+        };
+        let mut conditions = vec![];
+        // 0 <= input <= capacity and 0 <= output <= capacity, for each entity
+        for (entity_id, v) in self.input_entity_map.iter().chain(&self.output_entity_map) {
+            let capacity = entity(*entity_id)?.throughput as i64;
+            conditions.push(v.clone().ge(Expr::Int(0)));
+            conditions.push(v.clone().le(Expr::Int(capacity)));
+        }
+        // a single lane can only carry half of the capacity
+        for (idx, v) in self.input_map.iter().chain(&self.output_map) {
+            let node = &self.graph[*idx];
+            let lane = match node {
+                Node::Input(i) => i.lane,
+                Node::Output(o) => o.lane,
+                _ => Side::None,
+            };
+            if !lane.is_none() {
+                let half = entity(node.get_id())?.lane_capacity();
+                conditions.push(v.expr().le(Expr::Real(half)));
+            }
+        }
+        let input_sum = Expr::Add(self.input_map.values().map(Var::expr).collect());
+        let output_sum = Expr::Add(self.output_map.values().map(Var::expr).collect());
+        conditions.push(input_sum._eq(output_sum));
+
+        // the flow through the edges is universally quantified
+        let edges = self.edge_map.values().cloned().collect();
+        let model = Expr::And(self.model_constraint.clone());
+        conditions.push(Expr::Forall(edges, Box::new(!model)));
+        Ok(Expr::And(conditions))
+    }
+
+    /// Negation of the universal balancer property: blocking, correct model and NOT equal unblocked outputs
+    ///
+    /// Modelled with [`ModelFlags::Blocked`].
+    /// When lanes are modelled an output counts as blocked if any of its lanes is blocked.
+    pub fn universal(&self) -> Expr {
+        let eq_value = Var::new("output_value", Sort::Real);
+        let mut blocked_entities: HashMap<EntityId, Vec<Expr>> = HashMap::new();
+        for (idx, is_blocked) in &self.blocked_output_map {
+            blocked_entities
+                .entry(self.graph[*idx].get_id())
+                .or_default()
+                .push(is_blocked.expr());
+        }
+        let outputs_eq_value = self
+            .output_entity_map
+            .iter()
+            .map(|(entity_id, output)| {
+                let is_blocked = Expr::Or(blocked_entities[entity_id].clone());
+                (!is_blocked).implies(output.clone()._eq(eq_value.expr()))
+            })
+            .collect();
+        let out_eq_condition = Expr::Exists(vec![eq_value], Box::new(Expr::And(outputs_eq_value)));
+        let mut conditions = self.blocking_constraint.clone();
+        conditions.extend(self.model_constraint.iter().cloned());
+        conditions.push(!out_eq_condition);
+        Expr::And(conditions)
+    }
+
+    /// Conjunction of the model constraints and `condition`.
+    fn with_model(&self, condition: Expr) -> Expr {
+        let mut conditions = self.model_constraint.clone();
+        conditions.push(condition);
+        Expr::And(conditions)
+    }
+}
+
+/// Bounds of the `flow` through an edge, between 0 and the capacity of the edge.
+pub(super) fn edge_bounds(graph: &FlowGraph, idx: EdgeIndex, flow: Expr) -> [Expr; 2] {
+    let capacity = Expr::Real(graph[idx].capacity);
+    [flow.clone().le(capacity), flow.ge(Expr::zero())]
+}
+
+/// Groups the variables of the nodes by the entity they belong to, summing the ones of the same entity.
+///
+/// Nodes sharing the same variable, like the two outputs of a splitter, are only counted once.
+fn entity_map(graph: &FlowGraph, map: &HashMap<NodeIndex, Var>) -> HashMap<EntityId, Expr> {
+    let mut grouped: HashMap<EntityId, HashMap<String, &Var>> = HashMap::new();
+    for (idx, var) in map {
+        grouped
+            .entry(graph[*idx].get_id())
+            .or_default()
+            .insert(var.name.clone(), var);
+    }
+    grouped
+        .into_iter()
+        .map(|(id, vars)| {
+            let mut vars = vars.into_values().map(Var::expr).collect::<Vec<_>>();
+            let total = if vars.len() == 1 {
+                vars.pop().unwrap()
+            } else {
+                Expr::Add(vars)
+            };
+            (id, total)
+        })
+        .collect()
+}
@@ -0,0 +1,398 @@
+//! Solver-independent formulas over linear arithmetic, printable as SMT-LIB2
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    ops::{Not, Sub},
+};
+
+use fraction::GenericFraction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Sort {
+    Bool,
+    Int,
+    Real,
+}
+
+impl Display for Sort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Bool => "Bool",
+            Self::Int => "Int",
+            Self::Real => "Real",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Named variable, two variables with the same name are the same
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Var {
+    pub name: String,
+    pub sort: Sort,
+}
+
+impl Var {
+    pub fn new(name: impl Into<String>, sort: Sort) -> Self {
+        Self {
+            name: name.into(),
+            sort,
+        }
+    }
+
+    pub fn expr(&self) -> Expr {
+        Expr::Var(self.clone())
+    }
+}
+
+/// Value of a variable in a model found by a solver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Number(GenericFraction<u128>),
+}
+
+/// Boolean or arithmetic expression
+///
+/// Integers are converted to reals when mixed with them, like z3 does.
+/// Multiplication is only supported by constants, see [`Expr::Scale`], keeping the formulas linear.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Bool(bool),
+    Int(i64),
+    Real(GenericFraction<u128>),
+    Var(Var),
+    Add(Vec<Expr>),
+    /// Multiplication of an expression by a constant
+    Scale(GenericFraction<u128>, Box<Expr>),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Implies(Box<Expr>, Box<Expr>),
+    /// If-then-else, the branches have to be of the same kind
+    Ite(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Equality of two numbers or two booleans
+    Eq(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Forall(Vec<Var>, Box<Expr>),
+    Exists(Vec<Var>, Box<Expr>),
+}
+
+impl From<GenericFraction<u128>> for Expr {
+    fn from(value: GenericFraction<u128>) -> Self {
+        Self::Real(value)
+    }
+}
+
+impl Not for Expr {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        Self::Not(Box::new(self))
+    }
+}
+
+impl Sub for Expr {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::Add(vec![
+            self,
+            Self::Scale(-GenericFraction::from(1), Box::new(rhs)),
+        ])
+    }
+}
+
+impl Expr {
+    pub fn zero() -> Self {
+        Self::Real(0.into())
+    }
+
+    pub fn implies(self, other: Self) -> Self {
+        Self::Implies(Box::new(self), Box::new(other))
+    }
+
+    pub fn ite(self, then: Self, otherwise: Self) -> Self {
+        Self::Ite(Box::new(self), Box::new(then), Box::new(otherwise))
+    }
+
+    pub fn _eq(self, other: Self) -> Self {
+        Self::Eq(Box::new(self), Box::new(other))
+    }
+
+    pub fn le(self, other: Self) -> Self {
+        Self::Le(Box::new(self), Box::new(other))
+    }
+
+    pub fn lt(self, other: Self) -> Self {
+        Self::Lt(Box::new(self), Box::new(other))
+    }
+
+    pub fn ge(self, other: Self) -> Self {
+        other.le(self)
+    }
+
+    /// Pairwise equality of all the expressions.
+    pub fn equality(values: &[Self]) -> Self {
+        let pairwise_eq = values
+            .windows(2)
+            .map(|w| w[0].clone()._eq(w[1].clone()))
+            .collect();
+        Self::And(pairwise_eq)
+    }
+
+    /// Returns the sort of the expression, arithmetic is over reals if any of the operands is.
+    pub fn sort(&self) -> Sort {
+        match self {
+            Self::Int(_) => Sort::Int,
+            Self::Real(_) | Self::Scale(..) => Sort::Real,
+            Self::Var(var) => var.sort,
+            Self::Add(exprs) => arith_sort(exprs.iter()),
+            Self::Ite(_, a, b) => match a.sort() {
+                Sort::Bool => Sort::Bool,
+                _ => arith_sort([a.as_ref(), b.as_ref()].into_iter()),
+            },
+            _ => Sort::Bool,
+        }
+    }
+
+    /// Returns `true` if the expression contains a quantifier.
+    pub fn has_quantifiers(&self) -> bool {
+        let mut found = false;
+        self.visit(&mut |e| found |= matches!(e, Self::Forall(..) | Self::Exists(..)));
+        found
+    }
+
+    /// Calls `f` on the expression and all of its subexpressions.
+    pub fn visit(&self, f: &mut impl FnMut(&Self)) {
+        f(self);
+        match self {
+            Self::Bool(_) | Self::Int(_) | Self::Real(_) | Self::Var(_) => (),
+            Self::Add(exprs) | Self::And(exprs) | Self::Or(exprs) => {
+                exprs.iter().for_each(|e| e.visit(f))
+            }
+            Self::Scale(_, e) | Self::Not(e) | Self::Forall(_, e) | Self::Exists(_, e) => {
+                e.visit(f)
+            }
+            Self::Implies(a, b) | Self::Eq(a, b) | Self::Le(a, b) | Self::Lt(a, b) => {
+                a.visit(f);
+                b.visit(f);
+            }
+            Self::Ite(c, a, b) => {
+                c.visit(f);
+                a.visit(f);
+                b.visit(f);
+            }
+        }
+    }
+
+    /// Adds the variables that are not bound by a quantifier to `vars`.
+    pub fn free_vars(&self, vars: &mut BTreeSet<Var>) {
+        match self {
+            Self::Var(var) => {
+                vars.insert(var.clone());
+            }
+            Self::Forall(bound, e) | Self::Exists(bound, e) => {
+                let mut inner = BTreeSet::new();
+                e.free_vars(&mut inner);
+                vars.extend(inner.into_iter().filter(|v| !bound.contains(v)));
+            }
+            Self::Bool(_) | Self::Int(_) | Self::Real(_) => (),
+            Self::Add(exprs) | Self::And(exprs) | Self::Or(exprs) => {
+                exprs.iter().for_each(|e| e.free_vars(vars))
+            }
+            Self::Scale(_, e) | Self::Not(e) => e.free_vars(vars),
+            Self::Implies(a, b) | Self::Eq(a, b) | Self::Le(a, b) | Self::Lt(a, b) => {
+                a.free_vars(vars);
+                b.free_vars(vars);
+            }
+            Self::Ite(c, a, b) => {
+                c.free_vars(vars);
+                a.free_vars(vars);
+                b.free_vars(vars);
+            }
+        }
+    }
+
+    /// Evaluates the expression given the values of its variables.
+    ///
+    /// Returns `None` if a variable is missing or the expression is quantified.
+    pub fn eval(&self, values: &HashMap<String, Value>) -> Option<Value> {
+        let number = |e: &Self| match e.eval(values)? {
+            Value::Number(n) => Some(n),
+            Value::Bool(_) => None,
+        };
+        let boolean = |e: &Self| match e.eval(values)? {
+            Value::Bool(b) => Some(b),
+            Value::Number(_) => None,
+        };
+        let value = match self {
+            Self::Bool(b) => Value::Bool(*b),
+            Self::Int(i) => Value::Number(int_fraction(*i)),
+            Self::Real(r) => Value::Number(*r),
+            Self::Var(var) => *values.get(&var.name)?,
+            Self::Add(exprs) => {
+                let mut sum = GenericFraction::from(0);
+                for e in exprs {
+                    sum += number(e)?;
+                }
+                Value::Number(sum)
+            }
+            Self::Scale(factor, e) => Value::Number(*factor * number(e)?),
+            Self::Not(e) => Value::Bool(!boolean(e)?),
+            Self::And(exprs) => {
+                let mut all = true;
+                for e in exprs {
+                    all &= boolean(e)?;
+                }
+                Value::Bool(all)
+            }
+            Self::Or(exprs) => {
+                let mut any = false;
+                for e in exprs {
+                    any |= boolean(e)?;
+                }
+                Value::Bool(any)
+            }
+            Self::Implies(a, b) => Value::Bool(!boolean(a)? || boolean(b)?),
+            Self::Ite(c, a, b) => {
+                if boolean(c)? {
+                    a.eval(values)?
+                } else {
+                    b.eval(values)?
+                }
+            }
+            Self::Eq(a, b) => Value::Bool(a.eval(values)? == b.eval(values)?),
+            Self::Le(a, b) => Value::Bool(number(a)? <= number(b)?),
+            Self::Lt(a, b) => Value::Bool(number(a)? < number(b)?),
+            Self::Forall(..) | Self::Exists(..) => return None,
+        };
+        Some(value)
+    }
+
+    /// Writes the expression as an SMT-LIB2 term, converting integers to reals where needed.
+    fn write_smt2(&self, f: &mut std::fmt::Formatter<'_>, sort: Sort) -> std::fmt::Result {
+        /* integer operands of real arithmetic are converted explicitly, as required by SMT-LIB2 */
+        if sort == Sort::Real && self.sort() == Sort::Int {
+            write!(f, "(to_real ")?;
+            self.write_smt2(f, Sort::Int)?;
+            return write!(f, ")");
+        }
+        let list = |f: &mut std::fmt::Formatter<'_>, op: &str, exprs: &[&Expr], sort: Sort| {
+            write!(f, "({}", op)?;
+            for e in exprs {
+                write!(f, " ")?;
+                e.write_smt2(f, sort)?;
+            }
+            write!(f, ")")
+        };
+        /* the operands of a comparison have the same sort */
+        let operands = |a: &Expr, b: &Expr| match (a.sort(), b.sort()) {
+            (Sort::Real, _) | (_, Sort::Real) => Sort::Real,
+            (sort, _) => sort,
+        };
+        match self {
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Int(i) if *i < 0 => write!(f, "(- {})", i.unsigned_abs()),
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Real(r) => write_real(f, r),
+            Self::Var(var) => write!(f, "{}", var.name),
+            Self::Add(exprs) => match exprs.as_slice() {
+                [] if sort == Sort::Int => write!(f, "0"),
+                [] => write!(f, "0.0"),
+                [e] => e.write_smt2(f, sort),
+                exprs => list(f, "+", &exprs.iter().collect::<Vec<_>>(), sort),
+            },
+            Self::Scale(factor, e) => {
+                write!(f, "(* ")?;
+                write_real(f, factor)?;
+                write!(f, " ")?;
+                e.write_smt2(f, Sort::Real)?;
+                write!(f, ")")
+            }
+            Self::Not(e) => list(f, "not", &[e.as_ref()], Sort::Bool),
+            Self::And(exprs) if exprs.is_empty() => write!(f, "true"),
+            Self::Or(exprs) if exprs.is_empty() => write!(f, "false"),
+            Self::And(exprs) => list(f, "and", &exprs.iter().collect::<Vec<_>>(), Sort::Bool),
+            Self::Or(exprs) => list(f, "or", &exprs.iter().collect::<Vec<_>>(), Sort::Bool),
+            Self::Implies(a, b) => list(f, "=>", &[a.as_ref(), b.as_ref()], Sort::Bool),
+            Self::Ite(c, a, b) => {
+                /* boolean branches are written as such, arithmetic ones with the sort of the `ite` */
+                let sort = match a.sort() {
+                    Sort::Bool => Sort::Bool,
+                    _ => sort,
+                };
+                write!(f, "(ite ")?;
+                c.write_smt2(f, Sort::Bool)?;
+                write!(f, " ")?;
+                a.write_smt2(f, sort)?;
+                write!(f, " ")?;
+                b.write_smt2(f, sort)?;
+                write!(f, ")")
+            }
+            Self::Eq(a, b) => list(f, "=", &[a.as_ref(), b.as_ref()], operands(a, b)),
+            Self::Le(a, b) => list(f, "<=", &[a.as_ref(), b.as_ref()], operands(a, b)),
+            Self::Lt(a, b) => list(f, "<", &[a.as_ref(), b.as_ref()], operands(a, b)),
+            Self::Forall(vars, e) | Self::Exists(vars, e) if vars.is_empty() => {
+                e.write_smt2(f, Sort::Bool)
+            }
+            Self::Forall(vars, e) | Self::Exists(vars, e) => {
+                let quantifier = match self {
+                    Self::Forall(..) => "forall",
+                    _ => "exists",
+                };
+                write!(f, "({} (", quantifier)?;
+                for var in vars {
+                    write!(f, "({} {})", var.name, var.sort)?;
+                }
+                write!(f, ") ")?;
+                e.write_smt2(f, Sort::Bool)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_smt2(f, self.sort())
+    }
+}
+
+fn arith_sort<'e>(mut exprs: impl Iterator<Item = &'e Expr>) -> Sort {
+    if exprs.any(|e| e.sort() == Sort::Real) {
+        Sort::Real
+    } else {
+        Sort::Int
+    }
+}
+
+fn int_fraction(i: i64) -> GenericFraction<u128> {
+    let value = GenericFraction::from(i.unsigned_abs() as u128);
+    if i < 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Writes a rational as an SMT-LIB2 real, e.g. `(- (/ 1.0 2.0))`.
+///
+/// Fails on infinite numbers and NaN, which SMT-LIB2 can't express.
+fn write_real(f: &mut std::fmt::Formatter<'_>, r: &GenericFraction<u128>) -> std::fmt::Result {
+    let (Some(numer), Some(denom)) = (r.numer(), r.denom()) else {
+        return Err(std::fmt::Error);
+    };
+    let value = if *denom == 1 {
+        format!("{}.0", numer)
+    } else {
+        format!("(/ {}.0 {}.0)", numer, denom)
+    };
+    if *r < GenericFraction::from(0) {
+        write!(f, "(- {})", value)
+    } else {
+        write!(f, "{}", value)
+    }
+}
@@ -0,0 +1,403 @@
+//! Solver-independent back-end, proving properties with any [`SolverBackend`]
+//!
+//! The flow graph is encoded as [`Expr`]essions over named variables by a [`GraphModel`],
+//! the negation of the property is added and the resulting [`Problem`] is handed to a backend.
+//! As for the z3 back-end, finding a model constitutes a counter-example.
+//!
+//! Two backends are available:
+//!  - `Z3Backend` lowers the formulas to the z3 bindings, with the `z3` feature
+//!  - [`Smt2Process`] runs an external solver like `cvc5` and talks SMT-LIB2 with it
+//!
+//! Properties using quantifiers, like [`Property::ThroughputUnlimited`], need a backend supporting them,
+//! see [`Problem::is_quantifier_free`].
+
+mod encode;
+mod formula;
+mod production;
+mod smt2;
+#[cfg(feature = "z3")]
+mod z3_backend;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{Display, Write},
+};
+
+use crate::{entities::FBEntity, ir::FlowGraph};
+
+pub use self::{
+    encode::{EncodeError, GraphModel, ModelFlags},
+    formula::{Expr, Sort, Value, Var},
+    production::{Production, ProductionModel, Supply},
+    smt2::Smt2Process,
+};
+
+#[cfg(feature = "z3")]
+pub use self::z3_backend::Z3Backend;
+
+#[cfg(feature = "z3")]
+pub(crate) use self::z3_backend::{new_solver, z3_number, Lowering};
+
+use super::{ProofOutcome, ProofResult, Property, SolverOptions};
+
+/// Errors of a [`SolverBackend`] that prevent it from solving a problem
+#[derive(Debug)]
+pub enum BackendError {
+    /// The solver process could not be started
+    Spawn(std::io::Error),
+    /// Communication with the solver process failed
+    Io(std::io::Error),
+    /// The solver reported an error
+    Solver(String),
+    /// The output of the solver could not be understood
+    UnexpectedOutput(String),
+    /// The problem or the options use something the backend does not support
+    Unsupported(String),
+    /// The problem can't be handed to the solver, e.g. because of an infinite number
+    Encode(EncodeError),
+}
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spawn(e) => write!(f, "Could not start the solver: {}", e),
+            Self::Io(e) => write!(f, "Could not communicate with the solver: {}", e),
+            Self::Solver(message) => write!(f, "Solver error: {}", message),
+            Self::UnexpectedOutput(output) => write!(f, "Unexpected solver output `{}`", output),
+            Self::Unsupported(feature) => write!(f, "Unsupported {}", feature),
+            Self::Encode(e) => write!(f, "Could not encode the problem: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Conjunction of formulas whose satisfiability is checked by a [`SolverBackend`]
+#[derive(Debug, Clone, Default)]
+pub struct Problem {
+    pub assertions: Vec<Expr>,
+}
+
+impl Problem {
+    /// Returns the free variables of the assertions, sorted by name.
+    pub fn variables(&self) -> BTreeSet<Var> {
+        let mut vars = BTreeSet::new();
+        for assertion in &self.assertions {
+            assertion.free_vars(&mut vars);
+        }
+        vars
+    }
+
+    pub fn is_quantifier_free(&self) -> bool {
+        !self.assertions.iter().any(Expr::has_quantifiers)
+    }
+
+    /// Declares the variables and asserts the formulas in SMT-LIB2.
+    ///
+    /// Fails if a formula contains an infinite number or NaN, which SMT-LIB2 can't express.
+    pub fn to_smt2(&self) -> Result<String, EncodeError> {
+        let mut smt2 = String::new();
+        for var in self.variables() {
+            smt2 += &format!("(declare-fun {} () {})\n", var.name, var.sort);
+        }
+        for assertion in &self.assertions {
+            writeln!(smt2, "(assert {})", assertion).map_err(|_| EncodeError::NonFiniteNumber)?;
+        }
+        Ok(smt2)
+    }
+
+    /// Writes a script checking the problem in SMT-LIB2, with the `options` set before the assertions.
+    ///
+    /// The resource limit and the timeout are specific to each solver and are not part of the script.
+    pub fn script(&self, options: &SolverOptions) -> Result<String, EncodeError> {
+        let mut script = String::new();
+        if options.model {
+            script += "(set-option :produce-models true)\n";
+        }
+        if let Some(seed) = options.seed {
+            script += &format!("(set-option :random-seed {})\n", seed);
+        }
+        if let Some(logic) = &options.logic {
+            script += &format!("(set-logic {})\n", logic);
+        }
+        script += &self.to_smt2()?;
+        script += "(check-sat)\n";
+        Ok(script)
+    }
+
+    /// Writes the [`Problem::script`] followed by `(get-model)` if models are enabled, to be run by another solver.
+    pub fn export(&self, options: &SolverOptions) -> Result<String, EncodeError> {
+        let mut smt2 = self.script(options)?;
+        if options.model {
+            smt2 += "(get-model)\n";
+        }
+        Ok(smt2)
+    }
+}
+
+/// Answer of a [`SolverBackend`] to a [`Problem`]
+#[derive(Debug, Clone)]
+pub struct Solution {
+    /// Whether the assertions are satisfiable
+    pub result: ProofResult,
+    /// Values of the free variables, by name, if satisfiable and models are enabled
+    pub model: Option<HashMap<String, Value>>,
+    /// Why the solver gave up if the result is [`ProofResult::Unknown`]
+    pub reason_unknown: Option<String>,
+}
+
+/// Engine deciding the satisfiability of [`Problem`]s
+pub trait SolverBackend {
+    /// Name of the backend, shown to users
+    fn name(&self) -> &str;
+
+    /// Returns `false` if the backend can't decide the problem, e.g. because it uses quantifiers.
+    fn supports(&self, _problem: &Problem) -> bool {
+        true
+    }
+
+    /// Checks whether the assertions of the problem are satisfiable, configured by the `options`.
+    fn check(
+        &mut self,
+        problem: &Problem,
+        options: &SolverOptions,
+    ) -> Result<Solution, BackendError>;
+}
+
+impl Property {
+    /// Returns the problem whose models are counter-examples to the property, see [`Property::negation`].
+    pub fn problem(
+        &self,
+        model: &GraphModel,
+        entities: &[FBEntity<i32>],
+    ) -> Result<Problem, EncodeError> {
+        Ok(Problem {
+            assertions: vec![self.negation(model, entities)?],
+        })
+    }
+
+    /// Proves the property on a simplified graph with the given backend, see [`Property::prove`].
+    ///
+    /// Errors of the encoding or of the backend result in [`ProofResult::Unknown`], with the error as reason.
+    pub fn prove_on(
+        &self,
+        backend: &mut dyn SolverBackend,
+        graph: &FlowGraph,
+        entities: &[FBEntity<i32>],
+        options: &SolverOptions,
+    ) -> ProofOutcome {
+        let graph = self.oriented(graph);
+        let model = GraphModel::new(&graph, self.flags());
+        let unknown = |reason: String| ProofOutcome {
+            result: ProofResult::Unknown,
+            counterexample: None,
+            reason_unknown: Some(reason),
+        };
+        let problem = match self.problem(&model, entities) {
+            Ok(problem) => problem,
+            Err(e) => return unknown(e.to_string()),
+        };
+        if !backend.supports(&problem) {
+            return unknown(format!("{} does not support the property", backend.name()));
+        }
+        match backend.check(&problem, options) {
+            Ok(solution) => ProofOutcome {
+                result: solution.result.not(),
+                counterexample: solution.model.map(|values| model.counterexample(&values)),
+                reason_unknown: solution.reason_unknown,
+            },
+            Err(e) => unknown(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fraction::GenericFraction;
+
+    use super::*;
+    use crate::{
+        backends::ModelFlags,
+        entities::EntityId,
+        recipes::RecipeDatabase,
+        test_utils::{load_graph, production_graph},
+    };
+
+    fn load(file: &str, removed: &[EntityId], lanes: bool) -> (FlowGraph, Vec<FBEntity<i32>>) {
+        load_graph(file, removed, |compiler| {
+            if lanes {
+                compiler.create_lane_graph().unwrap()
+            } else {
+                compiler.create_graph()
+            }
+        })
+    }
+
+    /// The z3 backend finds the known results of the standard properties, `true` if the property holds.
+    #[cfg(feature = "z3")]
+    #[test]
+    fn z3_backend_results() {
+        let cases: [(&str, &[EntityId], Property, bool); 8] = [
+            ("tests/4-4", &[3], Property::BeltBalancer, true),
+            (
+                "tests/3-2-broken",
+                &[4, 5, 6],
+                Property::BeltBalancer,
+                false,
+            ),
+            ("tests/4-4", &[3], Property::EqualDrain, true),
+            ("tests/4-4-tu", &[], Property::ThroughputUnlimited, true),
+            ("tests/4-4-ntu", &[], Property::ThroughputUnlimited, false),
+            ("tests/4-4-tu", &[], Property::Universal, false),
+            ("tests/lane_balancer", &[], Property::LaneBalancer, true),
+            ("tests/4-4", &[3], Property::LaneBalancer, false),
+        ];
+        for (file, removed, property, holds) in cases {
+            let (graph, entities) = load(file, removed, property.needs_lanes());
+            let outcome =
+                property.prove_on(&mut Z3Backend, &graph, &entities, &SolverOptions::default());
+            let found = match outcome.result {
+                ProofResult::Sat => Some(true),
+                ProofResult::Unsat => Some(false),
+                ProofResult::Unknown => None,
+            };
+            assert_eq!(found, Some(holds), "{} of {}", property, file);
+        }
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn backend_counterexample() {
+        let (graph, entities) = load("tests/3-2-broken", &[4, 5, 6], false);
+        let outcome = Property::BeltBalancer.prove_on(
+            &mut Z3Backend,
+            &graph,
+            &entities,
+            &SolverOptions::default(),
+        );
+        assert!(matches!(outcome.result, ProofResult::Unsat));
+        let counterexample = outcome.counterexample.unwrap();
+        assert_eq!(counterexample.edges.len(), graph.edge_count());
+        let mut outputs = counterexample.outputs.values();
+        let first = outputs.next().unwrap();
+        assert!(outputs.any(|output| output != first));
+    }
+
+    /// Reaching a production rate is checked by any backend, the largest rate being 2.31 gears/s.
+    #[test]
+    fn production_problem() {
+        let graph = production_graph("tests/gear_column", &RecipeDatabase::vanilla());
+        let mut supplies = vec![Supply {
+            input: 1,
+            item: "iron-plate".to_owned(),
+            rate: None,
+        }];
+        let model = ProductionModel::new(&graph, "iron-gear-wheel", &supplies).unwrap();
+        let smt2 = model.problem(GenericFraction::from(2)).to_smt2().unwrap();
+        assert!(smt2.contains("iron-gear-wheel () Real)"));
+
+        #[cfg(feature = "z3")]
+        {
+            let options = SolverOptions::default();
+            let reaches = |rate: f64| {
+                let problem = model.problem(GenericFraction::from(rate));
+                Z3Backend.check(&problem, &options).unwrap().result
+            };
+            assert!(matches!(reaches(2.31), ProofResult::Sat));
+            assert!(matches!(reaches(2.32), ProofResult::Unsat));
+        }
+
+        /* infinite rates can't be written in SMT-LIB2 nor lowered to z3 */
+        let infinite = model.problem(GenericFraction::infinity());
+        assert_eq!(infinite.to_smt2(), Err(EncodeError::NonFiniteNumber));
+        #[cfg(feature = "z3")]
+        assert!(matches!(
+            Z3Backend.check(&infinite, &SolverOptions::default()),
+            Err(BackendError::Encode(EncodeError::NonFiniteNumber))
+        ));
+
+        supplies[0].input = 9;
+        assert!(matches!(
+            ProductionModel::new(&graph, "iron-gear-wheel", &supplies),
+            Err(EncodeError::UnknownInput(9))
+        ));
+    }
+
+    #[test]
+    fn quantifier_free_problems() {
+        let (graph, entities) = load("tests/4-4-tu", &[], false);
+        let model = GraphModel::new(&graph, ModelFlags::empty());
+        assert!(Property::BeltBalancer
+            .problem(&model, &entities)
+            .unwrap()
+            .is_quantifier_free());
+        let model = GraphModel::new(&graph, ModelFlags::Relaxed);
+        assert!(!Property::ThroughputUnlimited
+            .problem(&model, &entities)
+            .unwrap()
+            .is_quantifier_free());
+    }
+
+    #[test]
+    fn lane_balancer_needs_lanes() {
+        /* without lanes, the outputs would be balanced vacuously */
+        let (graph, entities) = load("tests/4-4", &[3], false);
+        let model = GraphModel::new(&graph, ModelFlags::empty());
+        assert!(matches!(
+            Property::LaneBalancer.problem(&model, &entities),
+            Err(EncodeError::NoLane(_))
+        ));
+    }
+
+    #[test]
+    fn smt2_encoding() {
+        let (graph, entities) = load("tests/simple_belt", &[], false);
+        let model = GraphModel::new(&graph, ModelFlags::Blocked);
+        let smt2 = Property::Universal
+            .problem(&model, &entities)
+            .unwrap()
+            .to_smt2()
+            .unwrap();
+        assert!(smt2.contains("(declare-fun input_1 () Int)"));
+        assert!(smt2.contains("(declare-fun output_3 () Real)"));
+        assert!(smt2.contains("(exists ((output_value Real))"));
+        assert!(!smt2.contains("(declare-fun output_value"));
+    }
+
+    #[test]
+    fn evaluate_model() {
+        let (graph, _) = load("tests/simple_belt", &[], false);
+        let model = GraphModel::new(&graph, ModelFlags::empty());
+        let rate = Value::Number(GenericFraction::from(15));
+        let values = Problem {
+            assertions: model.model_constraint.clone(),
+        }
+        .variables()
+        .into_iter()
+        .map(|var| (var.name, rate))
+        .collect::<HashMap<_, _>>();
+        for constraint in &model.model_constraint {
+            assert_eq!(constraint.eval(&values), Some(Value::Bool(true)));
+        }
+        let counterexample = model.counterexample(&values);
+        assert_eq!(counterexample.inputs[&1], GenericFraction::from(15));
+        assert_eq!(counterexample.outputs[&3], GenericFraction::from(15));
+    }
+
+    #[test]
+    fn missing_solver() {
+        let (graph, entities) = load("tests/simple_belt", &[], false);
+        let mut backend = Smt2Process::new("verifactory-missing-solver", &[]);
+        let outcome = Property::BeltBalancer.prove_on(
+            &mut backend,
+            &graph,
+            &entities,
+            &SolverOptions::default(),
+        );
+        assert!(matches!(outcome.result, ProofResult::Unknown));
+        assert!(outcome
+            .reason_unknown
+            .unwrap()
+            .starts_with("Could not start"));
+    }
+}
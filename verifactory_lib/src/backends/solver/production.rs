@@ -0,0 +1,280 @@
+//! Encoding of the item flows of a production graph as solver-independent formulas
+//!
+//! Unlike [`super::GraphModel`], which only counts items, each edge carries a flow per item type.
+//! Belts, splitters and mergers conserve each item, [`Craft`] nodes turn ingredients into products
+//! at the ratios of their recipe.
+
+use std::collections::{BTreeSet, HashMap};
+
+use fraction::GenericFraction;
+use petgraph::prelude::{EdgeIndex, NodeIndex};
+
+use crate::{
+    entities::EntityId,
+    ir::{Craft, FlowGraph, GraphHelper, Node},
+};
+
+use super::{
+    encode::edge_bounds,
+    formula::{Expr, Sort, Value, Var},
+    EncodeError, Problem,
+};
+
+/// Item fed to the blueprint by one of its inputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Supply {
+    /// Input carrying the item
+    pub input: EntityId,
+    /// Name of the item
+    pub item: String,
+    /// Largest rate of the item in items/s, only limited by the input if `None`
+    pub rate: Option<GenericFraction<u128>>,
+}
+
+/// Production rate of an item, see [`ProductionModel::production`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Production {
+    /// Rate of the item leaving the outputs, in items/s
+    pub rate: GenericFraction<u128>,
+    /// Items/s taken from each supplied input to reach the rate
+    pub inputs: HashMap<EntityId, GenericFraction<u128>>,
+    /// Crafts per second of each assembler to reach the rate
+    pub crafts: HashMap<EntityId, GenericFraction<u128>>,
+}
+
+impl Production {
+    /// Returns `true` if the blueprint can produce at least `rate` items/s.
+    pub fn reaches(&self, rate: GenericFraction<u128>) -> bool {
+        self.rate >= rate
+    }
+}
+
+/// Returns the items an edge may carry.
+///
+/// Inputs only carry the items supplied to them and crafts only produce and accept the items of their recipe,
+/// any other edge may carry any of the `items`.
+fn edge_items(
+    graph: &FlowGraph,
+    edge_idx: EdgeIndex,
+    items: &BTreeSet<String>,
+    supplies: &[Supply],
+) -> BTreeSet<String> {
+    let (src, dst) = graph.edge_endpoints(edge_idx).unwrap();
+    let names = |items: &[(String, GenericFraction<u128>)]| {
+        items
+            .iter()
+            .map(|(item, _)| item.clone())
+            .collect::<BTreeSet<_>>()
+    };
+    let carried = match &graph[src] {
+        Node::Input(input) => supplies
+            .iter()
+            .filter(|s| s.input == input.id)
+            .map(|s| s.item.clone())
+            .collect(),
+        Node::Craft(craft) => names(&craft.products),
+        _ => items.clone(),
+    };
+    match &graph[dst] {
+        Node::Craft(craft) => carried
+            .intersection(&names(&craft.ingredients))
+            .cloned()
+            .collect(),
+        _ => carried,
+    }
+}
+
+/// Returns every item supplied to the graph or used by one of its crafts.
+fn all_items(graph: &FlowGraph, supplies: &[Supply]) -> BTreeSet<String> {
+    let crafted = graph.node_weights().flat_map(|node| match node {
+        Node::Craft(Craft {
+            ingredients,
+            products,
+            ..
+        }) => ingredients.iter().chain(products).collect(),
+        _ => vec![],
+    });
+    crafted
+        .map(|(item, _)| item.clone())
+        .chain(supplies.iter().map(|s| s.item.clone()))
+        .collect()
+}
+
+/// Variables and constraints of the item flows of a graph
+///
+/// The graph should be created with [`crate::frontend::Compiler::create_production_graph`], so that
+/// assemblers consume their ingredients in the ratios of their recipe.
+/// Splitters route the items freely, as with [`super::ModelFlags::Relaxed`].
+#[derive(Debug, Clone)]
+pub struct ProductionModel<'a> {
+    /// Flowgraph associated with the model
+    pub graph: &'a FlowGraph,
+    /// Map from `EdgeIndex` to the flow of each item the edge may carry
+    pub flow_map: HashMap<EdgeIndex, Vec<(String, Var)>>,
+    /// Map from the `NodeIndex` of a supplied input to the items/s it takes
+    pub input_map: HashMap<NodeIndex, Expr>,
+    /// Map from the `NodeIndex` of a craft to its crafts per second
+    pub craft_map: HashMap<NodeIndex, Var>,
+    /// Rate of the produced item leaving the outputs
+    pub produced: Expr,
+    /// Bounds of the edges, conservation of each item, supplies and recipes of the crafts
+    pub model_constraint: Vec<Expr>,
+}
+
+impl<'a> ProductionModel<'a> {
+    /// Encodes the flows of `item` and of everything needed to produce it, given the `supplies` of the inputs.
+    ///
+    /// Inputs without a supply are idle. Fails if a supply is not fed by an input of the graph.
+    pub fn new(graph: &'a FlowGraph, item: &str, supplies: &[Supply]) -> Result<Self, EncodeError> {
+        let is_input = |id| {
+            graph
+                .node_weights()
+                .any(|node| matches!(node, Node::Input(i) if i.id == id))
+        };
+        if let Some(supply) = supplies.iter().find(|s| !is_input(s.input)) {
+            return Err(EncodeError::UnknownInput(supply.input));
+        }
+        let mut model = Self {
+            graph,
+            flow_map: HashMap::new(),
+            input_map: HashMap::new(),
+            craft_map: HashMap::new(),
+            produced: Expr::zero(),
+            model_constraint: vec![],
+        };
+
+        let items = all_items(graph, supplies);
+        for edge_idx in graph.edge_indices() {
+            let flows = edge_items(graph, edge_idx, &items, supplies)
+                .into_iter()
+                .map(|item| {
+                    let name = format!("flow_{}_{}", edge_idx.index(), item);
+                    let flow = Var::new(name, Sort::Real);
+                    model.model_constraint.push(flow.expr().ge(Expr::zero()));
+                    (item, flow)
+                })
+                .collect();
+            model.flow_map.insert(edge_idx, flows);
+            let total = model.total(&[edge_idx]);
+            model
+                .model_constraint
+                .extend(edge_bounds(graph, edge_idx, total));
+        }
+
+        let mut produced = vec![];
+        for node_idx in graph.node_indices() {
+            let in_edges = graph.in_edge_idx(node_idx);
+            let out_edges = graph.out_edge_idx(node_idx);
+            match &graph[node_idx] {
+                Node::Input(input) => {
+                    for supply in supplies.iter().filter(|s| s.input == input.id) {
+                        if let Some(rate) = supply.rate {
+                            let flow = model.sum(&out_edges, &supply.item);
+                            model.model_constraint.push(flow.le(Expr::Real(rate)));
+                        }
+                        model.input_map.insert(node_idx, model.total(&out_edges));
+                    }
+                }
+                Node::Output(_) => produced.push(model.sum(&in_edges, item)),
+                Node::Craft(craft) => {
+                    let name = format!("crafts_{}_{}", craft.id, node_idx.index());
+                    let rate = Var::new(name, Sort::Real);
+                    model.model_constraint.push(rate.expr().ge(Expr::zero()));
+                    let max_crafts = Expr::Real(craft.max_crafts);
+                    model.model_constraint.push(rate.expr().le(max_crafts));
+                    let sides = [
+                        (&in_edges, &craft.ingredients),
+                        (&out_edges, &craft.products),
+                    ];
+                    for (edges, amounts) in sides {
+                        for (item, amount) in amounts {
+                            let flow = Expr::Scale(*amount, Box::new(rate.expr()));
+                            let ast = model.sum(edges, item)._eq(flow);
+                            model.model_constraint.push(ast);
+                        }
+                    }
+                    model.craft_map.insert(node_idx, rate);
+                }
+                /* belts, splitters and mergers conserve each item */
+                Node::Connector(_) | Node::Merger(_) | Node::Splitter(_) => {
+                    for item in &items {
+                        let ast = model.sum(&in_edges, item)._eq(model.sum(&out_edges, item));
+                        model.model_constraint.push(ast);
+                    }
+                }
+            }
+        }
+        model.produced = add(produced);
+        Ok(model)
+    }
+
+    /// Returns the total flow on the `edges` of the items accepted by `filter`.
+    fn sum_by(&self, edges: &[EdgeIndex], filter: impl Fn(&str) -> bool) -> Expr {
+        let flows = edges
+            .iter()
+            .flat_map(|edge_idx| &self.flow_map[edge_idx])
+            .filter(|(item, _)| filter(item))
+            .map(|(_, flow)| flow.expr())
+            .collect();
+        add(flows)
+    }
+
+    /// Returns the total flow of `item` on the `edges`.
+    pub fn sum(&self, edges: &[EdgeIndex], item: &str) -> Expr {
+        self.sum_by(edges, |name| name == item)
+    }
+
+    /// Returns the total flow of all the items on the `edges`.
+    pub fn total(&self, edges: &[EdgeIndex]) -> Expr {
+        self.sum_by(edges, |_| true)
+    }
+
+    /// Returns the free variables of the model constraints, which include all the variables of the maps.
+    pub fn variables(&self) -> BTreeSet<Var> {
+        Problem {
+            assertions: self.model_constraint.clone(),
+        }
+        .variables()
+    }
+
+    /// Returns the problem whose models produce at least `rate` items/s.
+    ///
+    /// Any [`super::SolverBackend`] can check it, finding the largest rate needs an optimizer.
+    pub fn problem(&self, rate: GenericFraction<u128>) -> Problem {
+        let mut assertions = self.model_constraint.clone();
+        assertions.push(self.produced.clone().ge(Expr::Real(rate)));
+        Problem { assertions }
+    }
+
+    /// Reads the [`Production`] out of the values of the variables in a model, unset flows being idle.
+    pub fn production(&self, values: &HashMap<String, Value>) -> Production {
+        let number = |expr: &Expr| match expr.eval(values) {
+            Some(Value::Number(n)) => n,
+            _ => 0.into(),
+        };
+        let mut inputs = HashMap::new();
+        for (idx, flow) in &self.input_map {
+            let id = self.graph[*idx].get_id();
+            *inputs.entry(id).or_insert_with(|| 0.into()) += number(flow);
+        }
+        let crafts = self
+            .craft_map
+            .iter()
+            .map(|(idx, rate)| (self.graph[*idx].get_id(), number(&rate.expr())))
+            .collect();
+        Production {
+            rate: number(&self.produced),
+            inputs,
+            crafts,
+        }
+    }
+}
+
+/// Sum of the `terms`, 0 if there are none.
+fn add(mut terms: Vec<Expr>) -> Expr {
+    match terms.len() {
+        0 => Expr::zero(),
+        1 => terms.pop().unwrap(),
+        _ => Expr::Add(terms),
+    }
+}
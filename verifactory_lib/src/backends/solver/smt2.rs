@@ -0,0 +1,401 @@
+//! Backend running an external SMT solver, talking SMT-LIB2 over its stdin and stdout
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fraction::GenericFraction;
+
+use crate::backends::{ProofResult, SolverOptions};
+
+use super::{
+    formula::{Sort, Value},
+    BackendError, Problem, Solution, SolverBackend,
+};
+
+/// Solver run as a separate process for each problem, e.g. `z3 -in` or `cvc5 --lang=smt2`
+///
+/// The timeout of the [`SolverOptions`] kills the process, the resource limit is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Smt2Process {
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+impl Smt2Process {
+    pub fn new(command: impl Into<String>, args: &[&str]) -> Self {
+        Self {
+            command: command.into(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    /// Parses a command line like `cvc5 --lang=smt2`, splitting it at whitespace.
+    pub fn from_command_line(command_line: &str) -> Option<Self> {
+        let mut words = command_line.split_whitespace();
+        let command = words.next()?;
+        Some(Self {
+            command: command.to_owned(),
+            args: words.map(str::to_owned).collect(),
+        })
+    }
+
+    /// Writes the script sent to the solver, asking for the values of the free variables if satisfiable.
+    fn script(&self, problem: &Problem, options: &SolverOptions) -> Result<String, BackendError> {
+        let mut script = problem.script(options).map_err(BackendError::Encode)?;
+        let variables = problem.variables();
+        if options.model && !variables.is_empty() {
+            let names = variables
+                .iter()
+                .map(|v| v.name.as_str())
+                .collect::<Vec<_>>();
+            script += &format!("(get-value ({}))\n", names.join(" "));
+        }
+        script += "(exit)\n";
+        Ok(script)
+    }
+}
+
+impl SolverBackend for Smt2Process {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn check(
+        &mut self,
+        problem: &Problem,
+        options: &SolverOptions,
+    ) -> Result<Solution, BackendError> {
+        let script = self.script(problem, options)?;
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(BackendError::Spawn)?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        /* write and read on other threads, the solver may block on a full pipe */
+        let writer = thread::spawn(move || stdin.write_all(script.as_bytes()));
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+
+        let deadline = options
+            .timeout_ms
+            .map(|ms| Instant::now() + Duration::from_millis(ms as u64));
+        loop {
+            if child.try_wait().map_err(BackendError::Io)?.is_some() {
+                break;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                /* the pipes are closed once the process is killed, terminating the threads */
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(Solution {
+                    result: ProofResult::Unknown,
+                    model: None,
+                    reason_unknown: Some("timeout".to_owned()),
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        /* the solver may exit without reading everything, e.g. on an error */
+        let _ = writer.join();
+        let output = reader
+            .join()
+            .map_err(|_| BackendError::Io(std::io::Error::other("the output reader panicked")))?
+            .map_err(BackendError::Io)?;
+        parse_output(problem, &output)
+    }
+}
+
+/// Parses the response to `(check-sat)` and `(get-value ...)`.
+fn parse_output(problem: &Problem, output: &str) -> Result<Solution, BackendError> {
+    let mut sexprs = SExpr::parse_all(output)?.into_iter();
+    let result = match sexprs.next() {
+        Some(SExpr::Atom(a)) if a == "sat" => ProofResult::Sat,
+        Some(SExpr::Atom(a)) if a == "unsat" => ProofResult::Unsat,
+        Some(SExpr::Atom(a)) if a == "unknown" => ProofResult::Unknown,
+        Some(SExpr::List(list)) if matches!(list.first(), Some(SExpr::Atom(a)) if a == "error") => {
+            let message = list.get(1).map(ToString::to_string).unwrap_or_default();
+            return Err(BackendError::Solver(message));
+        }
+        other => {
+            let found = other.map(|s| s.to_string()).unwrap_or_default();
+            return Err(BackendError::UnexpectedOutput(found));
+        }
+    };
+    let model = match (result, sexprs.next()) {
+        (ProofResult::Sat, Some(SExpr::List(values))) => {
+            let sorts = problem
+                .variables()
+                .into_iter()
+                .map(|v| (v.name, v.sort))
+                .collect::<HashMap<_, _>>();
+            let mut model = HashMap::new();
+            for value in &values {
+                let SExpr::List(pair) = value else {
+                    return Err(BackendError::UnexpectedOutput(value.to_string()));
+                };
+                let [SExpr::Atom(name), term] = pair.as_slice() else {
+                    return Err(BackendError::UnexpectedOutput(value.to_string()));
+                };
+                let value = match sorts.get(name) {
+                    Some(Sort::Bool) => term.to_bool(),
+                    Some(_) => term.to_number().map(Value::Number),
+                    None => None,
+                };
+                let value =
+                    value.ok_or_else(|| BackendError::UnexpectedOutput(term.to_string()))?;
+                model.insert(name.clone(), value);
+            }
+            Some(model)
+        }
+        _ => None,
+    };
+    let reason_unknown = match result {
+        ProofResult::Unknown => Some("unknown".to_owned()),
+        _ => None,
+    };
+    Ok(Solution {
+        result,
+        model,
+        reason_unknown,
+    })
+}
+
+/// Parses a single numeral written in SMT-LIB, like `12` or `(- (/ 1.0 3.0))`.
+#[cfg_attr(not(feature = "z3"), allow(dead_code))]
+pub(super) fn parse_numeral(input: &str) -> Option<GenericFraction<u128>> {
+    match SExpr::parse_all(input).ok()?.as_slice() {
+        [numeral] => numeral.to_number(),
+        _ => None,
+    }
+}
+
+/// S-expression of the output of a solver
+#[derive(Debug, Clone, PartialEq)]
+enum SExpr {
+    Atom(String),
+    List(Vec<SExpr>),
+}
+
+impl SExpr {
+    /// Parses a sequence of s-expressions, skipping comments.
+    fn parse_all(input: &str) -> Result<Vec<Self>, BackendError> {
+        let mut stack: Vec<Vec<Self>> = vec![vec![]];
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => stack.push(vec![]),
+                ')' => {
+                    let list = stack.pop().filter(|_| !stack.is_empty()).ok_or_else(|| {
+                        BackendError::UnexpectedOutput("unbalanced parentheses".to_owned())
+                    })?;
+                    stack.last_mut().unwrap().push(Self::List(list));
+                }
+                ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+                '"' => {
+                    let mut string = String::new();
+                    while let Some(c) = chars.next() {
+                        /* quotes are escaped by doubling them */
+                        if c == '"' && chars.next_if_eq(&'"').is_none() {
+                            break;
+                        }
+                        string.push(c);
+                    }
+                    stack.last_mut().unwrap().push(Self::Atom(string));
+                }
+                c if c.is_whitespace() => (),
+                c => {
+                    let mut atom = c.to_string();
+                    while let Some(c) =
+                        chars.next_if(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | ';'))
+                    {
+                        atom.push(c);
+                    }
+                    stack.last_mut().unwrap().push(Self::Atom(atom));
+                }
+            }
+        }
+        match stack.len() {
+            1 => Ok(stack.pop().unwrap()),
+            _ => Err(BackendError::UnexpectedOutput(
+                "unbalanced parentheses".to_owned(),
+            )),
+        }
+    }
+
+    fn to_bool(&self) -> Option<Value> {
+        match self {
+            Self::Atom(a) if a == "true" => Some(Value::Bool(true)),
+            Self::Atom(a) if a == "false" => Some(Value::Bool(false)),
+            _ => None,
+        }
+    }
+
+    /// Converts a numeral like `3`, `1.5`, `(- 2.0)` or `(/ 1.0 3.0)`.
+    fn to_number(&self) -> Option<GenericFraction<u128>> {
+        match self {
+            Self::Atom(a) => {
+                let (int, frac) = a.split_once('.').unwrap_or((a, ""));
+                if int.is_empty() || !(int.to_owned() + frac).chars().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                let denom = 10u128.checked_pow(frac.len() as u32)?;
+                let numer = (int.to_owned() + frac).parse::<u128>().ok()?;
+                Some(GenericFraction::new(numer, denom))
+            }
+            Self::List(list) => match list.as_slice() {
+                [Self::Atom(op), e] if op == "-" => Some(-e.to_number()?),
+                [Self::Atom(op), a, b] if op == "/" => Some(a.to_number()? / b.to_number()?),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for SExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Atom(a) => write!(f, "{}", a),
+            Self::List(list) => {
+                write!(f, "(")?;
+                for (i, e) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::solver::{Expr, Var};
+
+    fn problem() -> Problem {
+        let x = Var::new("x", Sort::Real);
+        let b = Var::new("b", Sort::Bool);
+        Problem {
+            assertions: vec![b.expr().implies(x.expr().ge(Expr::Int(1)))],
+        }
+    }
+
+    #[test]
+    fn parse_sat() {
+        let output = "sat\n((b true)\n (x (/ 3.0 2.0)))\n";
+        let solution = parse_output(&problem(), output).unwrap();
+        assert!(matches!(solution.result, ProofResult::Sat));
+        let model = solution.model.unwrap();
+        assert_eq!(model["b"], Value::Bool(true));
+        assert_eq!(
+            model["x"],
+            Value::Number(GenericFraction::new(3u128, 2u128))
+        );
+
+        let output = "sat\n((b false) (x (- 2.5)))";
+        let model = parse_output(&problem(), output).unwrap().model.unwrap();
+        assert_eq!(
+            model["x"],
+            Value::Number(-GenericFraction::new(5u128, 2u128))
+        );
+    }
+
+    #[test]
+    fn numerals() {
+        assert_eq!(parse_numeral("12"), Some(GenericFraction::from(12)));
+        /* larger than i64::MAX */
+        assert_eq!(
+            parse_numeral("(- (/ 100000000000000000000.0 3.0))"),
+            Some(-GenericFraction::new(
+                100_000_000_000_000_000_000u128,
+                3u128
+            ))
+        );
+        assert_eq!(parse_numeral("x"), None);
+        assert_eq!(parse_numeral("1 2"), None);
+    }
+
+    #[test]
+    fn parse_unsat() {
+        /* the solver complains about `get-value` after `unsat` */
+        let output = "unsat\n(error \"line 5 column 10: model is not available\")\n";
+        let solution = parse_output(&problem(), output).unwrap();
+        assert!(matches!(solution.result, ProofResult::Unsat));
+        assert!(solution.model.is_none());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let output = "(error \"line 1 column 5: unknown constant y\")";
+        assert!(matches!(
+            parse_output(&problem(), output),
+            Err(BackendError::Solver(message)) if message.contains("unknown constant")
+        ));
+        assert!(matches!(
+            parse_output(&problem(), "sat\n((x 1.0)"),
+            Err(BackendError::UnexpectedOutput(_))
+        ));
+    }
+
+    #[test]
+    fn script() {
+        let solver = Smt2Process::from_command_line("cvc5 --lang=smt2").unwrap();
+        assert_eq!(solver.args, vec!["--lang=smt2"]);
+        let script = solver
+            .script(&problem(), &SolverOptions::default())
+            .unwrap();
+        assert!(script.starts_with("(set-option :produce-models true)\n"));
+        assert!(script.contains("(declare-fun b () Bool)\n(declare-fun x () Real)\n"));
+        assert!(script.contains("(assert (=> b (<= (to_real 1) x)))\n"));
+        assert!(script.ends_with("(check-sat)\n(get-value (b x))\n(exit)\n"));
+        assert!(Smt2Process::from_command_line("  ").is_none());
+    }
+
+    #[test]
+    fn stub_solver() {
+        let mut solver = Smt2Process::new("sh", &["-c", "cat >/dev/null; echo unsat"]);
+        let solution = solver.check(&problem(), &SolverOptions::default()).unwrap();
+        assert!(matches!(solution.result, ProofResult::Unsat));
+        assert!(solution.model.is_none());
+
+        let mut solver = Smt2Process::new("sh", &["-c", "cat >/dev/null; echo '(error \"oops\")'"]);
+        assert!(matches!(
+            solver.check(&problem(), &SolverOptions::default()),
+            Err(BackendError::Solver(message)) if message.contains("oops")
+        ));
+
+        let mut solver = Smt2Process::new("verifactory-missing-solver", &[]);
+        assert!(matches!(
+            solver.check(&problem(), &SolverOptions::default()),
+            Err(BackendError::Spawn(_))
+        ));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut solver = Smt2Process::new("sleep", &["10"]);
+        let options = SolverOptions {
+            timeout_ms: Some(100),
+            ..SolverOptions::default()
+        };
+        let start = Instant::now();
+        let solution = solver.check(&problem(), &options).unwrap();
+        assert!(matches!(solution.result, ProofResult::Unknown));
+        assert_eq!(solution.reason_unknown.as_deref(), Some("timeout"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
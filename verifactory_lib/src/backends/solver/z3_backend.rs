@@ -0,0 +1,239 @@
+//! Backend lowering the formulas to the z3 bindings
+//!
+//! This is the only place formulas are converted to z3, the proofs and optimizations using z3 directly
+//! go through [`Lowering`] too.
+
+use std::collections::{BTreeSet, HashMap};
+
+use fraction::GenericFraction;
+use z3::{
+    ast::{exists_const, forall_const, Ast, Bool, Dynamic, Int, Real},
+    Config, Context, Model, Solver,
+};
+
+use crate::backends::{ProofResult, SolverOptions};
+
+use super::{
+    formula::{Expr, Sort, Value, Var},
+    smt2::parse_numeral,
+    BackendError, EncodeError, Problem, Solution, SolverBackend,
+};
+
+/// Solves the problems with the z3 bindings, in a new context for each problem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Z3Backend;
+
+impl SolverBackend for Z3Backend {
+    fn name(&self) -> &str {
+        "z3"
+    }
+
+    fn check(
+        &mut self,
+        problem: &Problem,
+        options: &SolverOptions,
+    ) -> Result<Solution, BackendError> {
+        let cfg = Config::new();
+        let ctx = Context::new(&cfg);
+        let solver = new_solver(&ctx, options)?;
+
+        let lowering = Lowering::new(&ctx);
+        for assertion in &problem.assertions {
+            solver.assert(&lowering.bool(assertion).map_err(BackendError::Encode)?);
+        }
+        let result = ProofResult::from(solver.check());
+        let reason_unknown = match result {
+            ProofResult::Unknown => solver.get_reason_unknown(),
+            _ => None,
+        };
+        let model = match result {
+            ProofResult::Sat if options.model => solver
+                .get_model()
+                .map(|model| lowering.values(&model, problem.variables())),
+            _ => None,
+        };
+        Ok(Solution {
+            result,
+            model,
+            reason_unknown,
+        })
+    }
+}
+
+/// Creates a solver for the logic of the `options`, configured with their parameters.
+pub(crate) fn new_solver<'ctx>(
+    ctx: &'ctx Context,
+    options: &SolverOptions,
+) -> Result<Solver<'ctx>, BackendError> {
+    let solver = match &options.logic {
+        None => Solver::new(ctx),
+        Some(logic) => Solver::new_for_logic(ctx, logic.as_str())
+            .ok_or_else(|| BackendError::Unsupported(format!("logic {}", logic)))?,
+    };
+    solver.set_params(&options.params(ctx));
+    Ok(solver)
+}
+
+/// Conversion of the formulas to z3 terms, see [`Expr`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Lowering<'ctx> {
+    ctx: &'ctx Context,
+}
+
+impl<'ctx> Lowering<'ctx> {
+    pub(crate) fn new(ctx: &'ctx Context) -> Self {
+        Self { ctx }
+    }
+
+    /// Evaluates the variables in a model found by z3, skipping the ones it can't evaluate.
+    pub(crate) fn values(
+        &self,
+        model: &Model<'ctx>,
+        vars: BTreeSet<Var>,
+    ) -> HashMap<String, Value> {
+        vars.into_iter()
+            .filter_map(|var| {
+                let value = model.eval(&self.var(&var), true)?;
+                let value = match var.sort {
+                    Sort::Bool => Value::Bool(value.as_bool()?.as_bool()?),
+                    Sort::Int | Sort::Real => Value::Number(z3_number(&value)?),
+                };
+                Some((var.name, value))
+            })
+            .collect()
+    }
+
+    fn var(&self, var: &Var) -> Dynamic<'ctx> {
+        match var.sort {
+            Sort::Bool => Bool::new_const(self.ctx, var.name.as_str()).into(),
+            Sort::Int => Int::new_const(self.ctx, var.name.as_str()).into(),
+            Sort::Real => Real::new_const(self.ctx, var.name.as_str()).into(),
+        }
+    }
+
+    /// Lowers an expression of sort [`Sort::Bool`].
+    ///
+    /// Fails if the expression contains an infinite number or NaN, which z3 can't represent.
+    pub(crate) fn bool(&self, expr: &Expr) -> Result<Bool<'ctx>, EncodeError> {
+        let ctx = self.ctx;
+        let all = |exprs: &[Expr]| {
+            exprs
+                .iter()
+                .map(|e| self.bool(e))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let lowered = match expr {
+            Expr::Bool(b) => Bool::from_bool(ctx, *b),
+            Expr::Var(var) => Bool::new_const(ctx, var.name.as_str()),
+            Expr::Not(e) => self.bool(e)?.not(),
+            Expr::And(exprs) => Bool::and(ctx, &all(exprs)?.iter().collect::<Vec<_>>()),
+            Expr::Or(exprs) => Bool::or(ctx, &all(exprs)?.iter().collect::<Vec<_>>()),
+            Expr::Implies(a, b) => self.bool(a)?.implies(&self.bool(b)?),
+            Expr::Ite(c, a, b) => self.bool(c)?.ite(&self.bool(a)?, &self.bool(b)?),
+            Expr::Eq(a, b) => match (a.sort(), b.sort()) {
+                (Sort::Bool, _) => self.bool(a)?.iff(&self.bool(b)?),
+                (Sort::Int, Sort::Int) => self.int(a)?._eq(&self.int(b)?),
+                _ => self.real(a)?._eq(&self.real(b)?),
+            },
+            Expr::Le(a, b) => match (a.sort(), b.sort()) {
+                (Sort::Int, Sort::Int) => self.int(a)?.le(&self.int(b)?),
+                _ => self.real(a)?.le(&self.real(b)?),
+            },
+            Expr::Lt(a, b) => match (a.sort(), b.sort()) {
+                (Sort::Int, Sort::Int) => self.int(a)?.lt(&self.int(b)?),
+                _ => self.real(a)?.lt(&self.real(b)?),
+            },
+            Expr::Forall(vars, e) | Expr::Exists(vars, e) => {
+                let bound = vars.iter().map(|v| self.var(v)).collect::<Vec<_>>();
+                let bound = bound.iter().map(|v| v as &dyn Ast).collect::<Vec<_>>();
+                let body = self.bool(e)?;
+                match expr {
+                    Expr::Forall(..) => forall_const(ctx, &bound, &[], &body),
+                    _ => exists_const(ctx, &bound, &[], &body),
+                }
+            }
+            Expr::Int(_) | Expr::Real(_) | Expr::Add(_) | Expr::Scale(..) => {
+                unreachable!("{} is not a boolean", expr)
+            }
+        };
+        Ok(lowered)
+    }
+
+    /// Lowers an expression of sort [`Sort::Int`].
+    pub(crate) fn int(&self, expr: &Expr) -> Result<Int<'ctx>, EncodeError> {
+        let lowered = match expr {
+            Expr::Int(i) => Int::from_i64(self.ctx, *i),
+            Expr::Var(var) => Int::new_const(self.ctx, var.name.as_str()),
+            Expr::Add(exprs) if exprs.is_empty() => Int::from_i64(self.ctx, 0),
+            Expr::Add(exprs) => {
+                let terms = exprs
+                    .iter()
+                    .map(|e| self.int(e))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Int::add(self.ctx, &terms.iter().collect::<Vec<_>>())
+            }
+            Expr::Ite(c, a, b) => self.bool(c)?.ite(&self.int(a)?, &self.int(b)?),
+            _ => unreachable!("{} is not an integer", expr),
+        };
+        Ok(lowered)
+    }
+
+    /// Lowers an arithmetic expression, converting integers to reals.
+    pub(crate) fn real(&self, expr: &Expr) -> Result<Real<'ctx>, EncodeError> {
+        if expr.sort() == Sort::Int {
+            return Ok(Real::from_int(&self.int(expr)?));
+        }
+        let lowered = match expr {
+            Expr::Real(r) => self.fraction(r)?,
+            Expr::Var(var) => Real::new_const(self.ctx, var.name.as_str()),
+            Expr::Add(exprs) => {
+                let terms = exprs
+                    .iter()
+                    .map(|e| self.real(e))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Real::add(self.ctx, &terms.iter().collect::<Vec<_>>())
+            }
+            Expr::Scale(factor, e) => {
+                Real::mul(self.ctx, &[&self.fraction(factor)?, &self.real(e)?])
+            }
+            Expr::Ite(c, a, b) => self.bool(c)?.ite(&self.real(a)?, &self.real(b)?),
+            _ => unreachable!("{} is not a real", expr),
+        };
+        Ok(lowered)
+    }
+
+    fn fraction(&self, r: &GenericFraction<u128>) -> Result<Real<'ctx>, EncodeError> {
+        let (Some(numer), Some(denom)) = (r.numer(), r.denom()) else {
+            return Err(EncodeError::NonFiniteNumber);
+        };
+        let sign = if r.is_sign_negative() { "-" } else { "" };
+        let numer = format!("{}{}", sign, numer);
+        Real::from_real_str(self.ctx, &numer, &denom.to_string())
+            .ok_or(EncodeError::NonFiniteNumber)
+    }
+}
+
+/// Converts a numeral of z3, integer or rational, to a fraction.
+///
+/// Numerals which do not fit in `i64` are read from their SMT-LIB form.
+pub(crate) fn z3_number<'a>(value: &dyn Ast<'a>) -> Option<GenericFraction<u128>> {
+    let value = Dynamic::from_ast(value);
+    if let Some(int) = value.as_int() {
+        if let Some(int) = int.as_i64() {
+            return Some(to_fraction(int, 1));
+        }
+    } else if let Some((numer, denom)) = value.as_real()?.as_real() {
+        return Some(to_fraction(numer, denom));
+    }
+    parse_numeral(&value.to_string())
+}
+
+/// Converts a rational value of z3 to a fraction.
+fn to_fraction(numer: i64, denom: i64) -> GenericFraction<u128> {
+    let value = GenericFraction::new(numer.unsigned_abs() as u128, denom.unsigned_abs() as u128);
+    if (numer < 0) != (denom < 0) {
+        -value
+    } else {
+        value
+    }
+}
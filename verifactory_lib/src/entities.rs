@@ -1,18 +1,29 @@
 //! Definitions of entities that are part of a Factorio blueprint
 //!
 use crate::utils::{Direction, Position, Rotation};
-use serde::Deserialize;
+use fraction::GenericFraction;
+use serde::{Deserialize, Serialize};
 use std::ops::{Add, Sub};
 
 pub type EntityId = i32;
 
 /// Contains the subset of fields each entity possesses
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBBaseEntity<T> {
     pub id: EntityId,
     pub position: Position<T>,
     pub direction: Direction,
     pub throughput: f64,
+    /// Name of the prototype of the entity, e.g. `fast-transport-belt`, see [`crate::prototypes`]
+    pub name: String,
+}
+
+impl FBBaseEntity<i32> {
+    /// Capacity of a single lane of the entity, half of its throughput
+    pub fn lane_capacity(&self) -> GenericFraction<u128> {
+        let capacity: GenericFraction<u128> = self.throughput.into();
+        capacity / 2.
+    }
 }
 
 impl<T> FBBaseEntity<T>
@@ -25,11 +36,49 @@ where
     }
 }
 
+/// Bonuses of the modules affecting a machine, either inserted in it or in beacons around it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ModuleEffects {
+    /// Bonus to the crafting speed, `0.5` being +50%
+    pub speed: f64,
+    /// Share of extra products, `0.1` being +10%
+    pub productivity: f64,
+}
+
+impl ModuleEffects {
+    /// Returns the effects of both, e.g. of two modules.
+    pub fn combine(&self, other: &Self) -> Self {
+        Self {
+            speed: self.speed + other.speed,
+            productivity: self.productivity + other.productivity,
+        }
+    }
+
+    /// Returns the effects scaled by `factor`, e.g. the share a beacon transmits.
+    pub fn scale(&self, factor: f64) -> Self {
+        Self {
+            speed: self.speed * factor,
+            productivity: self.productivity * factor,
+        }
+    }
+
+    /// Returns the factor applied to the crafting speed, slowed down to 20% at most as in Factorio.
+    pub fn speed_multiplier(&self) -> f64 {
+        (1.0 + self.speed).max(0.2)
+    }
+
+    /// Returns the factor applied to the products, productivity can't be negative.
+    pub fn productivity_multiplier(&self) -> f64 {
+        1.0 + self.productivity.max(0.0)
+    }
+}
+
 /// Enum of possible entities that are supported by VeriFactory
 ///
 /// The phantoms are used to populate the grid of entities with entities that are bigger than 1x1.
 /// These include the splitter (2x1) and the assembler (3x3).
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum FBEntity<T> {
     Belt(FBBelt<T>),
     Underground(FBUnderground<T>),
@@ -39,6 +88,9 @@ pub enum FBEntity<T> {
     LongInserter(FBLongInserter<T>),
     Assembler(FBAssembler<T>),
     AssemblerPhantom(FBAssemblerPhantom<T>),
+    Beacon(FBBeacon<T>),
+    Loader(FBLoader<T>),
+    Container(FBContainer<T>),
 }
 
 impl<T> FBEntity<T> {
@@ -53,18 +105,21 @@ impl<T> FBEntity<T> {
             Self::LongInserter(b) => &b.base,
             Self::Assembler(b) => &b.base,
             Self::AssemblerPhantom(b) => &b.base,
+            Self::Beacon(b) => &b.base,
+            Self::Loader(b) => &b.base,
+            Self::Container(b) => &b.base,
         }
     }
 }
 
 /// Belt entity
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBBelt<T> {
     pub base: FBBaseEntity<T>,
 }
 
 /// Type of the underground belt. Either going into the ground, `Input`, or exiting, `Output`
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum BeltType {
     Input,
@@ -72,14 +127,14 @@ pub enum BeltType {
 }
 
 /// Underground belt entity
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBUnderground<T> {
     pub base: FBBaseEntity<T>,
     pub belt_type: BeltType,
 }
 
 /// Side priority for input or output of splitters
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum Priority {
     None,
@@ -88,7 +143,7 @@ pub enum Priority {
 }
 
 /// Splitter entity
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBSplitter<T> {
     pub base: FBBaseEntity<T>,
     pub input_prio: Priority,
@@ -99,14 +154,14 @@ impl FBSplitter<i32> {
     /// Get the phantom associated with a splitter entity.
     /// This is the left side of the splitter.
     pub fn get_phantom(&self) -> FBSplitterPhantom<i32> {
-        let mut base = self.base;
+        let mut base = self.base.clone();
         let rotation = base.direction.rotate(Rotation::Anticlockwise, 1);
         base.position = base.position.shift(rotation, 1);
         FBSplitterPhantom { base }
     }
 }
 /// Splitter phantom
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBSplitterPhantom<T> {
     pub base: FBBaseEntity<T>,
 }
@@ -120,7 +175,7 @@ pub trait InserterTrait {
 }
 
 /// Inserter entity
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBInserter<T> {
     pub base: FBBaseEntity<T>,
 }
@@ -136,7 +191,7 @@ impl InserterTrait for FBInserter<i32> {
 }
 
 /// Long inserter entity
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBLongInserter<T> {
     pub base: FBBaseEntity<T>,
 }
@@ -152,16 +207,31 @@ impl InserterTrait for FBLongInserter<i32> {
 }
 
 /// Assembler entity
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBAssembler<T> {
     pub base: FBBaseEntity<T>,
+    /// Name of the recipe set in the blueprint, see [`crate::recipes::RecipeDatabase`]
+    pub recipe: Option<String>,
+    /// Names of its modules, one per filled slot
+    pub modules: Vec<String>,
+    /// Effects of its modules and of the beacons around it
+    pub effects: ModuleEffects,
+}
+
+impl<T> FBAssembler<T> {
+    /// Returns the crafting speed of the assembler including the effects of the modules.
+    ///
+    /// The throughput of the base entity is the crafting speed without modules.
+    pub fn crafting_speed(&self) -> f64 {
+        self.base.throughput * self.effects.speed_multiplier()
+    }
 }
 
 impl FBAssembler<i32> {
     /// Get all the phantoms associated with the assembler entity.
     /// These are all the cells around the assembler entity as it's size is 3x3.
     pub fn get_phantoms(&self) -> Vec<FBAssemblerPhantom<i32>> {
-        let center_base = self.base;
+        let center_base = &self.base;
         let mut phantoms = vec![];
         for dx in -1..=1 {
             for dy in -1..=1 {
@@ -171,7 +241,7 @@ impl FBAssembler<i32> {
                 let position = center_base.position + Position { x: dx, y: dy };
                 let base = FBBaseEntity {
                     position,
-                    ..center_base
+                    ..center_base.clone()
                 };
                 phantoms.push(FBAssemblerPhantom { base });
             }
@@ -181,7 +251,68 @@ impl FBAssembler<i32> {
 }
 
 /// Assembler phantom
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct FBAssemblerPhantom<T> {
     pub base: FBBaseEntity<T>,
 }
+
+/// Beacon entity, transmitting the effects of its modules to the assemblers around it
+///
+/// The throughput of the base entity is its distribution effectivity.
+/// It does not take part in the flow of items, so it has no phantoms.
+#[derive(Debug, Clone)]
+pub struct FBBeacon<T> {
+    pub base: FBBaseEntity<T>,
+    /// Names of its modules, one per filled slot
+    pub modules: Vec<String>,
+    /// Distance between the beacon and the edge of its supply area
+    pub supply_area_distance: i32,
+    /// Effects of its modules, before the distribution effectivity
+    pub effects: ModuleEffects,
+}
+
+impl<T> FBBeacon<T>
+where
+    T: Into<f64> + Copy,
+{
+    /// Returns `true` if the assembler is within the supply area of the beacon.
+    ///
+    /// The area extends [`FBBeacon::supply_area_distance`] tiles around the beacon and has to overlap the
+    /// 3x3 tiles of the assembler.
+    pub fn affects(&self, assembler: &FBAssembler<T>) -> bool {
+        /* the beacon and the assembler reach 1 tile away from their center */
+        let reach = (self.supply_area_distance + 2) as f64;
+        let (beacon, assembler) = (self.base.position, assembler.base.position);
+        let dx = (beacon.x.into() - assembler.x.into()).abs();
+        let dy = (beacon.y.into() - assembler.y.into()).abs();
+        dx <= reach && dy <= reach
+    }
+}
+
+impl<T> FBBeacon<T> {
+    /// Supply area distance of vanilla beacons, used for the prototypes which do not specify one
+    pub const SUPPLY_AREA_DISTANCE: i32 = 3;
+}
+
+/// Loader entity, moving items between a container and a belt
+///
+/// Loaders are the sources and sinks of test benches: the container they empty or fill is not modelled.
+/// A loader occupying two tiles is placed on the tile facing its belt.
+#[derive(Debug, Clone)]
+pub struct FBLoader<T> {
+    pub base: FBBaseEntity<T>,
+    /// `Input` if the loader takes the items from the belt, `Output` if it puts them on the belt
+    pub belt_type: BeltType,
+    /// `true` for loaders occupying a single tile
+    pub compact: bool,
+}
+
+/// Container entity, like a chest, exchanging items with the inserters around it
+///
+/// The throughput of the base entity is unused, a container exchanges as many items as the inserters can move.
+#[derive(Debug, Clone)]
+pub struct FBContainer<T> {
+    pub base: FBBaseEntity<T>,
+    /// `true` for infinity chests, which both create and void items
+    pub infinite: bool,
+}
@@ -0,0 +1,391 @@
+//! Utility functions to convert a list of `FBEntity`s back into a Factorio blueprint string.
+//! This is the inverse of the functions found in [`crate::import`].
+
+use anyhow::{Context, Result};
+use base64::engine::{general_purpose, Engine as _};
+use deflate::deflate_bytes_zlib;
+use serde_json::{json, Map, Value};
+use std::fs;
+
+use crate::{
+    entities::{BeltType, FBEntity, Priority},
+    import::GameVersion,
+    utils::{Direction, Position},
+};
+
+/// Inventory of the modules of assembling machines in Factorio 2.0 blueprints
+const ASSEMBLER_MODULES: u32 = 4;
+/// Inventory of the modules of beacons in Factorio 2.0 blueprints
+const BEACON_MODULES: u32 = 1;
+
+/// Returns the Factorio name of an entity, the name of its prototype.
+///
+/// Phantoms are not part of the blueprint, so `None` is returned for them.
+fn entity_name(entity: &FBEntity<i32>) -> Option<&str> {
+    match entity {
+        FBEntity::SplitterPhantom(_) | FBEntity::AssemblerPhantom(_) => None,
+        _ => Some(&entity.get_base().name),
+    }
+}
+
+/// Returns the `items` field listing the `modules` of a machine, in the format of the game `version`.
+///
+/// Factorio 1.1 maps the name of each module to its count, whereas 2.0 lists the slots of the
+/// `inventory` filled by each module.
+fn export_modules(modules: &[String], inventory: u32, version: GameVersion) -> Value {
+    match version {
+        GameVersion::V1 => {
+            let mut counts = Map::new();
+            for module in modules {
+                let count = counts.get(module).and_then(Value::as_u64).unwrap_or(0);
+                counts.insert(module.clone(), json!(count + 1));
+            }
+            Value::Object(counts)
+        }
+        GameVersion::V2 => {
+            let mut items: Vec<(&str, Vec<Value>)> = vec![];
+            for (stack, module) in modules.iter().enumerate() {
+                let slot = json!({"inventory": inventory, "stack": stack});
+                match items.iter_mut().find(|(name, _)| name == module) {
+                    Some((_, slots)) => slots.push(slot),
+                    None => items.push((module, vec![slot])),
+                }
+            }
+            let items = items
+                .into_iter()
+                .map(
+                    |(name, slots)| json!({"id": {"name": name}, "items": {"in_inventory": slots}}),
+                )
+                .collect();
+            Value::Array(items)
+        }
+    }
+}
+
+/// Returns the position of the entity in the coordinate system of Factorio blueprints.
+///
+/// Undoes the inversion of the y-axis and, for splitters and loaders, the snapping to the grid.
+fn export_position(entity: &FBEntity<i32>) -> Position<f64> {
+    let position = entity.get_base().position;
+    /* splitters are centered between the two tiles they occupy */
+    let (x, y) = match entity {
+        FBEntity::Splitter(s) => {
+            let phantom = s.get_phantom().base.position;
+            (
+                (position.x + phantom.x) as f64 / 2.0,
+                (position.y + phantom.y) as f64 / 2.0,
+            )
+        }
+        /* loaders are centered between their belt and their container side */
+        FBEntity::Loader(l) if !l.compact => {
+            let dir = match l.belt_type {
+                BeltType::Input => l.base.direction,
+                BeltType::Output => l.base.direction.flip(),
+            };
+            let position = Position {
+                x: position.x as f64,
+                y: position.y as f64,
+            };
+            let center = position.shift(dir, 0.5);
+            (center.x, center.y)
+        }
+        _ => (position.x as f64, position.y as f64),
+    };
+    /* in Factorio blueprints the y-axis is inverted */
+    Position {
+        x: x + 0.5,
+        y: 0.5 - y,
+    }
+}
+
+/// Returns the direction of the entity as found in a Factorio blueprint.
+fn export_direction(entity: &FBEntity<i32>) -> Direction {
+    let direction = entity.get_base().direction;
+    match entity {
+        /* inserters are flipped when importing */
+        FBEntity::Inserter(_) | FBEntity::LongInserter(_) => direction.flip(),
+        _ => direction,
+    }
+}
+
+/// Returns the oldest game version supporting all the entities.
+///
+/// Turbo belts only exist in Factorio 2.0, any other blueprint is exported in the 1.1 format.
+fn export_version(entities: &[FBEntity<i32>]) -> GameVersion {
+    let has_turbo = entities.iter().any(|e| {
+        matches!(
+            e,
+            FBEntity::Belt(_)
+                | FBEntity::Underground(_)
+                | FBEntity::Splitter(_)
+                | FBEntity::Loader(_)
+        ) && e.get_base().throughput >= 60.0
+    });
+    if has_turbo {
+        GameVersion::V2
+    } else {
+        GameVersion::V1
+    }
+}
+
+/// Converts a single entity to its JSON representation.
+fn entity_to_json(entity: &FBEntity<i32>, version: GameVersion) -> Option<Value> {
+    let name = entity_name(entity)?;
+    let position = export_position(entity);
+    let direction = export_direction(entity);
+
+    let mut json = Map::new();
+    json.insert("entity_number".into(), json!(entity.get_base().id));
+    json.insert("name".into(), json!(name));
+    json.insert("position".into(), json!({"x": position.x, "y": position.y}));
+    /* the default direction is omitted in blueprints */
+    if direction != Direction::North {
+        let direction = version.encode_direction(direction);
+        json.insert("direction".into(), json!(direction));
+    }
+    match entity {
+        FBEntity::Underground(u) => {
+            json.insert("type".into(), json!(u.belt_type));
+        }
+        FBEntity::Loader(l) => {
+            json.insert("type".into(), json!(l.belt_type));
+        }
+        FBEntity::Splitter(s) => {
+            if s.input_prio != Priority::None {
+                json.insert("input_priority".into(), json!(s.input_prio));
+            }
+            if s.output_prio != Priority::None {
+                json.insert("output_priority".into(), json!(s.output_prio));
+            }
+        }
+        FBEntity::Assembler(a) => {
+            if let Some(recipe) = &a.recipe {
+                json.insert("recipe".into(), json!(recipe));
+            }
+            if !a.modules.is_empty() {
+                let items = export_modules(&a.modules, ASSEMBLER_MODULES, version);
+                json.insert("items".into(), items);
+            }
+        }
+        FBEntity::Beacon(b) if !b.modules.is_empty() => {
+            let items = export_modules(&b.modules, BEACON_MODULES, version);
+            json.insert("items".into(), items);
+        }
+        _ => (),
+    }
+    Some(Value::Object(json))
+}
+
+/// Compresses the JSON representation of a blueprint to a blueprint string.
+fn compress_json(json: &Value) -> Result<String> {
+    let serialized = serde_json::to_vec(json).context("Could not serialize blueprint")?;
+    let compressed = deflate_bytes_zlib(&serialized);
+    let encoded = general_purpose::STANDARD.encode(compressed);
+    /* the first byte is the version of the blueprint string */
+    Ok(format!("0{}", encoded))
+}
+
+/// Converts a list of `FBEntity`s to a blueprint string that can be imported in Factorio.
+///
+/// Phantoms are skipped, as they are added back when importing the blueprint string.
+/// Entities are exported with the name of their prototype, machines with their modules.
+/// Blueprints containing turbo belts are exported in the Factorio 2.0 format.
+pub fn entities_to_string(entities: &[FBEntity<i32>]) -> Result<String> {
+    let version = export_version(entities);
+    let json_entities = entities
+        .iter()
+        .filter_map(|e| entity_to_json(e, version))
+        .collect::<Vec<_>>();
+    let json = json!({
+        "blueprint": {
+            "icons": [{"signal": {"type": "item", "name": "transport-belt"}, "index": 1}],
+            "entities": json_entities,
+            "item": "blueprint",
+            "version": version.blueprint_version(),
+        }
+    });
+    compress_json(&json)
+}
+
+/// Writes a list of `FBEntity`s as a blueprint string to a file.
+pub fn entities_to_file(entities: &[FBEntity<i32>], file: &str) -> Result<()> {
+    let blueprint_string = entities_to_string(entities)?;
+    fs::write(file, blueprint_string)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{FBBaseEntity, FBContainer, FBInserter},
+        frontend::Compiler,
+        import::{
+            file_to_entities, string_to_entities, string_to_entities_with_options, ImportOptions,
+        },
+        inserters::InserterModel,
+        prototypes::PrototypeRegistry,
+    };
+
+    fn sorted_debug(entities: &[FBEntity<i32>]) -> Vec<String> {
+        let mut debug = entities
+            .iter()
+            .map(|e| format!("{:?}", e))
+            .collect::<Vec<_>>();
+        debug.sort();
+        debug
+    }
+
+    fn round_trip(file: &str) {
+        let entities = file_to_entities(file).unwrap();
+        let blueprint_string = entities_to_string(&entities).unwrap();
+        let imported = string_to_entities(&blueprint_string).unwrap();
+        assert_eq!(sorted_debug(&entities), sorted_debug(&imported));
+    }
+
+    fn round_trip_with_options(file: &str, options: &ImportOptions) -> Vec<FBEntity<i32>> {
+        let blueprint_string = fs::read_to_string(file).unwrap();
+        let (entities, _) = string_to_entities_with_options(&blueprint_string, options).unwrap();
+        let exported = entities_to_string(&entities).unwrap();
+        let (imported, report) = string_to_entities_with_options(&exported, options).unwrap();
+        assert!(report.is_empty());
+        assert_eq!(sorted_debug(&entities), sorted_debug(&imported));
+        imported
+    }
+
+    #[test]
+    fn round_trip_belts() {
+        round_trip("tests/belts");
+    }
+
+    #[test]
+    fn round_trip_balancer() {
+        round_trip("tests/3-2");
+        round_trip("tests/4-4-univ");
+    }
+
+    #[test]
+    fn round_trip_inserters() {
+        round_trip("tests/inserter_assembler");
+        round_trip("tests/feeds_from");
+        round_trip("tests/gear_column");
+    }
+
+    #[test]
+    fn round_trip_modded_belts() {
+        let mut options = ImportOptions::default();
+        let modded = PrototypeRegistry::from_file("tests/modded_prototypes.json").unwrap();
+        options.registry.extend(modded);
+        let entities = round_trip_with_options("tests/modded_belts", &options);
+        assert_eq!(entities.len(), 6);
+        /* modded names are kept instead of being guessed from the throughput */
+        assert!(entities
+            .iter()
+            .all(|e| options.registry.get(&e.get_base().name).is_some()));
+
+        /* undergrounds are paired by prototype, so they have to keep their name */
+        let position = |id| {
+            entities
+                .iter()
+                .find(|e| e.get_base().id == id)
+                .unwrap()
+                .get_base()
+                .position
+        };
+        let (entrance, exit) = (position(2), position(3));
+        let ctx = Compiler::with_registry(entities.clone(), &options.registry);
+        assert!(ctx.feeds_from[&exit].contains(&entrance));
+    }
+
+    #[test]
+    fn round_trip_inserter_model() {
+        let base = |id, y, name: &str| FBBaseEntity {
+            id,
+            position: Position { x: 2, y },
+            direction: Direction::North,
+            throughput: 0.83,
+            name: name.to_owned(),
+        };
+        let chest = |id, y| {
+            FBEntity::Container(FBContainer {
+                base: base(id, y, "wooden-chest"),
+                infinite: false,
+            })
+        };
+        let inserter = FBEntity::Inserter(FBInserter {
+            base: base(2, 3, "inserter"),
+        });
+        let blueprint_string = entities_to_string(&[chest(1, 2), inserter, chest(3, 4)]).unwrap();
+
+        let mut model = InserterModel::default();
+        model.capacity_bonus = 7;
+        let options = ImportOptions {
+            inserters: Some(model),
+            ..Default::default()
+        };
+        let inserter = |entities: &[FBEntity<i32>]| {
+            entities
+                .iter()
+                .find_map(|e| match e {
+                    FBEntity::Inserter(i) => Some(i.base.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let (entities, _) = string_to_entities_with_options(&blueprint_string, &options).unwrap();
+        assert!(inserter(&entities).throughput > 0.83);
+
+        /* the faster inserter keeps its name instead of being exported as a faster prototype */
+        let exported = entities_to_string(&entities).unwrap();
+        let imported = inserter(&string_to_entities(&exported).unwrap());
+        assert_eq!(imported.name, "inserter");
+        assert_eq!(imported.throughput, 0.83);
+    }
+
+    #[test]
+    fn round_trip_modules() {
+        let entities = round_trip_with_options("tests/moduled_column", &ImportOptions::default());
+        let modules = entities
+            .iter()
+            .filter_map(|e| match e {
+                FBEntity::Assembler(a) => Some(a.modules.len()),
+                FBEntity::Beacon(b) => Some(b.modules.len()),
+                _ => None,
+            })
+            .sum::<usize>();
+        assert!(modules > 0);
+
+        /* 2.0 blueprints list the slots of each module */
+        let entities = file_to_entities("tests/moduled_column").unwrap();
+        for e in &entities {
+            let (modules, inventory) = match e {
+                FBEntity::Assembler(a) => (&a.modules, ASSEMBLER_MODULES),
+                FBEntity::Beacon(b) => (&b.modules, BEACON_MODULES),
+                _ => continue,
+            };
+            let items = export_modules(modules, inventory, GameVersion::V2);
+            let slots = items
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["items"]["in_inventory"].as_array().unwrap().len())
+                .sum::<usize>();
+            assert_eq!(slots, modules.len());
+        }
+    }
+
+    #[test]
+    fn round_trip_test_bench() {
+        round_trip("tests/test_bench");
+    }
+
+    #[test]
+    fn round_trip_space_age() {
+        round_trip("tests/space_age");
+
+        let entities = file_to_entities("tests/space_age").unwrap();
+        assert_eq!(export_version(&entities), GameVersion::V2);
+        let entities = file_to_entities("tests/belts").unwrap();
+        assert_eq!(export_version(&entities), GameVersion::V1);
+    }
+}
@@ -1,10 +1,14 @@
 use fraction::GenericFraction;
 use petgraph::prelude::NodeIndex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::{
-    entities::{FBBelt, FBEntity, FBSplitter, FBUnderground},
-    ir::{self, Connector, Edge, FlowGraph, Node},
+    entities::{
+        FBAssembler, FBBelt, FBContainer, FBEntity, FBInserter, FBLoader, FBLongInserter,
+        FBSplitter, FBUnderground,
+    },
+    ir::{self, Connector, Craft, Edge, FlowGraph, Node, Output},
+    recipes::Recipe,
     utils::{Position, Side},
 };
 
@@ -31,6 +35,7 @@ fn add_belt_to_graph(
     let edge = Edge {
         side: Side::None,
         capacity,
+        entities: vec![id],
     };
 
     graph.add_edge(in_idx, out_idx, edge);
@@ -50,7 +55,7 @@ impl AddToGraph for FBBelt<i32> {
         graph: &mut FlowGraph,
         pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
     ) {
-        add_belt_to_graph(&FBEntity::Belt(*self), graph, pos_to_connector)
+        add_belt_to_graph(&FBEntity::Belt(self.clone()), graph, pos_to_connector)
     }
 }
 
@@ -60,7 +65,186 @@ impl AddToGraph for FBUnderground<i32> {
         graph: &mut FlowGraph,
         pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
     ) {
-        add_belt_to_graph(&FBEntity::Underground(*self), graph, pos_to_connector)
+        add_belt_to_graph(
+            &FBEntity::Underground(self.clone()),
+            graph,
+            pos_to_connector,
+        )
+    }
+}
+
+/* inserters are modelled like belts, as a single edge limited by their throughput */
+impl AddToGraph for FBInserter<i32> {
+    fn add_to_graph(
+        &self,
+        graph: &mut FlowGraph,
+        pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
+    ) {
+        add_belt_to_graph(&FBEntity::Inserter(self.clone()), graph, pos_to_connector)
+    }
+}
+
+impl AddToGraph for FBLongInserter<i32> {
+    fn add_to_graph(
+        &self,
+        graph: &mut FlowGraph,
+        pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
+    ) {
+        add_belt_to_graph(
+            &FBEntity::LongInserter(self.clone()),
+            graph,
+            pos_to_connector,
+        )
+    }
+}
+
+/* loaders are belts whose ends become inputs or outputs */
+impl AddToGraph for FBLoader<i32> {
+    fn add_to_graph(
+        &self,
+        graph: &mut FlowGraph,
+        pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
+    ) {
+        add_belt_to_graph(&FBEntity::Loader(self.clone()), graph, pos_to_connector)
+    }
+}
+
+/// Adds a container to the graph, limited by the `capacity` of the inserters around it.
+///
+/// A container that `consumes` and `produces` items buffers them, as a single edge,
+/// except for infinity chests whose sink and source are not connected, like those of assemblers.
+/// The unconnected ends are promoted to the inputs and outputs of the graph.
+pub fn add_container_to_graph(
+    container: &FBContainer<i32>,
+    capacity: GenericFraction<u128>,
+    consumes: bool,
+    produces: bool,
+    graph: &mut FlowGraph,
+    pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
+) {
+    let id = container.base.id;
+    let add_part = |graph: &mut FlowGraph| {
+        let in_idx = graph.add_node(Node::Connector(Connector { id }));
+        let out_idx = graph.add_node(Node::Connector(Connector { id }));
+        let edge = Edge {
+            side: Side::None,
+            capacity,
+            entities: vec![id],
+        };
+        graph.add_edge(in_idx, out_idx, edge);
+        (in_idx, out_idx)
+    };
+    let connectors = match (consumes, produces) {
+        (false, false) => return,
+        (true, true) if container.infinite => (add_part(graph).0, add_part(graph).1),
+        _ => add_part(graph),
+    };
+    pos_to_connector.insert(container.base.position, connectors);
+}
+
+/// Adds the sink and the source of an assembler to the graph, registering them for all of its tiles.
+///
+/// The sink is only added if the assembler `consumes` items, i.e. is fed by inserters,
+/// and the source if it `produces` items, i.e. feeds inserters.
+/// They are not connected, as the ingredients are turned into other items.
+/// The sink consumes one item per second of crafting speed, including the effects of modules,
+/// and the source additionally produces the extra items of productivity.
+pub fn add_assembler_to_graph(
+    assembler: &FBAssembler<i32>,
+    consumes: bool,
+    produces: bool,
+    graph: &mut FlowGraph,
+    pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
+) {
+    let id = assembler.base.id;
+    let speed = assembler.crafting_speed();
+    let add_part = |graph: &mut FlowGraph, capacity: f64| {
+        let in_idx = graph.add_node(Node::Connector(Connector { id }));
+        let out_idx = graph.add_node(Node::Connector(Connector { id }));
+        let edge = Edge {
+            side: Side::None,
+            capacity: capacity.into(),
+            entities: vec![id],
+        };
+        graph.add_edge(in_idx, out_idx, edge);
+        (in_idx, out_idx)
+    };
+    let sink = consumes.then(|| add_part(graph, speed));
+    let productivity = assembler.effects.productivity_multiplier();
+    let source = produces.then(|| add_part(graph, speed * productivity));
+    let connectors = match (sink, source) {
+        (None, None) => return,
+        (Some(sink), None) => sink,
+        (None, Some(source)) => source,
+        (Some(sink), Some(source)) => (sink.0, source.1),
+    };
+    let positions = assembler
+        .get_phantoms()
+        .into_iter()
+        .map(|p| p.base.position)
+        .chain([assembler.base.position]);
+    for pos in positions {
+        pos_to_connector.insert(pos, connectors);
+    }
+}
+
+/// Adds an assembler crafting a known `recipe` as a [`Craft`] node, registering it for all of its tiles.
+///
+/// If no inserter takes its products out, i.e. it does not `produces` items,
+/// they go to an output with the id of the assembler.
+/// Modules speed up the crafts and productivity adds to the products of each craft.
+pub fn add_craft_to_graph(
+    assembler: &FBAssembler<i32>,
+    name: &str,
+    recipe: &Recipe,
+    produces: bool,
+    graph: &mut FlowGraph,
+    pos_to_connector: &mut HashMap<Position<i32>, (NodeIndex, NodeIndex)>,
+) {
+    let id = assembler.base.id;
+    let amounts = |items: &BTreeMap<String, f64>, multiplier: f64| {
+        items
+            .iter()
+            .map(|(item, amount)| (item.clone(), GenericFraction::from(amount * multiplier)))
+            .collect::<Vec<_>>()
+    };
+    let craft = Craft {
+        id,
+        recipe: name.to_owned(),
+        max_crafts: GenericFraction::from(assembler.crafting_speed())
+            / GenericFraction::from(recipe.crafting_time),
+        ingredients: amounts(&recipe.ingredients, 1.0),
+        products: amounts(
+            &recipe.products,
+            assembler.effects.productivity_multiplier(),
+        ),
+    };
+    let per_craft = craft
+        .products
+        .iter()
+        .fold(GenericFraction::from(0), |sum, (_, amount)| sum + *amount);
+    let capacity = craft.max_crafts * per_craft;
+    let craft_idx = graph.add_node(Node::Craft(craft));
+    if !produces {
+        let output = Node::Output(Output {
+            id,
+            lane: Side::None,
+        });
+        let out_idx = graph.add_node(output);
+        let edge = Edge {
+            side: Side::None,
+            capacity,
+            entities: vec![id],
+        };
+        graph.add_edge(craft_idx, out_idx, edge);
+    }
+    let positions = assembler
+        .get_phantoms()
+        .into_iter()
+        .map(|p| p.base.position)
+        .chain([assembler.base.position]);
+    for pos in positions {
+        pos_to_connector.insert(pos, (craft_idx, craft_idx));
     }
 }
 
@@ -106,18 +290,21 @@ impl AddToGraph for FBSplitter<i32> {
         let merger_splitter_edge = Edge {
             side: Side::None,
             capacity: capacity * GenericFraction::new(2u128, 1u128),
+            entities: vec![id],
         };
         let r_edge = Edge {
             side: Side::Right,
             capacity,
+            entities: vec![],
         };
         let l_edge = Edge {
             side: Side::Left,
             capacity,
+            entities: vec![],
         };
 
-        graph.add_edge(in_l_idx, merger_idx, l_edge);
-        graph.add_edge(in_r_idx, merger_idx, r_edge);
+        graph.add_edge(in_l_idx, merger_idx, l_edge.clone());
+        graph.add_edge(in_r_idx, merger_idx, r_edge.clone());
 
         graph.add_edge(splitter_idx, out_l_idx, l_edge);
         graph.add_edge(splitter_idx, out_r_idx, r_edge);
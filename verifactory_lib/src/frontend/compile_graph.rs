@@ -1,4 +1,8 @@
-use petgraph::Direction::{Incoming, Outgoing};
+use fraction::GenericFraction;
+use petgraph::{
+    prelude::NodeIndex,
+    Direction::{Incoming, Outgoing},
+};
 use relations::Relation;
 use std::{
     collections::{HashMap, HashSet},
@@ -8,12 +12,19 @@ use std::{
 };
 
 use crate::{
-    entities::{BeltType, EntityId, FBEntity, FBUnderground, InserterTrait},
-    ir::{Edge, FlowGraph, Input, Node, Output},
+    entities::{BeltType, EntityId, FBBaseEntity, FBEntity, FBUnderground, InserterTrait},
+    ir::{self, Edge, FlowGraph, Input, Node, Output},
+    prototypes::PrototypeRegistry,
+    recipes::RecipeDatabase,
     utils::{Direction, Position, Side},
 };
 
-use super::compile_entities::AddToGraph;
+use super::{
+    compile_entities::{
+        add_assembler_to_graph, add_container_to_graph, add_craft_to_graph, AddToGraph,
+    },
+    compile_lanes::{add_lane_feeds, classify_feeds, AddLanesToGraph, Lane, UnsupportedEntity},
+};
 
 trait RelationMap<T>
 where
@@ -21,7 +32,6 @@ where
 {
     fn transpose(self) -> Self;
     fn to_relation(&self) -> Relation<T>;
-    fn get_cardinality(&self, key: &T) -> i32;
     fn add(&mut self, key: &T, value: T) -> bool;
 }
 
@@ -41,10 +51,6 @@ where
         Relation::from_iter(iter_relation)
     }
 
-    fn get_cardinality(&self, key: &T) -> i32 {
-        self.get(key).map(|s| s.len()).unwrap_or(0) as i32
-    }
-
     fn add(&mut self, key: &T, value: T) -> bool {
         match self.get_mut(key) {
             None => {
@@ -60,24 +66,26 @@ where
 
 pub type RelMap<T> = HashMap<T, HashSet<T>>;
 
+/// Link from the tile of an entity to the tile of an entity it feeds
+struct Link {
+    source: Position<i32>,
+    dest: Position<i32>,
+    /// Out-node of the source
+    source_idx: NodeIndex,
+    /// Node the link ends in, the in-node of the destination or a merger in front of it
+    target_idx: NodeIndex,
+}
+
 /* XXX: do we really need the entities vector?
  * => remove Rc, get entities with pos_to_entity.values() */
 pub struct Compiler {
     entities: Vec<Rc<FBEntity<i32>>>,
-    positions: HashSet<Position<i32>>,
     belt_positions: HashSet<Position<i32>>,
-    inserter_positions: HashSet<Position<i32>>,
     feeds_to: RelMap<Position<i32>>,
     pub feeds_from: RelMap<Position<i32>>,
     pos_to_entity: HashMap<Position<i32>, Rc<FBEntity<i32>>>,
 }
 
-struct PostionSets {
-    positions: HashSet<Position<i32>>,
-    belt_positions: HashSet<Position<i32>>,
-    inserter_positions: HashSet<Position<i32>>,
-}
-
 impl Compiler {
     fn generate_pos_to_entity(
         entities: &Vec<Rc<FBEntity<i32>>>,
@@ -89,31 +97,26 @@ impl Compiler {
             .collect::<HashMap<_, _>>();
 
         for e in entities {
-            if let FBEntity::Splitter(s) = **e {
+            if let FBEntity::Splitter(s) = &**e {
                 pos_to_entity.insert(s.get_phantom().base.position, e.clone());
             }
         }
         pos_to_entity
     }
 
-    fn generate_position_sets(
+    fn generate_belt_positions(
         pos_to_entity: &HashMap<Position<i32>, Rc<FBEntity<i32>>>,
-    ) -> PostionSets {
-        let positions = pos_to_entity.keys().cloned().collect::<HashSet<_>>();
-
-        let belt_positions = pos_to_entity
+    ) -> HashSet<Position<i32>> {
+        pos_to_entity
             .iter()
             .filter_map(|(k, v)| match **v {
-                FBEntity::Belt(_) | FBEntity::Underground(_) | FBEntity::Splitter(_) => Some(*k),
+                FBEntity::Belt(_)
+                | FBEntity::Underground(_)
+                | FBEntity::Splitter(_)
+                | FBEntity::Loader(_) => Some(*k),
                 _ => None,
             })
-            .collect();
-        let inserter_positions = positions.difference(&belt_positions).cloned().collect();
-        PostionSets {
-            positions,
-            belt_positions,
-            inserter_positions,
-        }
+            .collect()
     }
 
     /// Creates a relation of positions that feed other positions
@@ -128,9 +131,12 @@ impl Compiler {
     ///       |__/
     /// This only generates the following relation: {A->C, B->D}.
     /// To perform reachability analysis one would need to also include A->D and B->C.
+    ///
+    /// Inserters are part of the relation, they are fed by the tile they pick up from and feed the tile they drop to.
     pub fn populate_feeds_to(
         pos_to_entity: &HashMap<Position<i32>, Rc<FBEntity<i32>>>,
         entities: &Vec<Rc<FBEntity<i32>>>,
+        registry: &PrototypeRegistry,
     ) -> RelMap<Position<i32>> {
         let mut feeds_to = HashMap::new();
 
@@ -142,17 +148,20 @@ impl Compiler {
         ) {
             let dest = pos.shift(dir, 1);
             if let Some(e) = pos_to_entity.get(&dest) {
-                match **e {
+                match &**e {
                     FBEntity::Belt(_) | FBEntity::Underground(_) | FBEntity::Splitter(_) => {
                         feeds_to.add(&pos, pos.shift(dir, 1));
                     }
+                    FBEntity::Loader(l) if l.belt_type == BeltType::Input => {
+                        feeds_to.add(&pos, pos.shift(dir, 1));
+                    }
                     _ => (),
                 }
             }
         }
 
         let output_undergrounds = entities.iter().filter_map(|e| match **e {
-            FBEntity::Underground(x) if x.belt_type == BeltType::Output => Some(e.clone()),
+            FBEntity::Underground(ref x) if x.belt_type == BeltType::Output => Some(e.clone()),
             _ => None,
         });
 
@@ -160,11 +169,11 @@ impl Compiler {
             let base = e.get_base();
             let dir = base.direction;
             let pos = base.position;
-            match **e {
+            match &**e {
                 FBEntity::Belt(_) => add_feeds_to(&mut feeds_to, pos_to_entity, pos, dir),
                 FBEntity::Underground(u) if u.belt_type == BeltType::Input => {
                     if let Some(output_pos) =
-                        find_underground_output(&u, output_undergrounds.clone())
+                        find_underground_output(u, output_undergrounds.clone(), registry)
                     {
                         feeds_to.add(&pos, output_pos);
                     }
@@ -174,29 +183,40 @@ impl Compiler {
                 FBEntity::SplitterPhantom(_) => {
                     add_feeds_to(&mut feeds_to, pos_to_entity, pos, dir)
                 }
+                /* inserters are fed by their source and feed their destination */
                 FBEntity::Inserter(l) => {
-                    let source = l.get_source();
-                    let destination = l.get_destination();
-                    feeds_to.add(&source, destination);
+                    feeds_to.add(&l.get_source(), pos);
+                    feeds_to.add(&pos, l.get_destination());
                 }
                 FBEntity::LongInserter(l) => {
-                    let source = l.get_source();
-                    let destination = l.get_destination();
-                    feeds_to.add(&source, destination);
+                    feeds_to.add(&l.get_source(), pos);
+                    feeds_to.add(&pos, l.get_destination());
+                }
+                FBEntity::Loader(l) if l.belt_type == BeltType::Output => {
+                    add_feeds_to(&mut feeds_to, pos_to_entity, pos, dir)
                 }
-                FBEntity::Assembler(_) => todo!(),
-                FBEntity::AssemblerPhantom(_) => todo!(),
+                /* loaders taking items from a belt are sinks */
+                FBEntity::Loader(_) => (),
+                /* assemblers and containers only exchange items through inserters */
+                FBEntity::Assembler(_) | FBEntity::AssemblerPhantom(_) => (),
+                FBEntity::Container(_) => (),
+                /* beacons only affect the speed of assemblers */
+                FBEntity::Beacon(_) => (),
             };
         }
-        /* validate that noting feeds into an output underground except for an input underground */
+        /* validate that noting feeds into an output underground except for an input underground,
+         * or a belt side-loading it */
         for (source, set) in feeds_to.iter_mut() {
             set.retain(|dest| {
                 let source_entity = pos_to_entity.get(source);
                 let dest_entity = pos_to_entity.get(dest);
                 if let (Some(source), Some(dest)) = (source_entity, dest_entity) {
-                    let dest_is_output = matches!(**dest, FBEntity::Underground(x) if x.belt_type == BeltType::Output);
-                    let source_is_input = matches!(**source, FBEntity::Underground(x) if x.belt_type == BeltType::Input);
-                    return !dest_is_output || source_is_input;
+                    let dest_is_output = matches!(**dest, FBEntity::Underground(ref x) if x.belt_type == BeltType::Output);
+                    let source_is_input = matches!(**source, FBEntity::Underground(ref x) if x.belt_type == BeltType::Input);
+                    let (source_dir, dest_dir) =
+                        (source.get_base().direction, dest.get_base().direction);
+                    let from_side = source_dir != dest_dir && source_dir != dest_dir.flip();
+                    return !dest_is_output || source_is_input || from_side;
                 }
                 true
             });
@@ -209,29 +229,30 @@ impl Compiler {
     pub fn populate_feeds_from(
         pos_to_entity: &HashMap<Position<i32>, Rc<FBEntity<i32>>>,
         entities: &Vec<Rc<FBEntity<i32>>>,
+        registry: &PrototypeRegistry,
     ) -> RelMap<Position<i32>> {
-        Self::populate_feeds_to(pos_to_entity, entities).transpose()
+        Self::populate_feeds_to(pos_to_entity, entities, registry).transpose()
     }
 }
 
 impl Compiler {
+    /// Creates a compiler for blueprints containing only vanilla entities.
     pub fn new(entities: Vec<FBEntity<i32>>) -> Self {
+        Self::with_registry(entities, &PrototypeRegistry::default())
+    }
+
+    /// Creates a compiler using the `registry` to look up the reach of underground belts.
+    pub fn with_registry(entities: Vec<FBEntity<i32>>, registry: &PrototypeRegistry) -> Self {
         let entities: Vec<_> = entities.into_iter().map(Rc::new).collect();
         let pos_to_entity = Self::generate_pos_to_entity(&entities);
 
-        let PostionSets {
-            positions,
-            belt_positions,
-            inserter_positions,
-        } = Self::generate_position_sets(&pos_to_entity);
-        let feeds_to = Self::populate_feeds_to(&pos_to_entity, &entities);
-        let feeds_from = Self::populate_feeds_from(&pos_to_entity, &entities);
+        let belt_positions = Self::generate_belt_positions(&pos_to_entity);
+        let feeds_to = Self::populate_feeds_to(&pos_to_entity, &entities, registry);
+        let feeds_from = Self::populate_feeds_from(&pos_to_entity, &entities, registry);
 
         Self {
             entities,
-            positions,
             belt_positions,
-            inserter_positions,
             feeds_to,
             feeds_from,
             pos_to_entity,
@@ -259,7 +280,7 @@ impl Compiler {
         let mut feeds_to = self.feeds_to.clone();
 
         for e in &self.entities {
-            if let FBEntity::Splitter(s) = **e {
+            if let FBEntity::Splitter(s) = &**e {
                 let base = e.get_base();
                 let pos = base.position;
                 let dir = base.direction;
@@ -279,29 +300,116 @@ impl Compiler {
 }
 
 impl Compiler {
+    /// Returns the positions of the inputs of the blueprint.
+    ///
+    /// If the blueprint declares inputs with loaders and containers, see [`Compiler::declared_io`],
+    /// these are its inputs. Otherwise the inputs are the belts not fed by any other entity.
     pub fn find_input_positions(&self) -> Vec<Position<i32>> {
+        let (inputs, _) = self.declared_io();
+        if !inputs.is_empty() {
+            return inputs;
+        }
         self.belt_positions
             .iter()
-            .filter(|k| self.feeds_from.get(k).is_none())
+            .filter(|k| !self.feeds_from.contains_key(k))
             .cloned()
             .collect()
     }
 
+    /// Returns the positions of the outputs of the blueprint, see [`Compiler::find_input_positions`].
     pub fn find_output_positions(&self) -> Vec<Position<i32>> {
+        let (_, outputs) = self.declared_io();
+        if !outputs.is_empty() {
+            return outputs;
+        }
         self.belt_positions
             .iter()
-            .filter(|k| self.feeds_to.get(k).is_none())
+            .filter(|k| !self.feeds_to.contains_key(k))
             .cloned()
             .collect()
     }
 
+    /// Returns the positions of the inputs and of the outputs declared by loaders and containers.
+    ///
+    /// Loaders putting items on a belt are inputs, and those taking items from a belt are outputs.
+    /// Containers are inputs if inserters only take items out of them and outputs if inserters only put items in,
+    /// infinity chests can be both. Containers both filled and emptied by inserters are buffers.
+    pub fn declared_io(&self) -> (Vec<Position<i32>>, Vec<Position<i32>>) {
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+        for (pos, e) in &self.pos_to_entity {
+            let feeds = self.feeds_to.contains_key(pos);
+            let fed = self.feeds_from.contains_key(pos);
+            let (is_input, is_output) = match &**e {
+                FBEntity::Loader(l) => (
+                    l.belt_type == BeltType::Output,
+                    l.belt_type == BeltType::Input,
+                ),
+                FBEntity::Container(c) if c.infinite => (feeds, fed),
+                FBEntity::Container(_) => (feeds && !fed, fed && !feeds),
+                _ => continue,
+            };
+            if is_input {
+                inputs.push(*pos);
+            }
+            if is_output {
+                outputs.push(*pos);
+            }
+        }
+        (inputs, outputs)
+    }
+
+    /// Returns `true` if an inserter is at the position.
+    fn is_inserter(&self, pos: &Position<i32>) -> bool {
+        self.pos_to_entity
+            .get(pos)
+            .is_some_and(|e| matches!(**e, FBEntity::Inserter(_) | FBEntity::LongInserter(_)))
+    }
+
+    /// Returns the throughput of the entity at the position.
+    ///
+    /// Containers exchange as many items as the inserters around them can move.
+    fn throughput(&self, pos: &Position<i32>) -> GenericFraction<u128> {
+        match *self.pos_to_entity[pos] {
+            FBEntity::Container(_) => {
+                let sources = self.feeds_from.get(pos).into_iter().flatten();
+                let dests = self.feeds_to.get(pos).into_iter().flatten();
+                sources
+                    .chain(dests)
+                    .filter(|p| self.is_inserter(p))
+                    .map(|p| self.throughput(p))
+                    .fold(GenericFraction::from(0), |sum, t| sum + t)
+            }
+            _ => self.pos_to_entity[pos].get_base().throughput.into(),
+        }
+    }
+
+    /// Creates the graph of the blueprint.
+    ///
+    /// Inserters are edges limited by their throughput. An inserter picking up from a belt has priority over
+    /// the rest of the belt and an inserter dropping onto a belt is merged with the other feeds of the belt.
+    /// Assemblers are sinks and sources consuming and producing one item per second of crafting speed.
+    /// If the blueprint declares inputs or outputs with loaders and containers, only these become
+    /// the inputs or outputs of the graph, see [`Compiler::declared_io`].
     pub fn create_graph(&self) -> FlowGraph {
+        self.build_graph(None)
+    }
+
+    /// Creates the graph of the blueprint, turning assemblers with a recipe found in `recipes` into
+    /// [`ir::Craft`] nodes.
+    ///
+    /// The other entities are compiled as in [`Compiler::create_graph`].
+    pub fn create_production_graph(&self, recipes: &RecipeDatabase) -> FlowGraph {
+        self.build_graph(Some(recipes))
+    }
+
+    fn build_graph(&self, recipes: Option<&RecipeDatabase>) -> FlowGraph {
         let mut graph = petgraph::Graph::new();
 
         let mut pos_to_connector = HashMap::new();
 
         for e in &self.entities {
-            match **e {
+            match &**e {
                 FBEntity::Splitter(splitter) => {
                     splitter.add_to_graph(&mut graph, &mut pos_to_connector)
                 }
@@ -309,22 +417,83 @@ impl Compiler {
                 FBEntity::Underground(under) => {
                     under.add_to_graph(&mut graph, &mut pos_to_connector)
                 }
+                FBEntity::Inserter(inserter) => {
+                    inserter.add_to_graph(&mut graph, &mut pos_to_connector)
+                }
+                FBEntity::LongInserter(inserter) => {
+                    inserter.add_to_graph(&mut graph, &mut pos_to_connector)
+                }
+                FBEntity::Loader(loader) => loader.add_to_graph(&mut graph, &mut pos_to_connector),
+                FBEntity::Container(container) => {
+                    let pos = container.base.position;
+                    add_container_to_graph(
+                        container,
+                        self.throughput(&pos),
+                        self.feeds_from.contains_key(&pos),
+                        self.feeds_to.contains_key(&pos),
+                        &mut graph,
+                        &mut pos_to_connector,
+                    )
+                }
+                FBEntity::Assembler(ref assembler) => {
+                    let tiles = assembler
+                        .get_phantoms()
+                        .into_iter()
+                        .map(|p| p.base.position)
+                        .chain([assembler.base.position])
+                        .collect::<Vec<_>>();
+                    let consumes = tiles.iter().any(|pos| self.feeds_from.contains_key(pos));
+                    let produces = tiles.iter().any(|pos| self.feeds_to.contains_key(pos));
+                    let recipe = recipes
+                        .zip(assembler.recipe.as_deref())
+                        .and_then(|(recipes, name)| Some((name, recipes.get(name)?)));
+                    match recipe {
+                        Some((name, recipe)) => add_craft_to_graph(
+                            assembler,
+                            name,
+                            recipe,
+                            produces,
+                            &mut graph,
+                            &mut pos_to_connector,
+                        ),
+                        None => add_assembler_to_graph(
+                            assembler,
+                            consumes,
+                            produces,
+                            &mut graph,
+                            &mut pos_to_connector,
+                        ),
+                    }
+                }
                 _ => (),
             }
         }
+        let mut links = vec![];
         for (source, set) in &self.feeds_to {
             if let Some(source_idx) = pos_to_connector.get(source).map(|i| i.1) {
                 for dest in set {
                     if let Some(dest_idx) = pos_to_connector.get(dest).map(|i| i.0) {
-                        let edge = Edge {
-                            side: Side::None,
-                            capacity: 69.into(),
-                        };
-                        graph.add_edge(source_idx, dest_idx, edge);
+                        links.push(Link {
+                            source: *source,
+                            dest: *dest,
+                            source_idx,
+                            target_idx: dest_idx,
+                        });
                     }
                 }
             }
         }
+        self.add_links(&mut graph, links);
+        /* only the declared inputs, or outputs, are promoted if there are any on that side */
+        let (inputs, outputs) = self.declared_io();
+        let ids = |positions: &[Position<i32>]| {
+            positions
+                .iter()
+                .filter_map(|pos| self.pos_to_id(pos))
+                .collect::<HashSet<_>>()
+        };
+        let (inputs, outputs) = (ids(&inputs), ids(&outputs));
+        let promoted = |declared: &HashSet<_>, id| declared.is_empty() || declared.contains(&id);
         /* promote suitable connectors to input or output nodes */
         for node in graph.node_indices() {
             if let Some(Node::Connector(c)) = graph.node_weight(node) {
@@ -334,12 +503,19 @@ impl Compiler {
 
                 let is_output = out_degree == 0;
                 let is_input = in_degree == 0;
+                let declared = if is_input { &inputs } else { &outputs };
                 /* if the connector is not connected, leave it as is */
-                if is_input ^ is_output {
+                if is_input ^ is_output && promoted(declared, id) {
                     let new_node = if is_input {
-                        Node::Input(Input { id })
+                        Node::Input(Input {
+                            id,
+                            lane: Side::None,
+                        })
                     } else {
-                        Node::Output(Output { id })
+                        Node::Output(Output {
+                            id,
+                            lane: Side::None,
+                        })
                     };
                     let node_ref = graph.node_weight_mut(node).unwrap();
                     *node_ref = new_node;
@@ -348,19 +524,234 @@ impl Compiler {
         }
         graph
     }
+
+    /// Adds the edges linking the entities of the graph.
+    ///
+    /// A link is only limited by its source entity, or by the inserter it feeds.
+    /// Tiles linked to inserters and to more than one other entity are connected through a chain of
+    /// splitters, prioritizing the inserters, or a chain of mergers.
+    fn add_links(&self, graph: &mut FlowGraph, mut links: Vec<Link>) {
+        let edge = |side, capacity| Edge {
+            side,
+            capacity,
+            entities: vec![],
+        };
+        let group = |links: &[Link], key: fn(&Link) -> NodeIndex| {
+            let mut groups: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+            for (i, link) in links.iter().enumerate() {
+                groups.entry(key(link)).or_default().push(i);
+            }
+            groups
+                .into_values()
+                .filter(|group| group.len() > 1)
+                .filter(|group| {
+                    group.iter().any(|i| {
+                        self.is_inserter(&links[*i].source) || self.is_inserter(&links[*i].dest)
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        /* merge the links feeding the same tile */
+        for mut group in group(&links, |link| link.target_idx) {
+            group.sort_by_key(|i| (links[*i].source.x, links[*i].source.y));
+            let dest = links[group[0]].dest;
+            let id = self.pos_to_entity[&dest].get_base().id;
+            let mut target = links[group[0]].target_idx;
+            for &i in &group[1..] {
+                let merger = ir::Merger {
+                    input_priority: Side::None,
+                    id,
+                };
+                let merger_idx = graph.add_node(Node::Merger(merger));
+                graph.add_edge(merger_idx, target, edge(Side::None, self.throughput(&dest)));
+                links[i].target_idx = merger_idx;
+                target = merger_idx;
+            }
+            links[group[0]].target_idx = target;
+        }
+
+        /* split the links leaving the same tile, the inserters first */
+        let split = group(&links, |link| link.source_idx);
+        let mut split_links = HashSet::new();
+        for mut group in split {
+            group.sort_by_key(|i| {
+                let dest = links[*i].dest;
+                (!self.is_inserter(&dest), dest.x, dest.y)
+            });
+            let source = links[group[0]].source;
+            let id = self.pos_to_entity[&source].get_base().id;
+            let mut current = (links[group[0]].source_idx, Side::None);
+            for (n, &i) in group.iter().enumerate() {
+                let link = &links[i];
+                let capacity = self.link_capacity(link);
+                split_links.insert(i);
+                if n == group.len() - 1 {
+                    graph.add_edge(current.0, link.target_idx, edge(current.1, capacity));
+                    break;
+                }
+                let splitter = ir::Splitter {
+                    output_priority: Side::Left,
+                    id,
+                };
+                let splitter_idx = graph.add_node(Node::Splitter(splitter));
+                graph.add_edge(
+                    current.0,
+                    splitter_idx,
+                    edge(current.1, self.throughput(&source)),
+                );
+                graph.add_edge(splitter_idx, link.target_idx, edge(Side::Left, capacity));
+                current = (splitter_idx, Side::Right);
+            }
+        }
+
+        for (i, link) in links.iter().enumerate() {
+            if !split_links.contains(&i) {
+                let capacity = self.link_capacity(link);
+                graph.add_edge(link.source_idx, link.target_idx, edge(Side::None, capacity));
+            }
+        }
+    }
+
+    /// Returns the capacity of a link, the throughput of its source entity or of the inserter it feeds.
+    fn link_capacity(&self, link: &Link) -> GenericFraction<u128> {
+        let capacity = self.throughput(&link.source);
+        if self.is_inserter(&link.dest) {
+            capacity.min(self.throughput(&link.dest))
+        } else {
+            capacity
+        }
+    }
+
+    /// Creates the graph of the blueprint, modelling the left and right lane of each belt separately.
+    ///
+    /// Each lane has half the capacity of its belt and splitters keep the lanes separated.
+    /// Side-loading only feeds a single lane of the destination, belts facing each other head-on are not connected.
+    /// Inputs and outputs are created for belts that are not fed by, or don't feed, any other entity.
+    /// Loaders are compiled like belts, if they declare inputs or outputs only these become the inputs or outputs
+    /// of the graph, see [`Compiler::declared_io`].
+    ///
+    /// Fails on inserters, containers and assemblers, how they pick up items from the lanes is not modelled.
+    pub fn create_lane_graph(&self) -> Result<FlowGraph, UnsupportedEntity> {
+        let unsupported = self.entities.iter().find(|e| {
+            matches!(
+                ***e,
+                FBEntity::Inserter(_)
+                    | FBEntity::LongInserter(_)
+                    | FBEntity::Container(_)
+                    | FBEntity::Assembler(_)
+            )
+        });
+        if let Some(e) = unsupported {
+            let base = e.get_base();
+            return Err(UnsupportedEntity {
+                id: base.id,
+                name: base.name.clone(),
+            });
+        }
+        let mut graph = petgraph::Graph::new();
+
+        let mut pos_to_lanes = HashMap::new();
+
+        for e in &self.entities {
+            match &**e {
+                FBEntity::Splitter(splitter) => {
+                    splitter.add_lanes_to_graph(&mut graph, &mut pos_to_lanes)
+                }
+                FBEntity::Belt(belt) => belt.add_lanes_to_graph(&mut graph, &mut pos_to_lanes),
+                FBEntity::Underground(under) => {
+                    under.add_lanes_to_graph(&mut graph, &mut pos_to_lanes)
+                }
+                FBEntity::Loader(loader) => {
+                    loader.add_lanes_to_graph(&mut graph, &mut pos_to_lanes)
+                }
+                _ => (),
+            }
+        }
+
+        /* collect all the feeds of each lane, as a lane can be fed by multiple entities */
+        let mut lane_feeds: HashMap<NodeIndex, (FBBaseEntity<i32>, Vec<_>)> = HashMap::new();
+        for (dest_pos, dest_lanes) in &pos_to_lanes {
+            let Some(sources) = self.feeds_from.get(dest_pos) else {
+                continue;
+            };
+            let dest = &self.pos_to_entity[dest_pos];
+            let sources = sources
+                .iter()
+                .filter(|pos| pos_to_lanes.contains_key(pos))
+                .map(|pos| (*pos, FBEntity::clone(&self.pos_to_entity[pos])))
+                .collect::<Vec<_>>();
+            for (source_pos, feed) in classify_feeds(dest, *dest_pos, &sources) {
+                let source_lanes = pos_to_lanes[&source_pos];
+                let capacity = self.pos_to_entity[&source_pos].get_base().lane_capacity();
+                for (source_lane, dest_lane) in feed.lane_pairs() {
+                    let source_idx = source_lanes.lane(source_lane).1;
+                    let dest_idx = dest_lanes.lane(dest_lane).0;
+                    lane_feeds
+                        .entry(dest_idx)
+                        .or_insert_with(|| (dest.get_base().clone(), vec![]))
+                        .1
+                        .push((source_idx, capacity));
+                }
+            }
+        }
+        for (dest_idx, (dest_base, feeds)) in lane_feeds {
+            add_lane_feeds(&mut graph, dest_idx, feeds, &dest_base);
+        }
+
+        /* promote the lanes of unconnected belts to input or output nodes, only the declared ones if any */
+        let (inputs, outputs) = self.declared_io();
+        let promoted =
+            |declared: &[Position<i32>], pos| declared.is_empty() || declared.contains(pos);
+        for (pos, lanes) in &pos_to_lanes {
+            let id = self.pos_to_entity[pos].get_base().id;
+            let lanes = [(Lane::Left, lanes.left), (Lane::Right, lanes.right)];
+            let is_input = lanes
+                .iter()
+                .all(|(_, (in_idx, _))| graph.neighbors_directed(*in_idx, Incoming).count() == 0);
+            let is_output = lanes
+                .iter()
+                .all(|(_, (_, out_idx))| graph.neighbors_directed(*out_idx, Outgoing).count() == 0);
+            let is_input = is_input && promoted(&inputs, pos);
+            let is_output = is_output && promoted(&outputs, pos);
+            for (lane, (in_idx, out_idx)) in lanes {
+                if is_input {
+                    graph[in_idx] = Node::Input(Input {
+                        id,
+                        lane: lane.into(),
+                    });
+                }
+                if is_output {
+                    graph[out_idx] = Node::Output(Output {
+                        id,
+                        lane: lane.into(),
+                    });
+                }
+            }
+        }
+        Ok(graph)
+    }
 }
 
-fn find_underground_output<I>(underground: &FBUnderground<i32>, outputs: I) -> Option<Position<i32>>
+fn find_underground_output<I>(
+    underground: &FBUnderground<i32>,
+    outputs: I,
+    registry: &PrototypeRegistry,
+) -> Option<Position<i32>>
 where
     I: Iterator<Item = Rc<FBEntity<i32>>> + Clone,
 {
-    let base = underground.base;
+    let base = &underground.base;
     let pos = base.position;
     let dir = base.direction;
-    let throughput = base.throughput;
-    let max_distance = 3 + 2 * throughput as i32 / 15;
-    /* only matching underground belt tiers can be connected */
-    let outputs = outputs.filter(|u| u.get_base().throughput == throughput);
+    let max_distance = registry
+        .get(&underground.base.name)
+        .and_then(|p| p.max_underground_distance)
+        .unwrap_or_else(|| registry.max_underground_distance(base.throughput));
+    /* only underground belts of the same prototype can be connected */
+    let outputs = outputs.filter(
+        |u| matches!(&**u, FBEntity::Underground(output) if output.base.name == underground.base.name),
+    );
     /* XXX: runs in O(8n), with n = #outputs
      * can be improved to O(n) */
     for dist in 1..=max_distance {
@@ -381,7 +772,11 @@ where
 mod tests {
     use petgraph::dot::Dot;
 
-    use crate::{import::string_to_entities, ir::FlowGraphFun};
+    use crate::{
+        entities::{FBBelt, FBInserter, FBLoader},
+        import::{string_to_entities, string_to_entities_with_options, ImportOptions},
+        ir::{FlowGraphFun, GraphHelper},
+    };
 
     use super::*;
     use std::fs;
@@ -444,4 +839,427 @@ mod tests {
         assert_eq!(graph.node_count(), 2);
         assert_eq!(graph.edge_count(), 1);
     }
+
+    #[test]
+    fn turbo_underground() {
+        let entities = load("tests/space_age");
+        let position = |id| {
+            entities
+                .iter()
+                .find(|e| e.get_base().id == id)
+                .unwrap()
+                .get_base()
+                .position
+        };
+        /* the turbo underground spans 11 tiles */
+        let (entrance, exit) = (position(2), position(3));
+        assert_eq!(entrance.shift(Direction::East, 11), exit);
+
+        let ctx = Compiler::new(entities.clone());
+        assert!(ctx.feeds_to[&entrance].contains(&exit));
+        assert!(!ctx.find_input_positions().contains(&exit));
+    }
+
+    fn base(id: EntityId, x: i32, y: i32, throughput: f64) -> FBBaseEntity<i32> {
+        FBBaseEntity {
+            id,
+            position: Position { x, y },
+            direction: Direction::North,
+            throughput,
+            name: String::new(),
+        }
+    }
+
+    #[test]
+    fn link_capacity() {
+        /* a yellow belt feeding a red one, which feeds a yellow one */
+        let entities = [(1, 15.), (2, 30.), (3, 15.)]
+            .map(|(id, throughput)| {
+                FBEntity::Belt(FBBelt {
+                    base: base(id, 2, id + 1, throughput),
+                })
+            })
+            .to_vec();
+        let graph = Compiler::new(entities).create_graph();
+        /* the links between the belts are limited by the belt they leave */
+        let mut links = graph
+            .edge_weights()
+            .filter(|e| e.entities.is_empty())
+            .map(|e| e.capacity)
+            .collect::<Vec<_>>();
+        links.sort();
+        assert_eq!(
+            links,
+            [GenericFraction::from(15), GenericFraction::from(30)]
+        );
+    }
+
+    #[test]
+    fn fast_link_capacity() {
+        /* links used to have a constant capacity of 69 items/s, capping belts faster than that */
+        let entities = [(1, 120.), (2, 120.)]
+            .map(|(id, throughput)| {
+                FBEntity::Belt(FBBelt {
+                    base: base(id, 2, id + 1, throughput),
+                })
+            })
+            .to_vec();
+        let graph = Compiler::new(entities).create_graph();
+        let links = graph
+            .edge_weights()
+            .filter(|e| e.entities.is_empty())
+            .map(|e| e.capacity)
+            .collect::<Vec<_>>();
+        assert_eq!(links, [GenericFraction::from(120)]);
+    }
+
+    #[test]
+    fn underground_prototypes() {
+        let underground = |id, y, belt_type, name: &str| {
+            FBEntity::Underground(FBUnderground {
+                base: FBBaseEntity {
+                    name: name.to_owned(),
+                    ..base(id, 2, y, 15.)
+                },
+                belt_type,
+            })
+        };
+        let entrance = underground(1, 2, BeltType::Input, "underground-belt");
+        let connects = |exit| {
+            let ctx = Compiler::new(vec![entrance.clone(), exit]);
+            ctx.feeds_to.contains_key(&Position { x: 2, y: 2 })
+        };
+        assert!(connects(underground(
+            2,
+            4,
+            BeltType::Output,
+            "underground-belt"
+        )));
+        /* undergrounds of another prototype are not connected, even with the same throughput */
+        assert!(!connects(underground(
+            2,
+            4,
+            BeltType::Output,
+            "modded-underground-belt"
+        )));
+    }
+
+    #[test]
+    fn lane_side_loading() {
+        let entities = load("tests/side_loading");
+        let ctx = Compiler::new(entities);
+        let mut graph = ctx.create_lane_graph().unwrap();
+        let count =
+            |graph: &FlowGraph, f: fn(&Node) -> bool| graph.node_weights().filter(|n| f(n)).count();
+
+        /* the left lane of the side-loaded belt is fed by three lanes */
+        assert_eq!(count(&graph, |n| matches!(n, Node::Merger(_))), 2);
+        assert_eq!(count(&graph, |n| matches!(n, Node::Input(_))), 8);
+        assert_eq!(count(&graph, |n| matches!(n, Node::Output(_))), 6);
+
+        /* side-loading onto an underground only feeds one of its lanes */
+        graph.simplify(&[], crate::ir::CoalesceStrength::Lossless);
+        let underground_lanes = graph
+            .node_weights()
+            .filter_map(|n| match n {
+                Node::Output(o) if o.id == 6 => Some(o.lane),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(underground_lanes, vec![Side::Left]);
+    }
+
+    #[test]
+    fn lane_side_loading_exit() {
+        /* a belt side-loading the exit of an underground from its left */
+        let belt = FBBaseEntity {
+            direction: Direction::East,
+            ..base(2, 1, 3, 15.)
+        };
+        let entities = vec![
+            FBEntity::Underground(FBUnderground {
+                base: base(1, 2, 3, 15.),
+                belt_type: BeltType::Output,
+            }),
+            FBEntity::Belt(FBBelt { base: belt }),
+        ];
+        let graph = Compiler::new(entities).create_lane_graph().unwrap();
+        let links = graph
+            .edge_indices()
+            .filter_map(|e| graph.edge_endpoints(e))
+            .filter(|(source, dest)| graph[*source].get_id() == 2 && graph[*dest].get_id() == 1)
+            .collect::<Vec<_>>();
+        /* only the lane closer to the front of the exit is side-loaded, onto the left lane */
+        assert_eq!(links.len(), 1);
+        let (source, dest) = links[0];
+        assert!(
+            matches!(&graph[graph.in_nodes(source)[0]], Node::Input(i) if i.lane == Side::Left)
+        );
+        assert!(
+            matches!(&graph[graph.out_nodes(dest)[0]], Node::Output(o) if o.lane == Side::Left)
+        );
+    }
+
+    #[test]
+    fn lane_loaders() {
+        /* two loaders at the ends of a belt, side-loaded by a belt fed by nothing */
+        let east = |id, x| FBBaseEntity {
+            direction: Direction::East,
+            ..base(id, x, 1, 15.)
+        };
+        let loader = |id, x, belt_type| {
+            FBEntity::Loader(FBLoader {
+                base: east(id, x),
+                belt_type,
+                compact: false,
+            })
+        };
+        let mut entities = vec![
+            loader(1, 1, BeltType::Output),
+            FBEntity::Belt(FBBelt { base: east(2, 2) }),
+            FBEntity::Belt(FBBelt { base: east(3, 3) }),
+            loader(4, 4, BeltType::Input),
+            FBEntity::Belt(FBBelt {
+                base: base(5, 2, 0, 15.),
+            }),
+        ];
+        let graph = Compiler::new(entities.clone()).create_lane_graph().unwrap();
+        let io = |input: bool| {
+            let mut ids = graph
+                .node_weights()
+                .filter_map(|n| match n {
+                    Node::Input(i) if input => Some(i.id),
+                    Node::Output(o) if !input => Some(o.id),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        /* only the declared loaders, one node per lane */
+        assert_eq!(io(true), [1, 1]);
+        assert_eq!(io(false), [4, 4]);
+
+        /* how inserters pick up items from the lanes is not modelled */
+        entities.push(FBEntity::Inserter(FBInserter {
+            base: base(6, 3, 2, 2.31),
+        }));
+        let error = Compiler::new(entities).create_lane_graph().unwrap_err();
+        assert_eq!(error.id, 6);
+    }
+
+    #[test]
+    fn lane_splitter() {
+        let entities = load("tests/simple_splitter");
+        let ctx = Compiler::new(entities);
+        let graph = ctx.create_lane_graph().unwrap();
+        let splitters = graph
+            .node_weights()
+            .filter(|n| matches!(n, Node::Splitter(_)))
+            .count();
+        let mergers = graph
+            .node_weights()
+            .filter(|n| matches!(n, Node::Merger(_)))
+            .count();
+        assert_eq!((splitters, mergers), (2, 2));
+    }
+
+    #[test]
+    fn assembler_column() {
+        let entities = load("tests/assembler_column");
+        let ctx = Compiler::new(entities);
+        let mut graph = ctx.create_graph();
+
+        /* the inserters pick up before the rest of the belt */
+        let splitters = graph
+            .node_weights()
+            .filter(|n| matches!(n, Node::Splitter(s) if matches!(s.output_priority, Side::Left)))
+            .count();
+        assert_eq!(splitters, 2);
+        let inputs = graph
+            .node_weights()
+            .filter_map(|n| match n {
+                Node::Input(i) => Some(i.id),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(inputs, HashSet::from([1]));
+
+        /* the assemblers consume at their crafting speed */
+        graph.simplify(&[], crate::ir::CoalesceStrength::Aggressive);
+        let mut outputs = HashMap::new();
+        for idx in graph.node_indices() {
+            if let Node::Output(o) = &graph[idx] {
+                outputs.insert(o.id, graph.in_edges(idx)[0].capacity);
+            }
+        }
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[&9], GenericFraction::new(3u128, 4u128));
+        assert_eq!(outputs[&10], GenericFraction::new(3u128, 4u128));
+    }
+
+    #[test]
+    fn inserter_assembler() {
+        /* the long inserter feeds the assembler */
+        let entities = load("tests/inserter_assembler");
+        let graph = Compiler::new(entities).create_graph();
+        assert!(graph
+            .edge_weights()
+            .any(|e| e.entities == vec![4] && e.capacity == GenericFraction::new(5u128, 4u128)));
+    }
+
+    #[test]
+    fn gear_column() {
+        let entities = load("tests/gear_column");
+        let ctx = Compiler::new(entities);
+        let is_craft = |n: &Node| matches!(n, Node::Craft(_));
+        assert!(!ctx.create_graph().node_weights().any(is_craft));
+
+        let graph = ctx.create_production_graph(&RecipeDatabase::vanilla());
+        let crafts = graph
+            .node_indices()
+            .filter(|idx| is_craft(&graph[*idx]))
+            .collect::<Vec<_>>();
+        assert_eq!(crafts.len(), 2);
+        for idx in crafts {
+            let Node::Craft(craft) = &graph[idx] else {
+                unreachable!()
+            };
+            /* 0.75 crafting speed, 0.5 s per gear */
+            assert_eq!(craft.max_crafts, GenericFraction::new(3u128, 2u128));
+            assert_eq!(
+                craft.ingredients,
+                [("iron-plate".to_owned(), GenericFraction::from(2))]
+            );
+            /* fed by an inserter, the gears go to an output */
+            assert_eq!(graph.in_deg(idx), 1);
+            let output = graph.out_nodes(idx)[0];
+            assert!(matches!(&graph[output], Node::Output(o) if o.id == craft.id));
+            assert_eq!(graph.out_edges(idx)[0].capacity, craft.max_crafts);
+        }
+    }
+
+    #[test]
+    fn test_bench() {
+        let ctx = Compiler::new(load("tests/test_bench"));
+        let ids = |positions: Vec<Position<i32>>| {
+            let mut ids = positions
+                .iter()
+                .filter_map(|pos| ctx.pos_to_id(pos))
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        /* the belt side-loading the line is not an input, as the loaders and chests are declared */
+        assert_eq!(ids(ctx.find_input_positions()), [1, 6]);
+        assert_eq!(ids(ctx.find_output_positions()), [5, 10]);
+
+        let graph = ctx.create_graph();
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+        for node in graph.node_weights() {
+            match node {
+                Node::Input(i) => inputs.push(i.id),
+                Node::Output(o) => outputs.push(o.id),
+                _ => (),
+            }
+        }
+        inputs.sort();
+        outputs.sort();
+        assert_eq!(inputs, [1, 6]);
+        assert_eq!(outputs, [5, 10]);
+
+        /* the loaders carry a belt, the chests as much as their inserter */
+        let capacity = |id| {
+            graph
+                .edge_weights()
+                .find(|e| e.entities == [id])
+                .unwrap()
+                .capacity
+        };
+        assert_eq!(capacity(1), GenericFraction::from(15));
+        assert_eq!(capacity(6), GenericFraction::from(2.31));
+        assert_eq!(capacity(10), GenericFraction::from(2.31));
+    }
+
+    #[test]
+    fn output_chests() {
+        /* only the output is declared, the inputs are still the belts not fed by any entity */
+        let ctx = Compiler::new(load("tests/output_chests"));
+        let ids = |positions: Vec<Position<i32>>| {
+            positions
+                .iter()
+                .filter_map(|pos| ctx.pos_to_id(pos))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(ctx.find_input_positions()), [1]);
+        assert_eq!(ids(ctx.find_output_positions()), [6]);
+
+        let graph = ctx.create_graph();
+        let inputs = graph
+            .node_weights()
+            .filter_map(|n| match n {
+                Node::Input(i) => Some(i.id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let outputs = graph
+            .node_weights()
+            .filter_map(|n| match n {
+                Node::Output(o) => Some(o.id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(inputs, [1]);
+        assert_eq!(outputs, [6]);
+    }
+
+    #[test]
+    fn moduled_column() {
+        let entities = load("tests/moduled_column");
+        let graph = Compiler::new(entities).create_production_graph(&RecipeDatabase::vanilla());
+        let craft = |id| {
+            graph
+                .node_weights()
+                .find_map(|n| match n {
+                    Node::Craft(c) if c.id == id => Some(c.clone()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        /* 1.875 crafting speed with the modules and the beacon */
+        assert_eq!(craft(9).max_crafts, GenericFraction::new(15u128, 4u128));
+        /* productivity adds to the products, not to the ingredients */
+        let productive = craft(10);
+        assert_eq!(productive.ingredients[0].1, GenericFraction::from(2));
+        assert!(productive.products[0].1 > GenericFraction::from(1));
+    }
+
+    #[test]
+    fn modded_underground() {
+        let mut options = ImportOptions::default();
+        let modded = PrototypeRegistry::from_file("tests/modded_prototypes.json").unwrap();
+        options.registry.extend(modded);
+        let blueprint_string = fs::read_to_string("tests/modded_belts").unwrap();
+        let (entities, _) = string_to_entities_with_options(&blueprint_string, &options).unwrap();
+        let position = |id| {
+            entities
+                .iter()
+                .find(|e| e.get_base().id == id)
+                .unwrap()
+                .get_base()
+                .position
+        };
+
+        let ctx = Compiler::with_registry(entities.clone(), &options.registry);
+        /* the ultra underground reaches 13 tiles */
+        assert!(ctx.feeds_to[&position(2)].contains(&position(3)));
+        /* the hyper underground only reaches 15 tiles, its exit is 17 tiles away */
+        assert!(!ctx.feeds_to.contains_key(&position(5)));
+
+        /* the links between entities do not limit the throughput */
+        let mut graph = ctx.create_graph();
+        graph.simplify(&[], crate::ir::CoalesceStrength::Aggressive);
+        assert!(graph.edge_weights().all(|e| e.capacity >= 90.into()));
+    }
 }
@@ -0,0 +1,315 @@
+//! Lane-aware conversion of entities to the IR, see [`super::Compiler::create_lane_graph`].
+
+use fraction::GenericFraction;
+use petgraph::prelude::NodeIndex;
+use std::{collections::HashMap, fmt::Display, ops::Neg};
+
+use crate::{
+    entities::{
+        BeltType, EntityId, FBBaseEntity, FBBelt, FBEntity, FBLoader, FBSplitter, FBUnderground,
+    },
+    ir::{self, Connector, Edge, FlowGraph, Node},
+    utils::{Position, Rotation, Side},
+};
+
+/// One of the two lanes of a belt, seen in the direction of the belt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Left,
+    Right,
+}
+
+impl Neg for Lane {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+}
+
+impl From<Lane> for Side {
+    fn from(value: Lane) -> Self {
+        match value {
+            Lane::Left => Self::Left,
+            Lane::Right => Self::Right,
+        }
+    }
+}
+
+/// In- and out-nodes of the left and right lane of a single tile
+#[derive(Debug, Clone, Copy)]
+pub struct LaneNodes {
+    pub left: (NodeIndex, NodeIndex),
+    pub right: (NodeIndex, NodeIndex),
+}
+
+impl LaneNodes {
+    /// Returns the in- and out-node of the given lane.
+    pub fn lane(&self, lane: Lane) -> (NodeIndex, NodeIndex) {
+        match lane {
+            Lane::Left => self.left,
+            Lane::Right => self.right,
+        }
+    }
+}
+
+pub type PosToLanes = HashMap<Position<i32>, LaneNodes>;
+
+/// Entity exchanging items with the lanes in a way that is not modelled, see [`super::Compiler::create_lane_graph`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedEntity {
+    pub id: EntityId,
+    /// Name of the prototype of the entity
+    pub name: String,
+}
+
+impl Display for UnsupportedEntity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Entity {} ({}) can't be modelled with lanes, only belts, splitters and loaders can",
+            self.id, self.name
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedEntity {}
+
+fn add_belt_lanes_to_graph(
+    base: &FBBaseEntity<i32>,
+    graph: &mut FlowGraph,
+    pos_to_lanes: &mut PosToLanes,
+) {
+    let id = base.id;
+    let capacity = base.lane_capacity();
+
+    let mut add_lane = || {
+        let in_idx = graph.add_node(Node::Connector(Connector { id }));
+        let out_idx = graph.add_node(Node::Connector(Connector { id }));
+        let edge = Edge {
+            side: Side::None,
+            capacity,
+            entities: vec![id],
+        };
+        graph.add_edge(in_idx, out_idx, edge);
+        (in_idx, out_idx)
+    };
+    let left = add_lane();
+    let right = add_lane();
+    pos_to_lanes.insert(base.position, LaneNodes { left, right });
+}
+
+pub trait AddLanesToGraph {
+    fn add_lanes_to_graph(&self, graph: &mut FlowGraph, pos_to_lanes: &mut PosToLanes);
+}
+
+impl AddLanesToGraph for FBBelt<i32> {
+    fn add_lanes_to_graph(&self, graph: &mut FlowGraph, pos_to_lanes: &mut PosToLanes) {
+        add_belt_lanes_to_graph(&self.base, graph, pos_to_lanes)
+    }
+}
+
+impl AddLanesToGraph for FBUnderground<i32> {
+    fn add_lanes_to_graph(&self, graph: &mut FlowGraph, pos_to_lanes: &mut PosToLanes) {
+        add_belt_lanes_to_graph(&self.base, graph, pos_to_lanes)
+    }
+}
+
+/* loaders are belts whose ends become inputs or outputs */
+impl AddLanesToGraph for FBLoader<i32> {
+    fn add_lanes_to_graph(&self, graph: &mut FlowGraph, pos_to_lanes: &mut PosToLanes) {
+        add_belt_lanes_to_graph(&self.base, graph, pos_to_lanes)
+    }
+}
+
+/// A splitter keeps the lanes separated: each lane gets its own merger and splitter,
+/// connecting the same lane of both inputs to the same lane of both outputs.
+impl AddLanesToGraph for FBSplitter<i32> {
+    fn add_lanes_to_graph(&self, graph: &mut FlowGraph, pos_to_lanes: &mut PosToLanes) {
+        let id = self.base.id;
+        let capacity = self.base.lane_capacity();
+
+        let mut add_lane = || {
+            let ir_merger = ir::Merger {
+                input_priority: self.input_prio.into(),
+                id,
+            };
+            let ir_splitter = ir::Splitter {
+                output_priority: self.output_prio.into(),
+                id,
+            };
+            let splitter_idx = graph.add_node(Node::Splitter(ir_splitter));
+            let merger_idx = graph.add_node(Node::Merger(ir_merger));
+
+            let in_r_idx = graph.add_node(Node::Connector(Connector { id }));
+            let out_r_idx = graph.add_node(Node::Connector(Connector { id }));
+            let in_l_idx = graph.add_node(Node::Connector(Connector { id }));
+            let out_l_idx = graph.add_node(Node::Connector(Connector { id }));
+
+            let merger_splitter_edge = Edge {
+                side: Side::None,
+                capacity: capacity * GenericFraction::new(2u128, 1u128),
+                entities: vec![id],
+            };
+            let r_edge = Edge {
+                side: Side::Right,
+                capacity,
+                entities: vec![],
+            };
+            let l_edge = Edge {
+                side: Side::Left,
+                capacity,
+                entities: vec![],
+            };
+
+            graph.add_edge(in_l_idx, merger_idx, l_edge.clone());
+            graph.add_edge(in_r_idx, merger_idx, r_edge.clone());
+
+            graph.add_edge(splitter_idx, out_l_idx, l_edge);
+            graph.add_edge(splitter_idx, out_r_idx, r_edge);
+
+            graph.add_edge(merger_idx, splitter_idx, merger_splitter_edge);
+            ((in_l_idx, out_l_idx), (in_r_idx, out_r_idx))
+        };
+        let (left_belt_left, right_belt_left) = add_lane();
+        let (left_belt_right, right_belt_right) = add_lane();
+
+        let pos_r = self.base.position;
+        let pos_l = self.get_phantom().base.position;
+        pos_to_lanes.insert(
+            pos_r,
+            LaneNodes {
+                left: right_belt_left,
+                right: right_belt_right,
+            },
+        );
+        pos_to_lanes.insert(
+            pos_l,
+            LaneNodes {
+                left: left_belt_left,
+                right: left_belt_right,
+            },
+        );
+    }
+}
+
+/// How an entity feeds the lanes of the entity in front of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaneFeed {
+    /// Both lanes are kept, e.g. a straight line of belts, a curve or an underground belt
+    Straight,
+    /// Both lanes of the source are side-loaded onto the given lane of the destination
+    SideLoad(Lane),
+    /// Side-loading onto the entrance of an underground belt from the given side.
+    ///
+    /// Half of the entrance is covered, so only the lane of the source that is closer to the open half is side-loaded.
+    UndergroundSideLoad(Lane),
+    /// Side-loading onto the exit of an underground belt from the given side.
+    ///
+    /// The back half of the exit is covered, so only the lane of the source closer to its front is side-loaded.
+    UndergroundExitSideLoad(Lane),
+}
+
+impl LaneFeed {
+    /// Returns the pairs of (source lane, destination lane) connected by this feed.
+    pub fn lane_pairs(&self) -> Vec<(Lane, Lane)> {
+        match *self {
+            Self::Straight => vec![(Lane::Left, Lane::Left), (Lane::Right, Lane::Right)],
+            Self::SideLoad(side) => vec![(Lane::Left, side), (Lane::Right, side)],
+            Self::UndergroundSideLoad(side) => vec![(-side, side)],
+            Self::UndergroundExitSideLoad(side) => vec![(side, side)],
+        }
+    }
+}
+
+/// Determines how each of the `sources` feeds the `dest` entity.
+///
+/// A belt fed from exactly one side and not from behind is a curve, otherwise belts coming from the sides side-load it.
+/// Belts facing the destination head-on, or going into the side of a splitter, don't feed it.
+pub fn classify_feeds(
+    dest: &FBEntity<i32>,
+    dest_pos: Position<i32>,
+    sources: &[(Position<i32>, FBEntity<i32>)],
+) -> Vec<(Position<i32>, LaneFeed)> {
+    let dest_dir = dest.get_base().direction;
+    let left_pos = dest_pos.shift(dest_dir.rotate(Rotation::Anticlockwise, 1), 1);
+
+    let from_behind = |source: &FBEntity<i32>| {
+        matches!(source, FBEntity::Underground(u) if u.belt_type == BeltType::Input)
+            || source.get_base().direction == dest_dir
+    };
+    let from_side = |source: &FBEntity<i32>| {
+        let dir = source.get_base().direction;
+        !from_behind(source) && dir != dest_dir.flip()
+    };
+    let has_behind = sources.iter().any(|(_, s)| from_behind(s));
+    let side_count = sources.iter().filter(|(_, s)| from_side(s)).count();
+    let is_curve = matches!(dest, FBEntity::Belt(_)) && !has_behind && side_count == 1;
+
+    sources
+        .iter()
+        .filter_map(|(pos, source)| {
+            if from_behind(source) {
+                return Some((*pos, LaneFeed::Straight));
+            }
+            if !from_side(source) {
+                return None;
+            }
+            let side = if *pos == left_pos {
+                Lane::Left
+            } else {
+                Lane::Right
+            };
+            let feed = match dest {
+                FBEntity::Belt(_) if is_curve => LaneFeed::Straight,
+                FBEntity::Belt(_) => LaneFeed::SideLoad(side),
+                FBEntity::Underground(u) if u.belt_type == BeltType::Input => {
+                    LaneFeed::UndergroundSideLoad(side)
+                }
+                FBEntity::Underground(_) => LaneFeed::UndergroundExitSideLoad(side),
+                _ => return None,
+            };
+            Some((*pos, feed))
+        })
+        .collect()
+}
+
+/// Connects all the `feeds` to the in-node of a lane.
+///
+/// A single feed is connected directly, multiple feeds are merged using a chain of mergers.
+pub fn add_lane_feeds(
+    graph: &mut FlowGraph,
+    lane_idx: NodeIndex,
+    feeds: Vec<(NodeIndex, GenericFraction<u128>)>,
+    dest_base: &FBBaseEntity<i32>,
+) {
+    let mut feeds = feeds.into_iter();
+    let Some((mut current, mut capacity)) = feeds.next() else {
+        return;
+    };
+    for (source, source_capacity) in feeds {
+        let merger = ir::Merger {
+            input_priority: Side::None,
+            id: dest_base.id,
+        };
+        let merger_idx = graph.add_node(Node::Merger(merger));
+        let edge = |capacity| Edge {
+            side: Side::None,
+            capacity,
+            entities: vec![],
+        };
+        graph.add_edge(current, merger_idx, edge(capacity));
+        graph.add_edge(source, merger_idx, edge(source_capacity));
+        current = merger_idx;
+        capacity = dest_base.lane_capacity();
+    }
+    let edge = Edge {
+        side: Side::None,
+        capacity,
+        entities: vec![],
+    };
+    graph.add_edge(current, lane_idx, edge);
+}
@@ -2,5 +2,7 @@
 
 mod compile_entities;
 mod compile_graph;
+mod compile_lanes;
 
 pub use compile_graph::{Compiler, RelMap};
+pub use compile_lanes::UnsupportedEntity;
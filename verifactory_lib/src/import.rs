@@ -1,147 +1,498 @@
 //! Utility functions to convert a Factorio blueprint string into a list of `FBEntity`s.
 //! A description of the JSON representation of the blueprint string can be found [here](https://wiki.factorio.com/Blueprint_string_format).
 
-use anyhow::{anyhow, Context, Result};
 use base64::engine::{general_purpose, Engine as _};
 use inflate::inflate_bytes_zlib;
 use serde::{de::Error, Deserialize, Deserializer};
 use serde_json::Value;
-use std::fs;
+use std::{collections::HashMap, fmt::Display, fs};
 
 use crate::{
     entities::*,
-    utils::{Direction, Position, Rotation},
+    inserters::{InserterModel, TargetKind},
+    prototypes::{EntityKind, PrototypeRegistry},
+    utils::{Direction, InvalidDirection, Position, Rotation},
 };
 
+/// Errors that can occur whilst importing a blueprint string
+#[derive(Debug)]
+pub enum ImportError {
+    /// The blueprint string is empty
+    EmptyString,
+    /// The blueprint string is not valid base64
+    Base64(base64::DecodeError),
+    /// The decoded blueprint string is not valid zlib compressed data
+    Zlib(String),
+    /// The decompressed blueprint string is not valid JSON
+    Json(serde_json::Error),
+    /// A key required to import the blueprint is missing
+    MissingKey(&'static str),
+    /// The value of a key does not have the expected type
+    InvalidValue {
+        key: &'static str,
+        expected: &'static str,
+    },
+    /// The direction of an entity is not one of the four cardinal directions
+    NonCardinalDirection(u64),
+    /// The tier of an entity, like the one of an assembling machine, is not known
+    UnknownTier(String),
+    /// The entity is not supported by VeriFactory
+    UnsupportedEntity(String),
+    /// Error whilst importing a blueprint that is part of a blueprint book
+    InBook {
+        index: Vec<usize>,
+        error: Box<ImportError>,
+    },
+    /// Error whilst reading a file containing a blueprint string
+    Io(std::io::Error),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyString => write!(f, "Blueprint string is empty"),
+            Self::Base64(e) => write!(f, "Blueprint string is not valid base64: {}", e),
+            Self::Zlib(e) => write!(f, "Blueprint string is not valid zlib data: {}", e),
+            Self::Json(e) => write!(f, "Blueprint string is not valid JSON: {}", e),
+            Self::MissingKey(key) => write!(f, "No {} key in json", key),
+            Self::InvalidValue { key, expected } => {
+                write!(f, "Value of the {} key is not {}", key, expected)
+            }
+            Self::NonCardinalDirection(d) => {
+                write!(f, "Direction is not in cardinal direction: ({})", d)
+            }
+            Self::UnknownTier(name) => write!(f, "Unknown tier of entity: ({})", name),
+            Self::UnsupportedEntity(name) => write!(f, "Invalid entity: ({})", name),
+            Self::InBook { index, error } => {
+                write!(f, "Blueprint at index {:?} of the book: {}", index, error)
+            }
+            Self::Io(e) => write!(f, "Could not read blueprint file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Base64(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::InBook { error, .. } => Some(error.as_ref()),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<base64::DecodeError> for ImportError {
+    fn from(value: base64::DecodeError) -> Self {
+        Self::Base64(value)
+    }
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+impl From<InvalidDirection> for ImportError {
+    fn from(value: InvalidDirection) -> Self {
+        Self::NonCardinalDirection(value.0 as u64)
+    }
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// An entity of the blueprint that could not be imported
+#[derive(Debug)]
+pub struct SkippedEntity {
+    /// Name of the entity, if present
+    pub name: Option<String>,
+    /// Entity number of the entity, if present
+    pub entity_number: Option<EntityId>,
+    /// Why the entity was skipped
+    pub reason: ImportError,
+}
+
+impl Display for SkippedEntity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.name.as_deref().unwrap_or("<unnamed>");
+        match self.entity_number {
+            Some(id) => write!(f, "{} (#{}): {}", name, id, self.reason),
+            None => write!(f, "{}: {}", name, self.reason),
+        }
+    }
+}
+
+/// Report listing the entities that were skipped whilst importing a blueprint
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub skipped: Vec<SkippedEntity>,
+}
+
+impl ImportReport {
+    /// Returns `true` if no entity was skipped.
+    pub fn is_empty(&self) -> bool {
+        self.skipped.is_empty()
+    }
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for skipped in &self.skipped {
+            writeln!(f, "{}", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Major version of Factorio a blueprint was created with.
+///
+/// Factorio 2.0 changed the encoding of some fields, most notably directions which now have 16 values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameVersion {
+    /// Factorio 1.1 and earlier, directions are encoded with 8 values
+    V1,
+    /// Factorio 2.0 and later, directions are encoded with 16 values
+    V2,
+}
+
+impl GameVersion {
+    /// Returns the game version from the `version` field of a blueprint.
+    ///
+    /// The field packs the version as four 16 bit numbers, the major version being the highest one.
+    pub fn from_blueprint_version(version: u64) -> Self {
+        if version >> 48 >= 2 {
+            Self::V2
+        } else {
+            Self::V1
+        }
+    }
+
+    /// Returns the `version` field written in exported blueprints (1.1.61 and 2.0.28).
+    pub fn blueprint_version(&self) -> u64 {
+        match self {
+            Self::V1 => 281479275675648,
+            Self::V2 => 562949955256320,
+        }
+    }
+
+    /// Converts the `direction` field of a blueprint entity to a `Direction`.
+    pub fn decode_direction(&self, value: u64) -> Result<Direction, ImportError> {
+        let value = match self {
+            Self::V1 => value,
+            /* 2.0 has 16 directions, the cardinal ones being multiples of 4 */
+            Self::V2 if value.is_multiple_of(4) => value / 2,
+            Self::V2 => return Err(ImportError::NonCardinalDirection(value)),
+        };
+        let value = u8::try_from(value).map_err(|_| ImportError::NonCardinalDirection(value))?;
+        Ok(Direction::try_from(value)?)
+    }
+
+    /// Returns the share of the effects of a beacon transmitted to an assembler affected by `beacons` beacons.
+    ///
+    /// Factorio 2.0 divides the distribution `effectivity` of the beacon prototype by the square root of
+    /// the number of beacons affecting the assembler.
+    pub fn beacon_transmission(&self, effectivity: f64, beacons: usize) -> f64 {
+        match self {
+            Self::V1 => effectivity,
+            Self::V2 => effectivity / (beacons as f64).sqrt(),
+        }
+    }
+
+    /// Converts a `Direction` to the value of the `direction` field of a blueprint entity.
+    pub fn encode_direction(&self, direction: Direction) -> u8 {
+        match self {
+            Self::V1 => direction as u8,
+            Self::V2 => direction as u8 * 2,
+        }
+    }
+}
+
+/// Reads the game version of a blueprint, assuming 1.1 if the blueprint has no version.
+fn get_game_version(blueprint: &Value) -> Result<GameVersion, ImportError> {
+    match blueprint.get("version") {
+        Some(v) => {
+            v.as_u64()
+                .map(GameVersion::from_blueprint_version)
+                .ok_or(ImportError::InvalidValue {
+                    key: "version",
+                    expected: "an unsigned integer",
+                })
+        }
+        None => Ok(GameVersion::V1),
+    }
+}
+
+/// Options used whilst importing a blueprint
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Prototypes of the entities that can be imported, the vanilla ones by default
+    pub registry: PrototypeRegistry,
+    /// Model computing the throughput of inserters from their surroundings and the research,
+    /// the throughput of their prototype is used if `None`
+    pub inserters: Option<InserterModel>,
+}
+
 /// Decompresses the string such that it can be interpreted as a JSON.
-fn decompress_string(blueprint_string: &str) -> Result<Value> {
-    let skip_first_byte = &blueprint_string.as_bytes()[1..blueprint_string.len()];
+fn decompress_string(blueprint_string: &str) -> Result<Value, ImportError> {
+    let blueprint_string = blueprint_string.trim();
+    /* the first byte is the version of the blueprint string */
+    let skip_first_byte = blueprint_string
+        .as_bytes()
+        .get(1..)
+        .ok_or(ImportError::EmptyString)?;
     let base64_decoded = general_purpose::STANDARD.decode(skip_first_byte)?;
-    let decoded = inflate_bytes_zlib(&base64_decoded).map_err(|s| anyhow!(s))?;
+    let decoded = inflate_bytes_zlib(&base64_decoded).map_err(ImportError::Zlib)?;
     Ok(serde_json::from_slice(&decoded)?)
 }
 
-/// Turns a JSON string into a list of JSON substrings, each representing an entity of the blueprint.
-fn get_json_entities(json: Value) -> Result<Vec<Value>> {
-    json.get("blueprint")
-        .context("No blueprint key in json")?
+/// Turns a JSON blueprint into a list of JSON substrings, each representing an entity of the blueprint.
+fn get_json_entities(blueprint: &Value) -> Result<Vec<Value>, ImportError> {
+    blueprint
         .get("entities")
-        .context("No entities key in blueprint")?
+        .ok_or(ImportError::MissingKey("entities"))?
         .as_array()
-        .context("Entities are not an array")
+        .ok_or(ImportError::InvalidValue {
+            key: "entities",
+            expected: "an array",
+        })
         .map(|v| v.to_owned())
 }
 
-/// Helper function that deserializes the attributes shared by each entity.
+/// Helper function that parses the attributes shared by each entity.
+///
+/// The throughput is looked up in the prototype of the entity, see [`parse_entity`].
+fn parse_base_entity(
+    value: &Value,
+    version: GameVersion,
+) -> Result<FBBaseEntity<f64>, ImportError> {
+    let name = value
+        .get("name")
+        .ok_or(ImportError::MissingKey("name"))?
+        .as_str()
+        .ok_or(ImportError::InvalidValue {
+            key: "name",
+            expected: "a string",
+        })?
+        .to_owned();
+    let id = value
+        .get("entity_number")
+        .ok_or(ImportError::MissingKey("entity_number"))?
+        .as_i64()
+        .ok_or(ImportError::InvalidValue {
+            key: "entity_number",
+            expected: "an integer",
+        })? as EntityId;
+
+    let position: Position<f64> = value
+        .get("position")
+        .ok_or(ImportError::MissingKey("position"))
+        .and_then(|v| Ok(serde_json::from_value(v.clone())?))?;
+
+    let direction = match value.get("direction") {
+        Some(v) => {
+            let direction = v.as_u64().ok_or(ImportError::InvalidValue {
+                key: "direction",
+                expected: "an unsigned integer",
+            })?;
+            version.decode_direction(direction)?
+        }
+        None => Direction::North,
+    };
+
+    let base = FBBaseEntity {
+        id,
+        position,
+        direction,
+        throughput: 0.0,
+        name,
+    };
+    Ok(base)
+}
+
+/// Helper function that parses an optional field of an entity, using `default` if the field is missing.
+fn parse_optional<T>(value: &Value, key: &'static str, default: T) -> Result<T, ImportError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    match value.get(key) {
+        Some(v) => Ok(serde_json::from_value(v.clone())?),
+        None => Ok(default),
+    }
+}
+
+/// Helper function that parses the `type` of underground belts and loaders.
+fn parse_belt_type(value: &Value) -> Result<BeltType, ImportError> {
+    value
+        .get("type")
+        .ok_or(ImportError::MissingKey("type"))
+        .and_then(|v| Ok(serde_json::from_value(v.clone())?))
+}
+
+/// Returns the error of an entity missing from the registry.
+///
+/// Names belonging to a family of supported entities, like `assembling-machine-4`, are reported as unknown tiers.
+fn unknown_entity(name: &str) -> ImportError {
+    const FAMILIES: [&str; 6] = [
+        "transport-belt",
+        "underground-belt",
+        "splitter",
+        "inserter",
+        "assembling-machine",
+        "loader",
+    ];
+    if FAMILIES.iter().any(|family| name.contains(family)) {
+        ImportError::UnknownTier(name.to_owned())
+    } else {
+        ImportError::UnsupportedEntity(name.to_owned())
+    }
+}
+
+/// Parses the modules in the `items` field of an entity, returning the name of the module in each slot.
+///
+/// Factorio 1.1 maps the name of each item to its count, whereas 2.0 lists each item with the inventory slots
+/// it fills. Items that are not modules, like fuel, are ignored.
+fn parse_modules(value: &Value, registry: &PrototypeRegistry) -> Result<Vec<String>, ImportError> {
+    let invalid = ImportError::InvalidValue {
+        key: "items",
+        expected: "a map of item counts or a list of items",
+    };
+    let counts: Vec<(&str, u64)> = match value.get("items") {
+        None => vec![],
+        Some(Value::Object(items)) => items
+            .iter()
+            .map(|(name, count)| Some((name.as_str(), count.as_u64()?)))
+            .collect::<Option<_>>()
+            .ok_or(invalid)?,
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                let name = item.get("id")?.get("name")?.as_str()?;
+                let slots = item.get("items")?.get("in_inventory")?.as_array()?;
+                Some((name, slots.len() as u64))
+            })
+            .collect::<Option<_>>()
+            .ok_or(invalid)?,
+        Some(_) => return Err(invalid),
+    };
+    let modules = counts
+        .into_iter()
+        .filter(|(name, _)| {
+            registry
+                .get(name)
+                .is_some_and(|p| p.kind == EntityKind::Module)
+        })
+        .flat_map(|(name, count)| (0..count).map(move |_| name.to_owned()))
+        .collect();
+    Ok(modules)
+}
+
+/// Sums the effects of the `modules`, see [`parse_modules`].
+fn module_effects(modules: &[String], registry: &PrototypeRegistry) -> ModuleEffects {
+    modules
+        .iter()
+        .filter_map(|name| registry.get(name))
+        .fold(ModuleEffects::default(), |sum, prototype| {
+            sum.combine(&prototype.effects)
+        })
+}
+
+/// Parses the JSON representation of an entity into a `FBEntity<f64>`.
+///
+/// The kind and throughput of the entity are looked up in the `registry`, for the given game `version`.
+fn parse_entity(
+    value: &Value,
+    version: GameVersion,
+    registry: &PrototypeRegistry,
+) -> Result<FBEntity<f64>, ImportError> {
+    let mut base = parse_base_entity(value, version)?;
+    let name = base.name.as_str();
+    let prototype = registry
+        .get_in(name, version)
+        .ok_or_else(|| unknown_entity(name))?;
+    base.throughput = prototype.throughput;
+
+    let entity = match prototype.kind {
+        EntityKind::Belt => FBEntity::Belt(FBBelt { base }),
+        EntityKind::Underground => FBEntity::Underground(FBUnderground {
+            base,
+            belt_type: parse_belt_type(value)?,
+        }),
+        EntityKind::Splitter => {
+            let input_prio = parse_optional(value, "input_priority", Priority::None)?;
+            let output_prio = parse_optional(value, "output_priority", Priority::None)?;
+            FBEntity::Splitter(FBSplitter {
+                base,
+                input_prio,
+                output_prio,
+            })
+        }
+        EntityKind::Inserter => FBEntity::Inserter(FBInserter { base }),
+        EntityKind::LongInserter => FBEntity::LongInserter(FBLongInserter { base }),
+        EntityKind::Assembler => {
+            let recipe = parse_optional::<Option<String>>(value, "recipe", None)?;
+            let modules = parse_modules(value, registry)?;
+            FBEntity::Assembler(FBAssembler {
+                base,
+                recipe,
+                effects: module_effects(&modules, registry),
+                modules,
+            })
+        }
+        EntityKind::Beacon => {
+            let modules = parse_modules(value, registry)?;
+            FBEntity::Beacon(FBBeacon {
+                base,
+                effects: module_effects(&modules, registry),
+                modules,
+                supply_area_distance: prototype
+                    .supply_area_distance
+                    .unwrap_or(FBBeacon::<f64>::SUPPLY_AREA_DISTANCE),
+            })
+        }
+        /* modules are items, not entities */
+        EntityKind::Module => return Err(ImportError::UnsupportedEntity(base.name)),
+        EntityKind::Loader | EntityKind::Loader1x1 => FBEntity::Loader(FBLoader {
+            base,
+            belt_type: parse_belt_type(value)?,
+            compact: prototype.kind == EntityKind::Loader1x1,
+        }),
+        EntityKind::Container | EntityKind::InfinityContainer => FBEntity::Container(FBContainer {
+            base,
+            infinite: prototype.kind == EntityKind::InfinityContainer,
+        }),
+    };
+    Ok(entity)
+}
+
+/// Deserialization function for the attributes shared by each entity.
+///
+/// Directions are interpreted as in Factorio 1.1 blueprints.
 impl<'de> Deserialize<'de> for FBBaseEntity<f64> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         let value: Value = Deserialize::deserialize(deserializer)?;
-
-        let id = value
-            .get("entity_number")
-            .and_then(|v| serde_json::from_value(v.clone()).ok())
-            .ok_or(Error::missing_field("entity_number"))?;
-
-        let position: Position<f64> = value
-            .get("position")
-            .and_then(|v| serde_json::from_value(v.clone()).ok())
-            .ok_or(Error::missing_field("position"))?;
-
-        let direction = value
-            .get("direction")
-            .and_then(|v| serde_json::from_value(v.clone()).ok())
-            .unwrap_or(Direction::North);
-
-        let base = FBBaseEntity {
-            id,
-            position,
-            direction,
-            throughput: 0.0,
-        };
-        Ok(base)
+        parse_base_entity(&value, GameVersion::V1).map_err(Error::custom)
     }
 }
 
 /// Deserialization function turning each JSON string into a `FBEntity<f64>`.
+///
+/// Directions are interpreted as in Factorio 1.1 blueprints and only vanilla entities are supported.
 impl<'de> Deserialize<'de> for FBEntity<f64> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         let value: Value = Deserialize::deserialize(deserializer)?;
-
-        let name = value
-            .get("name")
-            .and_then(|v| v.as_str())
-            .ok_or(Error::missing_field("name"))?;
-
-        let mut base: FBBaseEntity<f64> = serde_json::from_value(value.clone())
-            .map_err(|_| Error::custom("Could not deserialize BaseEntity"))?;
-        base.throughput = if name.contains("express") {
-            45.0
-        } else if name.contains("fast") {
-            30.0
-        } else {
-            15.0
-        };
-
-        if name.contains("transport-belt") {
-            Ok(Self::Belt(FBBelt { base }))
-        } else if name.contains("underground-belt") {
-            let belt_type = value
-                .get("type")
-                .and_then(|v| serde_json::from_value(v.clone()).ok())
-                .ok_or(Error::missing_field("type"))?;
-
-            Ok(Self::Underground(FBUnderground { base, belt_type }))
-        } else if name.contains("splitter") {
-            let input_prio = value
-                .get("input_priority")
-                .and_then(|v| serde_json::from_value(v.clone()).ok())
-                .unwrap_or(Priority::None);
-
-            let output_prio = value
-                .get("output_priority")
-                .and_then(|v| serde_json::from_value(v.clone()).ok())
-                .unwrap_or(Priority::None);
-
-            Ok(Self::Splitter(FBSplitter {
-                base,
-                input_prio,
-                output_prio,
-            }))
-        } else if name.contains("inserter") {
-            if name.contains("long-handed") {
-                base.throughput = 1.2;
-                return Ok(Self::LongInserter(FBLongInserter { base }));
-            }
-            base.throughput = if name == "inserter" {
-                0.83
-            } else if name.contains("burner") {
-                0.6
-            } else {
-                2.31
-            };
-            Ok(Self::Inserter(FBInserter { base }))
-        } else if name.contains("assembling-machine") {
-            let tier = name
-                .strip_prefix("assembling-machine-")
-                .ok_or(Error::custom(
-                    "Error whilst deserializing assembling machine tier",
-                ))?;
-            base.throughput = match tier {
-                "1" => 0.5,
-                "2" => 0.75,
-                "3" => 1.25,
-                _ => panic!(),
-            };
-            Ok(Self::Assembler(FBAssembler { base }))
-        } else {
-            Err(format!("Invalid entity: ({})", name)).map_err(serde::de::Error::custom)
-        }
+        parse_entity(&value, GameVersion::V1, &PrototypeRegistry::vanilla()).map_err(Error::custom)
     }
 }
 
@@ -171,6 +522,20 @@ fn snap_to_grid(entities: &mut [FBEntity<f64>]) {
                 let dir = inserter.base.direction;
                 inserter.base.direction = dir.flip();
             }
+            /* snap loaders to the tile facing their belt, the one in front of them if they output items */
+            FBEntity::Loader(loader) if !loader.compact => {
+                let dir = match loader.belt_type {
+                    BeltType::Input => loader.base.direction.flip(),
+                    BeltType::Output => loader.base.direction,
+                };
+                /* in Factorio blueprints the y-axis is inverted */
+                let shift_dir = match dir {
+                    Direction::North => Direction::South,
+                    Direction::South => Direction::North,
+                    x => x,
+                };
+                loader.base.shift(shift_dir, 0.5);
+            }
             _ => (),
         }
     }
@@ -205,6 +570,7 @@ fn normalize_entities(entities: &[FBEntity<f64>]) -> Vec<FBEntity<i32>> {
                 id: base.id,
                 direction: base.direction,
                 throughput: base.throughput,
+                name: base.name.clone(),
             };
             match e {
                 FBEntity::Belt(_) => FBEntity::Belt(FBBelt { base }),
@@ -222,32 +588,161 @@ fn normalize_entities(entities: &[FBEntity<f64>]) -> Vec<FBEntity<i32>> {
                 }
                 FBEntity::Inserter(_) => FBEntity::Inserter(FBInserter { base }),
                 FBEntity::LongInserter(_) => FBEntity::LongInserter(FBLongInserter { base }),
-                FBEntity::Assembler(_) => FBEntity::Assembler(FBAssembler { base }),
+                FBEntity::Assembler(a) => FBEntity::Assembler(FBAssembler {
+                    base,
+                    recipe: a.recipe.clone(),
+                    modules: a.modules.clone(),
+                    effects: a.effects,
+                }),
                 FBEntity::AssemblerPhantom(_) => {
                     FBEntity::AssemblerPhantom(FBAssemblerPhantom { base })
                 }
+                FBEntity::Beacon(b) => FBEntity::Beacon(FBBeacon {
+                    base,
+                    modules: b.modules.clone(),
+                    supply_area_distance: b.supply_area_distance,
+                    effects: b.effects,
+                }),
+                FBEntity::Loader(l) => FBEntity::Loader(FBLoader {
+                    base,
+                    belt_type: l.belt_type,
+                    compact: l.compact,
+                }),
+                FBEntity::Container(c) => FBEntity::Container(FBContainer {
+                    base,
+                    infinite: c.infinite,
+                }),
             }
         })
         .collect()
 }
 
-/// Parses a blueprint string, as exported from Factorio, to a list of `FBEntity`s
-///
-/// Unsupported entities, like power poles, are skipped.
-pub fn string_to_entities(blueprint_string: &str) -> Result<Vec<FBEntity<i32>>> {
-    let json = decompress_string(blueprint_string)?;
-    let mut entities: Vec<_> = get_json_entities(json)?
-        .into_iter()
-        .flat_map(serde_json::from_value)
+/// Adds the effects of the beacons to the assemblers in their supply area.
+fn apply_beacons(entities: &mut [FBEntity<i32>], version: GameVersion) {
+    let beacons = entities
+        .iter()
+        .filter_map(|e| match e {
+            FBEntity::Beacon(b) => Some(b.clone()),
+            _ => None,
+        })
         .collect::<Vec<_>>();
+    for e in entities {
+        if let FBEntity::Assembler(a) = e {
+            let affecting = beacons.iter().filter(|b| b.affects(a)).collect::<Vec<_>>();
+            for beacon in &affecting {
+                let share = version.beacon_transmission(beacon.base.throughput, affecting.len());
+                a.effects = a.effects.combine(&beacon.effects.scale(share));
+            }
+        }
+    }
+}
+
+/// Sets the throughput of the inserters known to the `model`, based on what they pick up from and drop to.
+///
+/// Inserters picking up from or dropping to a tile without a belt, an assembler or a container keep the
+/// throughput of their prototype. The phantoms of splitters and assemblers have to be part of the `entities`.
+fn apply_inserter_model(
+    entities: &mut [FBEntity<i32>],
+    version: GameVersion,
+    model: &InserterModel,
+) {
+    let targets = entities
+        .iter()
+        .filter_map(|e| {
+            let kind = match e {
+                FBEntity::Belt(_)
+                | FBEntity::Underground(_)
+                | FBEntity::Splitter(_)
+                | FBEntity::SplitterPhantom(_)
+                | FBEntity::Loader(_) => TargetKind::Belt,
+                FBEntity::Assembler(_) | FBEntity::AssemblerPhantom(_) => TargetKind::Machine,
+                FBEntity::Container(_) => TargetKind::Chest,
+                FBEntity::Inserter(_) | FBEntity::LongInserter(_) | FBEntity::Beacon(_) => {
+                    return None
+                }
+            };
+            Some((e.get_base().position, kind))
+        })
+        .collect::<HashMap<_, _>>();
+
+    for e in entities {
+        let (pickup, drop, base) = match e {
+            FBEntity::Inserter(i) => (i.get_source(), i.get_destination(), &mut i.base),
+            FBEntity::LongInserter(i) => (i.get_source(), i.get_destination(), &mut i.base),
+            _ => continue,
+        };
+        let (Some(&pickup), Some(&drop)) = (targets.get(&pickup), targets.get(&drop)) else {
+            continue;
+        };
+        if let Some(throughput) = model.throughput(&base.name, version, pickup, drop) {
+            base.throughput = throughput;
+        }
+    }
+}
+
+/// A single blueprint contained in a blueprint string, possibly nested inside of blueprint books.
+#[derive(Debug)]
+pub struct ImportedBlueprint {
+    /// Label of the blueprint, if it has one
+    pub label: Option<String>,
+    /// Index of the blueprint inside of its book, with one element per level of nesting.
+    ///
+    /// Empty if the blueprint string is a single blueprint.
+    pub index: Vec<usize>,
+    /// Entities of the blueprint
+    pub entities: Vec<FBEntity<i32>>,
+    /// Entities of the blueprint that were skipped
+    pub report: ImportReport,
+}
+
+/// Parses the JSON entities, skipping the ones that can't be imported and adding them to the report.
+fn parse_entities(
+    json_entities: &[Value],
+    version: GameVersion,
+    registry: &PrototypeRegistry,
+    report: &mut ImportReport,
+) -> Vec<FBEntity<f64>> {
+    let mut entities = vec![];
+    for value in json_entities {
+        match parse_entity(value, version, registry) {
+            Ok(entity) => entities.push(entity),
+            Err(reason) => {
+                let name = value.get("name").and_then(|v| v.as_str()).map(String::from);
+                let entity_number = value
+                    .get("entity_number")
+                    .and_then(|v| v.as_i64())
+                    .map(|id| id as EntityId);
+                report.skipped.push(SkippedEntity {
+                    name,
+                    entity_number,
+                    reason,
+                });
+            }
+        }
+    }
+    entities
+}
+
+/// Converts the JSON representation of a single blueprint to a list of `FBEntity`s.
+///
+/// Both Factorio 1.1 and 2.0 blueprints are supported, based on the `version` of the blueprint.
+fn json_to_entities(
+    blueprint: &Value,
+    options: &ImportOptions,
+) -> Result<(Vec<FBEntity<i32>>, ImportReport), ImportError> {
+    let mut report = ImportReport::default();
+    let version = get_game_version(blueprint)?;
+    let json_entities = get_json_entities(blueprint)?;
+    let mut entities = parse_entities(&json_entities, version, &options.registry, &mut report);
 
     snap_to_grid(&mut entities);
     let mut entities = normalize_entities(&entities);
+    apply_beacons(&mut entities, version);
 
     // add splitter phantoms
     let phantoms = entities
         .iter()
-        .filter_map(|&e| match e {
+        .filter_map(|e| match e {
             FBEntity::Splitter(s) => Some(FBEntity::SplitterPhantom(s.get_phantom())),
             _ => None,
         })
@@ -257,7 +752,7 @@ pub fn string_to_entities(blueprint_string: &str) -> Result<Vec<FBEntity<i32>>>
     // add assembler phantoms
     let phantoms = entities
         .iter()
-        .filter_map(|&e| match e {
+        .filter_map(|e| match e {
             FBEntity::Assembler(a) => Some(a.get_phantoms()),
             _ => None,
         })
@@ -265,17 +760,146 @@ pub fn string_to_entities(blueprint_string: &str) -> Result<Vec<FBEntity<i32>>>
         .map(FBEntity::AssemblerPhantom)
         .collect::<Vec<_>>();
     entities.extend(phantoms);
-    Ok(entities)
+
+    if let Some(model) = &options.inserters {
+        apply_inserter_model(&mut entities, version, model);
+    }
+    Ok((entities, report))
+}
+
+/// Recursively walks a JSON object containing either a `blueprint` or a `blueprint_book`,
+/// collecting every blueprint found in `blueprints`.
+///
+/// Other objects that can be part of a book, like upgrade or deconstruction planners, are skipped.
+fn collect_blueprints(
+    json: &Value,
+    index: Vec<usize>,
+    options: &ImportOptions,
+    blueprints: &mut Vec<ImportedBlueprint>,
+) -> Result<(), ImportError> {
+    if let Some(blueprint) = json.get("blueprint") {
+        let label = blueprint
+            .get("label")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        let (entities, report) = match json_to_entities(blueprint, options) {
+            Ok(res) => res,
+            Err(error) if !index.is_empty() => {
+                return Err(ImportError::InBook {
+                    index,
+                    error: Box::new(error),
+                })
+            }
+            Err(error) => return Err(error),
+        };
+        blueprints.push(ImportedBlueprint {
+            label,
+            index,
+            entities,
+            report,
+        });
+    } else if let Some(book) = json.get("blueprint_book") {
+        /* empty books don't have a blueprints key */
+        let entries = match book.get("blueprints") {
+            Some(entries) => entries.as_array().ok_or(ImportError::InvalidValue {
+                key: "blueprints",
+                expected: "an array",
+            })?,
+            None => return Ok(()),
+        };
+        for (position, entry) in entries.iter().enumerate() {
+            let entry_index = entry
+                .get("index")
+                .and_then(|v| v.as_u64())
+                .map(|i| i as usize)
+                .unwrap_or(position);
+            let mut index = index.clone();
+            index.push(entry_index);
+            collect_blueprints(entry, index, options, blueprints)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses a blueprint string, as exported from Factorio 1.1 or 2.0, to a list of `FBEntity`s
+///
+/// Unsupported entities, like power poles, are skipped.
+/// Use [`string_to_entities_with_report`] to know which entities were skipped.
+pub fn string_to_entities(blueprint_string: &str) -> Result<Vec<FBEntity<i32>>, ImportError> {
+    string_to_entities_with_report(blueprint_string).map(|(entities, _)| entities)
+}
+
+/// Parses a blueprint string, as exported from Factorio, to a list of `FBEntity`s.
+///
+/// Additionally returns an [`ImportReport`] listing all the entities that were skipped and why.
+pub fn string_to_entities_with_report(
+    blueprint_string: &str,
+) -> Result<(Vec<FBEntity<i32>>, ImportReport), ImportError> {
+    string_to_entities_with_options(blueprint_string, &ImportOptions::default())
+}
+
+/// Parses a blueprint string to a list of `FBEntity`s and an [`ImportReport`], using the given options.
+///
+/// Use this function to import blueprints containing modded entities.
+pub fn string_to_entities_with_options(
+    blueprint_string: &str,
+    options: &ImportOptions,
+) -> Result<(Vec<FBEntity<i32>>, ImportReport), ImportError> {
+    let json = decompress_string(blueprint_string)?;
+    let blueprint = json
+        .get("blueprint")
+        .ok_or(ImportError::MissingKey("blueprint"))?;
+    json_to_entities(blueprint, options)
 }
 
 /// Parses a file containing a blueprint string to a list of `FBEntity`s.
 ///
 /// Unsupported entities, like power poles, are skipped.
-pub fn file_to_entities(file: &str) -> Result<Vec<FBEntity<i32>>> {
+pub fn file_to_entities(file: &str) -> Result<Vec<FBEntity<i32>>, ImportError> {
     let blueprint_string = fs::read_to_string(file)?;
     string_to_entities(&blueprint_string)
 }
 
+/// Parses a file containing a blueprint string to a list of `FBEntity`s and an [`ImportReport`].
+pub fn file_to_entities_with_report(
+    file: &str,
+) -> Result<(Vec<FBEntity<i32>>, ImportReport), ImportError> {
+    let blueprint_string = fs::read_to_string(file)?;
+    string_to_entities_with_report(&blueprint_string)
+}
+
+/// Parses a blueprint string containing either a single blueprint or a blueprint book.
+///
+/// Every blueprint of the book, including the ones inside of nested books, is returned in order.
+/// A single blueprint results in a list with a single element.
+pub fn string_to_blueprints(blueprint_string: &str) -> Result<Vec<ImportedBlueprint>, ImportError> {
+    string_to_blueprints_with_options(blueprint_string, &ImportOptions::default())
+}
+
+/// Parses a blueprint string containing either a single blueprint or a blueprint book, using the given options.
+///
+/// See [`string_to_blueprints`].
+pub fn string_to_blueprints_with_options(
+    blueprint_string: &str,
+    options: &ImportOptions,
+) -> Result<Vec<ImportedBlueprint>, ImportError> {
+    let json = decompress_string(blueprint_string)?;
+    if json.get("blueprint").is_none() && json.get("blueprint_book").is_none() {
+        return Err(ImportError::MissingKey("blueprint or blueprint_book"));
+    }
+    let mut blueprints = vec![];
+    collect_blueprints(&json, vec![], options, &mut blueprints)?;
+    Ok(blueprints)
+}
+
+/// Parses a file containing a blueprint or blueprint book string to a list of blueprints.
+///
+/// See [`string_to_blueprints`].
+pub fn file_to_blueprints(file: &str) -> Result<Vec<ImportedBlueprint>, ImportError> {
+    let blueprint_string = fs::read_to_string(file)?;
+    string_to_blueprints(&blueprint_string)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -377,6 +1001,107 @@ mod tests {
         }
     }
 
+    #[test]
+    fn loaders_and_chests() {
+        let entities = file_to_entities("tests/test_bench").unwrap();
+        let position = |id| {
+            entities
+                .iter()
+                .find(|e| e.get_base().id == id)
+                .unwrap()
+                .get_base()
+                .position
+        };
+        /* loaders are on the tile facing their belt */
+        assert_eq!(position(1).shift(Direction::East, 1), position(2));
+        assert_eq!(position(4).shift(Direction::East, 1), position(5));
+        let loaders = entities
+            .iter()
+            .filter_map(|e| match e {
+                FBEntity::Loader(l) => Some((l.belt_type, l.compact, l.base.throughput)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(loaders.len(), 2);
+        assert!(loaders.contains(&(BeltType::Output, false, 15.0)));
+        assert!(loaders.contains(&(BeltType::Input, false, 15.0)));
+
+        let chests = entities
+            .iter()
+            .filter_map(|e| match e {
+                FBEntity::Container(c) => Some((c.base.id, c.infinite)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(chests.len(), 2);
+        assert!(chests.contains(&(6, true)));
+        assert!(chests.contains(&(10, false)));
+    }
+
+    #[test]
+    fn inserter_model() {
+        let blueprint_string = fs::read_to_string("tests/gear_column").unwrap();
+        let throughputs = |options: &ImportOptions| {
+            string_to_entities_with_options(&blueprint_string, options)
+                .unwrap()
+                .0
+                .iter()
+                .filter_map(|e| match e {
+                    FBEntity::Inserter(i) => Some(i.base.throughput),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut options = ImportOptions::default();
+        assert_eq!(throughputs(&options), [2.31; 2]);
+
+        /* a single item every 26 ticks */
+        options.inserters = Some(InserterModel::default());
+        assert_eq!(throughputs(&options), [60.0 / 26.0; 2]);
+
+        /* 3 items per swing, 2 more ticks to take them from the belt */
+        let mut model = InserterModel::default();
+        model.capacity_bonus = 7;
+        options.inserters = Some(model);
+        assert_eq!(throughputs(&options), [180.0 / 28.0; 2]);
+    }
+
+    #[test]
+    fn inserter_model_targets() {
+        let base = |id, y, name: &str| FBBaseEntity {
+            id,
+            position: Position { x: 2, y },
+            direction: Direction::North,
+            throughput: 0.83,
+            name: name.to_owned(),
+        };
+        let inserter = FBEntity::Inserter(FBInserter {
+            base: base(1, 3, "inserter"),
+        });
+        let chest = |id, y| {
+            FBEntity::Container(FBContainer {
+                base: base(id, y, "wooden-chest"),
+                infinite: false,
+            })
+        };
+        let throughput = |entities: &mut [FBEntity<i32>]| {
+            apply_inserter_model(entities, GameVersion::V1, &InserterModel::default());
+            entities[0].get_base().throughput
+        };
+
+        /* nothing to pick up from, the prototype is kept */
+        let mut entities = vec![inserter.clone(), chest(2, 4)];
+        assert_eq!(throughput(&mut entities), 0.83);
+        let mut entities = vec![inserter, chest(2, 2), chest(3, 4)];
+        let chests = InserterModel::default().throughput(
+            "inserter",
+            GameVersion::V1,
+            TargetKind::Chest,
+            TargetKind::Chest,
+        );
+        assert_eq!(Some(throughput(&mut entities)), chests);
+    }
+
     #[test]
     fn assembler() {
         let entities = get_assembly_entities();
@@ -388,4 +1113,246 @@ mod tests {
         println!("{:?}", &entities);
         assert_eq!(entities.len(), 9 + 3);
     }
+
+    #[test]
+    fn assembler_recipe() {
+        for e in get_assembly_entities() {
+            if let FBEntity::Assembler(a) = e {
+                assert_eq!(a.recipe, None);
+            }
+        }
+        let entities = file_to_entities("tests/gear_column").unwrap();
+        let recipes = entities
+            .iter()
+            .filter_map(|e| match e {
+                FBEntity::Assembler(a) => a.recipe.as_deref(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(recipes, ["iron-gear-wheel"; 2]);
+    }
+
+    #[test]
+    fn modules_and_beacons() {
+        let entities = file_to_entities("tests/moduled_column").unwrap();
+        let assembler = |id| {
+            entities
+                .iter()
+                .find_map(|e| match e {
+                    FBEntity::Assembler(a) if a.base.id == id => Some(a),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        /* two speed modules 3 and a beacon with two more, at half effectivity */
+        let sped_up = assembler(9);
+        assert_eq!(sped_up.effects.speed, 1.5);
+        assert_eq!(sped_up.crafting_speed(), 1.875);
+        assert_eq!(sped_up.base.throughput, 0.75);
+        /* two productivity modules 3, out of reach of the beacon */
+        let productive = assembler(10);
+        assert!((productive.crafting_speed() - 0.525).abs() < 1e-9);
+        assert!((productive.effects.productivity_multiplier() - 1.2).abs() < 1e-9);
+        assert!(entities.iter().any(|e| matches!(e, FBEntity::Beacon(_))));
+    }
+
+    #[test]
+    fn modules_v2() {
+        let registry = PrototypeRegistry::vanilla();
+        let module = |name: &str, slots: usize| {
+            serde_json::json!({
+                "id": {"name": name, "quality": "normal"},
+                "items": {"in_inventory": vec![serde_json::json!({"inventory": 4}); slots]}
+            })
+        };
+        let value = serde_json::json!({
+            "items": [module("speed-module-2", 1), module("productivity-module", 2), module("coal", 5)]
+        });
+        let modules = parse_modules(&value, &registry).unwrap();
+        assert_eq!(
+            modules,
+            [
+                "speed-module-2",
+                "productivity-module",
+                "productivity-module"
+            ]
+        );
+        let effects = module_effects(&modules, &registry);
+        assert!((effects.speed - 0.2).abs() < 1e-9);
+        assert!((effects.productivity - 0.08).abs() < 1e-9);
+
+        let invalid = serde_json::json!({"items": 3});
+        assert!(parse_modules(&invalid, &registry).is_err());
+
+        /* 2.0 beacons have diminishing returns */
+        assert_eq!(GameVersion::V1.beacon_transmission(0.5, 4), 0.5);
+        assert_eq!(GameVersion::V2.beacon_transmission(1.5, 4), 0.75);
+    }
+
+    #[test]
+    fn skipped_entities() {
+        let (entities, report) = file_to_entities_with_report("tests/skipped_entities").unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(report.skipped.len(), 4);
+
+        fn reason(report: &ImportReport, id: EntityId) -> &ImportError {
+            &report
+                .skipped
+                .iter()
+                .find(|s| s.entity_number == Some(id))
+                .unwrap()
+                .reason
+        }
+        assert!(matches!(
+            reason(&report, 3),
+            ImportError::UnsupportedEntity(_)
+        ));
+        assert!(matches!(reason(&report, 4), ImportError::UnknownTier(_)));
+        assert!(matches!(
+            reason(&report, 5),
+            ImportError::NonCardinalDirection(1)
+        ));
+        assert!(matches!(
+            reason(&report, 6),
+            ImportError::MissingKey("position")
+        ));
+
+        let pole = report
+            .skipped
+            .iter()
+            .find(|s| s.entity_number == Some(3))
+            .unwrap();
+        assert_eq!(pole.name.as_deref(), Some("small-electric-pole"));
+    }
+
+    #[test]
+    fn malformed_strings() {
+        assert!(matches!(
+            string_to_entities(""),
+            Err(ImportError::EmptyString)
+        ));
+        assert!(matches!(
+            string_to_entities("0not base64!"),
+            Err(ImportError::Base64(_))
+        ));
+        /* valid base64 but not zlib compressed */
+        assert!(matches!(
+            string_to_entities("0aGVsbG8gd29ybGQ="),
+            Err(ImportError::Zlib(_))
+        ));
+        let book = fs::read_to_string("tests/book").unwrap();
+        assert!(matches!(
+            string_to_entities(&book),
+            Err(ImportError::MissingKey("blueprint"))
+        ));
+    }
+
+    #[test]
+    fn space_age_blueprint() {
+        let entities = file_to_entities("tests/space_age").unwrap();
+        /* 9 entities and a splitter phantom */
+        assert_eq!(entities.len(), 10);
+
+        let turbo = entities
+            .iter()
+            .filter(|e| e.get_base().throughput == 60.0)
+            .count();
+        assert_eq!(turbo, 7);
+
+        let direction = |id| {
+            entities
+                .iter()
+                .find(|e| e.get_base().id == id)
+                .unwrap()
+                .get_base()
+                .direction
+        };
+        assert_eq!(direction(1), Direction::East);
+        assert_eq!(direction(7), Direction::West);
+        assert_eq!(direction(9), Direction::South);
+    }
+
+    #[test]
+    fn game_version_directions() {
+        assert_eq!(
+            GameVersion::from_blueprint_version(281479275675648),
+            GameVersion::V1
+        );
+        assert_eq!(
+            GameVersion::from_blueprint_version(562949955256320),
+            GameVersion::V2
+        );
+        assert_eq!(
+            GameVersion::V2.decode_direction(12).unwrap(),
+            Direction::West
+        );
+        assert!(matches!(
+            GameVersion::V2.decode_direction(2),
+            Err(ImportError::NonCardinalDirection(2))
+        ));
+        for dir in [
+            Direction::North,
+            Direction::East,
+            Direction::South,
+            Direction::West,
+        ] {
+            for version in [GameVersion::V1, GameVersion::V2] {
+                let encoded = version.encode_direction(dir) as u64;
+                assert_eq!(version.decode_direction(encoded).unwrap(), dir);
+            }
+        }
+    }
+
+    #[test]
+    fn modded_entities() {
+        let blueprint_string = fs::read_to_string("tests/modded_belts").unwrap();
+        let (entities, report) = string_to_entities_with_report(&blueprint_string).unwrap();
+        assert!(entities.is_empty());
+        assert_eq!(report.skipped.len(), 6);
+        assert!(report
+            .skipped
+            .iter()
+            .all(|s| matches!(s.reason, ImportError::UnknownTier(_))));
+
+        let mut options = ImportOptions::default();
+        let modded = PrototypeRegistry::from_file("tests/modded_prototypes.json").unwrap();
+        options.registry.extend(modded);
+        let (entities, report) =
+            string_to_entities_with_options(&blueprint_string, &options).unwrap();
+        assert!(report.is_empty());
+        assert_eq!(entities.len(), 6);
+        for e in &entities {
+            let expected = if matches!(e.get_base().id, 5 | 6) {
+                120.0
+            } else {
+                90.0
+            };
+            assert_eq!(e.get_base().throughput, expected);
+        }
+    }
+
+    #[test]
+    fn blueprint_book() {
+        let blueprints = file_to_blueprints("tests/book").unwrap();
+        assert_eq!(blueprints.len(), 2);
+
+        assert_eq!(blueprints[0].label.as_deref(), Some("simple belt"));
+        assert_eq!(blueprints[0].index, vec![0]);
+        assert_eq!(blueprints[0].entities.len(), 3);
+
+        /* nested book */
+        assert_eq!(
+            blueprints[1].label.as_deref(),
+            Some("4-4 throughput unlimited")
+        );
+        assert_eq!(blueprints[1].index, vec![1, 0]);
+    }
+
+    #[test]
+    fn single_blueprint_as_book() {
+        let blueprints = file_to_blueprints("tests/belts").unwrap();
+        assert_eq!(blueprints.len(), 1);
+        assert!(blueprints[0].index.is_empty());
+        assert_eq!(blueprints[0].entities.len(), get_belt_entities().len());
+    }
 }
@@ -0,0 +1,223 @@
+//! Throughput of inserters, depending on their research bonuses and on what they move items between.
+//!
+//! An inserter needs half a turn to swing from its pickup target to its drop target, and as long to swing back.
+//! At each swing it moves as many items as its hand holds, which grows with the inserter capacity bonus research.
+//! Chests and machines hand over all the items at once, whereas items are taken from and put on belts
+//! one stack at a time. A stack is a single item, except for the stack inserters of Factorio 2.0 which
+//! stack items on belts, up to the belt stack size researched.
+
+use std::collections::HashMap;
+
+use crate::import::GameVersion;
+
+/// Game ticks per second
+const TICKS_PER_SECOND: f64 = 60.0;
+
+/// Ticks needed to take or put a single stack of items on a belt
+const TICKS_PER_BELT_STACK: f64 = 1.0;
+
+/// Hand size of regular inserters, at each level of the inserter capacity bonus research
+const REGULAR_HAND_SIZES: [u32; 8] = [1, 1, 2, 2, 2, 2, 2, 3];
+
+/// Hand size of bulk and stack inserters, at each level of the inserter capacity bonus research
+const BULK_HAND_SIZES: [u32; 8] = [1, 2, 3, 4, 5, 7, 9, 12];
+
+/// Largest number of items in a stack on a belt
+const MAX_BELT_STACK_SIZE: u32 = 4;
+
+/// Vanilla inserters, their rotation speed in turns per tick and their hand
+const INSERTERS: [(&str, f64, HandKind); 8] = [
+    ("burner-inserter", 0.01, HandKind::Regular),
+    ("inserter", 0.014, HandKind::Regular),
+    ("long-handed-inserter", 0.02, HandKind::Regular),
+    ("fast-inserter", 0.04, HandKind::Regular),
+    ("filter-inserter", 0.04, HandKind::Regular),
+    ("stack-inserter", 0.04, HandKind::Stack),
+    ("stack-filter-inserter", 0.04, HandKind::Bulk),
+    ("bulk-inserter", 0.04, HandKind::Bulk),
+];
+
+/// Kind of entity an inserter picks items up from or drops them to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetKind {
+    Belt,
+    /// Chests, and any other entity holding items, like wagons
+    Chest,
+    /// Assemblers, which exchange items like chests do
+    Machine,
+}
+
+/// How the hand size of an inserter grows with the inserter capacity bonus research
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandKind {
+    /// Inserters carrying up to 3 items
+    Regular,
+    /// Bulk inserters, named stack inserters in Factorio 1.1, carrying up to 12 items
+    Bulk,
+    /// Stack inserters of Factorio 2.0, which carry as many items as bulk inserters and stack them on belts.
+    /// The stack inserters of Factorio 1.1 share their name but behave like bulk inserters.
+    Stack,
+}
+
+/// Properties of an inserter type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InserterStats {
+    /// Turns per tick
+    pub rotation_speed: f64,
+    pub hand: HandKind,
+}
+
+/// Computes the throughput of inserters, see the [module documentation](self)
+///
+/// The default model contains the vanilla inserters, without any research.
+#[derive(Debug, Clone)]
+pub struct InserterModel {
+    inserters: HashMap<String, InserterStats>,
+    /// Level of the inserter capacity bonus research, from 0 to 7
+    pub capacity_bonus: u32,
+    /// Items per stack put on belts by the stack inserters of Factorio 2.0, from 1 to 4
+    pub belt_stack_size: u32,
+}
+
+impl Default for InserterModel {
+    fn default() -> Self {
+        Self::vanilla()
+    }
+}
+
+impl InserterModel {
+    /// Creates a model containing the vanilla inserters, without any research.
+    pub fn vanilla() -> Self {
+        let inserters = INSERTERS
+            .into_iter()
+            .map(|(name, rotation_speed, hand)| {
+                let stats = InserterStats {
+                    rotation_speed,
+                    hand,
+                };
+                (name.to_owned(), stats)
+            })
+            .collect();
+        Self {
+            inserters,
+            capacity_bonus: 0,
+            belt_stack_size: 1,
+        }
+    }
+
+    /// Adds an inserter type, replacing the one with the same name.
+    pub fn insert(&mut self, name: String, stats: InserterStats) {
+        self.inserters.insert(name, stats);
+    }
+
+    /// Returns the properties of the inserter type with the given name.
+    pub fn get(&self, name: &str) -> Option<&InserterStats> {
+        self.inserters.get(name)
+    }
+
+    /// Returns the number of items moved at each swing by an inserter with the given hand.
+    pub fn hand_size(&self, hand: HandKind) -> u32 {
+        let level = self.capacity_bonus.min(7) as usize;
+        match hand {
+            HandKind::Regular => REGULAR_HAND_SIZES[level],
+            HandKind::Bulk | HandKind::Stack => BULK_HAND_SIZES[level],
+        }
+    }
+
+    /// Returns the items per second moved by an inserter of the type `name`, `None` if the type is unknown.
+    pub fn throughput(
+        &self,
+        name: &str,
+        version: GameVersion,
+        pickup: TargetKind,
+        drop: TargetKind,
+    ) -> Option<f64> {
+        let stats = self.get(name)?;
+        let hand_size = self.hand_size(stats.hand);
+        let stack_size = match (stats.hand, version) {
+            (HandKind::Stack, GameVersion::V2) => {
+                self.belt_stack_size.clamp(1, MAX_BELT_STACK_SIZE)
+            }
+            _ => 1,
+        };
+        let belt_stacks = hand_size.div_ceil(stack_size) as f64;
+
+        /* half a turn to reach each of the targets */
+        let mut ticks = 2.0 * (0.5 / stats.rotation_speed).ceil();
+        /* the first stack is taken or put as the hand arrives */
+        for target in [pickup, drop] {
+            if target == TargetKind::Belt {
+                ticks += (belt_stacks - 1.0) * TICKS_PER_BELT_STACK;
+            }
+        }
+        Some(hand_size as f64 * TICKS_PER_SECOND / ticks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vanilla_inserters() {
+        let model = InserterModel::default();
+        let chests = |name| {
+            model
+                .throughput(name, GameVersion::V1, TargetKind::Chest, TargetKind::Chest)
+                .unwrap()
+        };
+        /* the throughputs of the prototypes, rounded down */
+        assert_eq!(chests("burner-inserter"), 0.6);
+        assert_eq!(chests("long-handed-inserter"), 1.2);
+        assert_eq!((chests("inserter") * 100.0).floor(), 83.0);
+        assert_eq!((chests("fast-inserter") * 100.0).floor(), 230.0);
+        assert!(model
+            .throughput(
+                "small-electric-pole",
+                GameVersion::V1,
+                TargetKind::Belt,
+                TargetKind::Chest
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn capacity_bonus() {
+        let mut model = InserterModel::vanilla();
+        model.capacity_bonus = 7;
+        assert_eq!(model.hand_size(HandKind::Regular), 3);
+        assert_eq!(model.hand_size(HandKind::Bulk), 12);
+
+        let bulk = |pickup, drop| {
+            model
+                .throughput("bulk-inserter", GameVersion::V2, pickup, drop)
+                .unwrap()
+        };
+        /* 12 items every 26 ticks */
+        let chest_to_chest = bulk(TargetKind::Chest, TargetKind::Machine);
+        assert!((chest_to_chest - 12.0 * 60.0 / 26.0).abs() < 1e-9);
+        /* 11 more ticks to take the items from a belt, and as many to put them on one */
+        assert!((bulk(TargetKind::Belt, TargetKind::Chest) - 12.0 * 60.0 / 37.0).abs() < 1e-9);
+        assert!((bulk(TargetKind::Belt, TargetKind::Belt) - 12.0 * 60.0 / 48.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn belt_stacking() {
+        let mut model = InserterModel::vanilla();
+        model.capacity_bonus = 7;
+        model.belt_stack_size = 4;
+        let stack = |version| {
+            model
+                .throughput(
+                    "stack-inserter",
+                    version,
+                    TargetKind::Chest,
+                    TargetKind::Belt,
+                )
+                .unwrap()
+        };
+        /* 3 stacks of 4 items in 2.0, 12 single items in 1.1 */
+        assert!((stack(GameVersion::V2) - 12.0 * 60.0 / 28.0).abs() < 1e-9);
+        assert!((stack(GameVersion::V1) - 12.0 * 60.0 / 37.0).abs() < 1e-9);
+    }
+}
@@ -351,7 +351,10 @@ mod test {
         graph.simplify(&[], Aggressive);
         assert_eq!(graph.node_count(), 2);
         assert_eq!(graph.edge_count(), 1);
-        assert_eq!(graph.edge_weights().next().unwrap().capacity, 15.into());
+        let edge = graph.edge_weights().next().unwrap();
+        assert_eq!(edge.capacity, 15.into());
+        /* the remaining edge still knows about all the belts */
+        assert_eq!(edge.entities.len(), 3);
     }
 
     #[test]
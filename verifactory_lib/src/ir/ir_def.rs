@@ -4,6 +4,7 @@ use crate::{entities::EntityId, utils::Side};
 use petgraph::prelude::{EdgeIndex, NodeIndex};
 use petgraph::visit::EdgeRef;
 use petgraph::Direction::{Incoming, Outgoing};
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 
 #[derive(Debug, Clone)]
@@ -28,6 +29,10 @@ pub enum Node {
     ///
     /// Element with in_deg = 1 and out_deg = 0
     Output(Output),
+    /// See [`Craft`]
+    ///
+    /// Element with any in_deg and out_deg
+    Craft(Craft),
 }
 
 impl Node {
@@ -38,18 +43,20 @@ impl Node {
             Node::Merger(m) => m.id,
             Node::Output(o) => o.id,
             Node::Splitter(s) => s.id,
+            Node::Craft(c) => c.id,
         }
     }
 
     pub fn get_str(&self) -> String {
-        let prefix = match self {
-            Node::Connector(_) => "c",
-            Node::Input(_) => "i",
-            Node::Merger(_) => "m",
-            Node::Output(_) => "o",
-            Node::Splitter(_) => "s",
+        let (prefix, lane) = match self {
+            Node::Connector(_) => ("c", Side::None),
+            Node::Input(i) => ("i", i.lane),
+            Node::Merger(_) => ("m", Side::None),
+            Node::Output(o) => ("o", o.lane),
+            Node::Splitter(_) => ("s", Side::None),
+            Node::Craft(_) => ("a", Side::None),
         };
-        format!("{}{}", prefix, self.get_id())
+        format!("{}{}{}", prefix, self.get_id(), lane.lane_suffix())
     }
 }
 
@@ -79,6 +86,8 @@ pub struct Connector {
 pub struct Input {
     /// What entity this connector corresponds to
     pub id: EntityId,
+    /// The lane of the belt this input corresponds to, `Side::None` if lanes are not modelled
+    pub lane: Side,
 }
 
 /// A node that has no outgoing edges
@@ -86,6 +95,8 @@ pub struct Input {
 pub struct Output {
     /// What entity this connector corresponds to
     pub id: EntityId,
+    /// The lane of the belt this output corresponds to, `Side::None` if lanes are not modelled
+    pub lane: Side,
 }
 
 /// Element that splits a single input into two outputs, optionally prioritizing one side.
@@ -96,6 +107,25 @@ pub struct Splitter {
     pub id: EntityId,
 }
 
+/// An assembler turning ingredients into products following a recipe.
+///
+/// The inbound edges carry the ingredients and the outbound edges the products.
+/// Per item type, the flow of each ingredient and product is its amount per craft times the crafts per second,
+/// see [`crate::backends::max_production`]. Models of a single item type leave the edges of a craft free.
+#[derive(Debug, Clone)]
+pub struct Craft {
+    /// What entity this corresponds to
+    pub id: EntityId,
+    /// Name of the recipe
+    pub recipe: String,
+    /// Crafts per second at full speed
+    pub max_crafts: GenericFraction<u128>,
+    /// Amount of each item consumed per craft
+    pub ingredients: Vec<(String, GenericFraction<u128>)>,
+    /// Amount of each item produced per craft
+    pub products: Vec<(String, GenericFraction<u128>)>,
+}
+
 pub trait Lattice {
     /// Compute the meet operation of two elements of a lattice
     ///
@@ -136,7 +166,7 @@ impl Lattice for Side {
 }
 
 /// An edge connecting two nodes
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Edge {
     /// The side this edge corresponds to, if applicable. E.g. a belt's left or right side.
     pub side: Side,
@@ -145,6 +175,19 @@ pub struct Edge {
     /// For example, if this represents a line of belts, the capacity is the min capacity
     /// of all belts in the line.
     pub capacity: GenericFraction<u128>,
+    /// Entities whose whole throughput flows through this edge
+    ///
+    /// For example, if this represents a line of belts, all the belts of the line.
+    /// Edges linking two entities, or the sides of a splitter, don't carry any entity.
+    pub entities: Vec<EntityId>,
+}
+
+impl Edge {
+    /// Returns the entities of both edges, sorted and without duplicates.
+    fn merge_entities(&self, other: &Self) -> Vec<EntityId> {
+        let entities: BTreeSet<_> = self.entities.iter().chain(&other.entities).collect();
+        entities.into_iter().copied().collect()
+    }
 }
 
 impl Debug for Edge {
@@ -162,14 +205,24 @@ impl Lattice for Edge {
     fn meet(&self, other: &Self) -> Self {
         let side = self.side.meet(&other.side);
         let capacity = self.capacity.min(other.capacity);
-        Self { side, capacity }
+        let entities = self.merge_entities(other);
+        Self {
+            side,
+            capacity,
+            entities,
+        }
     }
 
     fn join(&self, other: &Self) -> Self {
         let side = self.side.join(&other.side);
         /* should be max but we don't want this kind of join */
         let capacity = self.capacity.min(other.capacity);
-        Self { side, capacity }
+        let entities = self.merge_entities(other);
+        Self {
+            side,
+            capacity,
+            entities,
+        }
     }
 
     fn can_join(&self, other: &Self) -> bool {
@@ -0,0 +1,264 @@
+//! Minimum cut of a [`FlowGraph`], locating the edges that limit its throughput.
+
+use std::collections::VecDeque;
+
+use fraction::GenericFraction;
+use petgraph::prelude::EdgeIndex;
+
+use super::{FlowGraph, Node};
+use crate::entities::EntityId;
+
+/// Edges separating the inputs from the outputs whose total capacity is the smallest, see [`min_cut`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinCut {
+    /// Total capacity of the cut edges, i.e. the maximum flow if splitters could route items freely
+    pub capacity: GenericFraction<u128>,
+    /// Cut edges, sorted
+    pub edges: Vec<EdgeIndex>,
+    /// Entities of the cut edges, sorted
+    ///
+    /// These are the entities carried by the edges, see [`super::Edge::entities`].
+    /// Edges carrying none, e.g. the sides of a splitter, contribute the entities of their endpoints.
+    pub entities: Vec<EntityId>,
+}
+
+/// Arc of the residual network, either an edge of the graph or one linking the source or the sink
+struct Arc {
+    from: usize,
+    to: usize,
+    capacity: GenericFraction<u128>,
+    flow: GenericFraction<u128>,
+}
+
+/// Residual network used by the Edmonds-Karp algorithm
+struct Network {
+    arcs: Vec<Arc>,
+    /// Arcs leaving or entering each node, with `true` for the ones leaving it
+    adjacent: Vec<Vec<(usize, bool)>>,
+}
+
+impl Network {
+    fn new(node_count: usize) -> Self {
+        Self {
+            arcs: vec![],
+            adjacent: (0..node_count).map(|_| vec![]).collect(),
+        }
+    }
+
+    fn add_arc(&mut self, from: usize, to: usize, capacity: GenericFraction<u128>) {
+        self.adjacent[from].push((self.arcs.len(), true));
+        self.adjacent[to].push((self.arcs.len(), false));
+        self.arcs.push(Arc {
+            from,
+            to,
+            capacity,
+            flow: 0.into(),
+        });
+    }
+
+    /// Returns the capacity left on an arc in the given direction.
+    fn residual(&self, arc: usize, forward: bool) -> GenericFraction<u128> {
+        let arc = &self.arcs[arc];
+        if forward {
+            arc.capacity - arc.flow
+        } else {
+            arc.flow
+        }
+    }
+
+    /// Finds a shortest path with capacity left, returning the arc used to reach each node of the search.
+    fn search(&self, source: usize) -> Vec<Option<(usize, bool)>> {
+        let mut parent = vec![None; self.adjacent.len()];
+        let mut visited = vec![false; self.adjacent.len()];
+        let mut queue = VecDeque::from([source]);
+        visited[source] = true;
+        while let Some(node) = queue.pop_front() {
+            for &(arc, forward) in &self.adjacent[node] {
+                let next = if forward {
+                    self.arcs[arc].to
+                } else {
+                    self.arcs[arc].from
+                };
+                if !visited[next] && self.residual(arc, forward) > GenericFraction::from(0) {
+                    visited[next] = true;
+                    parent[next] = Some((arc, forward));
+                    queue.push_back(next);
+                }
+            }
+        }
+        parent
+    }
+
+    /// Saturates the network with augmenting paths, returning the nodes still reachable from the source.
+    fn max_flow(&mut self, source: usize, sink: usize) -> Vec<bool> {
+        loop {
+            let parent = self.search(source);
+            if parent[sink].is_none() {
+                let mut reachable = parent.iter().map(Option::is_some).collect::<Vec<_>>();
+                reachable[source] = true;
+                return reachable;
+            }
+            let mut path = vec![];
+            let mut node = sink;
+            while let Some((arc, forward)) = parent[node] {
+                path.push((arc, forward));
+                node = if forward {
+                    self.arcs[arc].from
+                } else {
+                    self.arcs[arc].to
+                };
+            }
+            let augment = path
+                .iter()
+                .map(|&(arc, forward)| self.residual(arc, forward))
+                .min()
+                .unwrap();
+            for (arc, forward) in path {
+                if forward {
+                    self.arcs[arc].flow += augment;
+                } else {
+                    self.arcs[arc].flow -= augment;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the entities an edge is traced back to, see [`MinCut::entities`].
+fn edge_entities(graph: &FlowGraph, edge_idx: EdgeIndex) -> Vec<EntityId> {
+    let edge = &graph[edge_idx];
+    if !edge.entities.is_empty() {
+        return edge.entities.clone();
+    }
+    let (from, to) = graph.edge_endpoints(edge_idx).unwrap();
+    vec![graph[from].get_id(), graph[to].get_id()]
+}
+
+/// Finds a minimum cut between the inputs and the outputs of the graph, using the capacities of its edges.
+///
+/// Only the given `inputs` and `outputs` are considered, all of them if `None`.
+/// The cut ignores how splitters and mergers distribute the items, so its capacity is an upper bound of
+/// the throughput of the graph. Simplifying the graph first tightens it, as the capacities are shrunk.
+pub fn min_cut(
+    graph: &FlowGraph,
+    inputs: Option<&[EntityId]>,
+    outputs: Option<&[EntityId]>,
+) -> MinCut {
+    let selected = |ids: Option<&[EntityId]>, id: EntityId| ids.is_none_or(|ids| ids.contains(&id));
+    let source = graph.node_count();
+    let sink = source + 1;
+    let mut network = Network::new(graph.node_count() + 2);
+    /* larger than any cut, so the arcs of the source and the sink are never cut */
+    let unlimited = graph
+        .edge_weights()
+        .fold(GenericFraction::from(1), |sum, edge| sum + edge.capacity);
+    for edge_idx in graph.edge_indices() {
+        let (from, to) = graph.edge_endpoints(edge_idx).unwrap();
+        network.add_arc(from.index(), to.index(), graph[edge_idx].capacity);
+    }
+    for node_idx in graph.node_indices() {
+        match &graph[node_idx] {
+            Node::Input(i) if selected(inputs, i.id) => {
+                network.add_arc(source, node_idx.index(), unlimited)
+            }
+            Node::Output(o) if selected(outputs, o.id) => {
+                network.add_arc(node_idx.index(), sink, unlimited)
+            }
+            _ => (),
+        }
+    }
+
+    let reachable = network.max_flow(source, sink);
+    let edges = graph
+        .edge_indices()
+        .filter(|edge_idx| {
+            let (from, to) = graph.edge_endpoints(*edge_idx).unwrap();
+            reachable[from.index()] && !reachable[to.index()]
+        })
+        .collect::<Vec<_>>();
+    let capacity = edges
+        .iter()
+        .fold(GenericFraction::from(0), |sum, edge_idx| {
+            sum + graph[*edge_idx].capacity
+        });
+    let mut entities = edges
+        .iter()
+        .flat_map(|edge_idx| edge_entities(graph, *edge_idx))
+        .collect::<Vec<_>>();
+    entities.sort();
+    entities.dedup();
+    MinCut {
+        capacity,
+        edges,
+        entities,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::{prelude::NodeIndex, visit::EdgeRef};
+
+    use super::*;
+    use crate::test_utils;
+
+    fn graph(file: &str) -> FlowGraph {
+        test_utils::graph(file, &[])
+    }
+
+    /// Returns `true` if no output is reachable from an input without crossing the cut.
+    fn separates(graph: &FlowGraph, cut: &MinCut) -> bool {
+        let mut reachable = vec![false; graph.node_count()];
+        let mut queue = graph
+            .node_indices()
+            .filter(|idx| matches!(graph[*idx], Node::Input(_)))
+            .collect::<VecDeque<NodeIndex>>();
+        while let Some(node) = queue.pop_front() {
+            if reachable[node.index()] {
+                continue;
+            }
+            reachable[node.index()] = true;
+            for edge in graph.edges(node) {
+                if !cut.edges.contains(&edge.id()) {
+                    queue.push_back(edge.target());
+                }
+            }
+        }
+        !graph
+            .node_indices()
+            .any(|idx| reachable[idx.index()] && matches!(graph[idx], Node::Output(_)))
+    }
+
+    #[test]
+    fn simple_belt() {
+        let graph = graph("tests/simple_belt");
+        let cut = min_cut(&graph, None, None);
+        assert_eq!(cut.capacity, GenericFraction::from(15));
+        assert_eq!(cut.edges.len(), 1);
+        assert!(cut.entities.contains(&1));
+        assert!(separates(&graph, &cut));
+    }
+
+    #[test]
+    fn balancer_cut() {
+        let graph = graph("tests/4-4-ntu");
+        let cut = min_cut(&graph, None, None);
+        assert!(separates(&graph, &cut));
+        assert!(!cut.entities.is_empty());
+        /* the cut is not larger than cutting all the inputs */
+        let inputs = graph
+            .node_indices()
+            .filter(|idx| matches!(graph[*idx], Node::Input(_)))
+            .flat_map(|idx| graph.edges(idx))
+            .fold(GenericFraction::from(0), |sum, edge| {
+                sum + edge.weight().capacity
+            });
+        assert!(cut.capacity <= inputs);
+    }
+
+    #[test]
+    fn empty_selection() {
+        let cut = min_cut(&graph("tests/simple_belt"), Some(&[]), None);
+        assert_eq!(cut.capacity, GenericFraction::from(0));
+        assert!(cut.edges.is_empty());
+    }
+}
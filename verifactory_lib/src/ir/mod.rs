@@ -2,8 +2,10 @@
 
 mod graph_algos;
 mod ir_def;
+mod min_cut;
 mod reverse;
 
 pub use self::reverse::Reversable;
 pub use graph_algos::*;
 pub use ir_def::*;
+pub use min_cut::{min_cut, MinCut};
@@ -2,7 +2,7 @@
 
 use petgraph::Graph;
 
-use super::{Connector, Edge, FlowGraph, Input, Merger, Node, Output, Splitter};
+use super::{Connector, Craft, Edge, FlowGraph, Input, Merger, Node, Output, Splitter};
 use crate::utils::Side;
 
 /// Trait used to represent that something can be reversed in direction.
@@ -20,7 +20,7 @@ impl Reversable for Side {
 
 impl Reversable for Edge {
     fn reverse(&self) -> Self {
-        let mut rev = *self;
+        let mut rev = self.clone();
         rev.side = rev.side.reverse();
         rev
     }
@@ -30,8 +30,14 @@ impl Reversable for Node {
     fn reverse(&self) -> Self {
         match self {
             Node::Connector(c) => Node::Connector(Connector { ..*c }),
-            Node::Input(i) => Node::Output(Output { id: i.id }),
-            Node::Output(o) => Node::Input(Input { id: o.id }),
+            Node::Input(i) => Node::Output(Output {
+                id: i.id,
+                lane: i.lane,
+            }),
+            Node::Output(o) => Node::Input(Input {
+                id: o.id,
+                lane: o.lane,
+            }),
             Node::Merger(m) => Node::Splitter(Splitter {
                 output_priority: m.input_priority.reverse(),
                 id: m.id,
@@ -40,6 +46,12 @@ impl Reversable for Node {
                 input_priority: s.output_priority.reverse(),
                 id: s.id,
             }),
+            /* the products are turned back into the ingredients */
+            Node::Craft(c) => Node::Craft(Craft {
+                ingredients: c.products.clone(),
+                products: c.ingredients.clone(),
+                ..c.clone()
+            }),
         }
     }
 }
@@ -75,6 +87,6 @@ mod test {
         let entities = file_to_entities("tests/3-2").unwrap();
         let mut graph = Compiler::new(entities).create_graph();
         graph.simplify(&[3], Aggressive);
-        let rev = graph.reverse();
+        let _rev = graph.reverse();
     }
 }
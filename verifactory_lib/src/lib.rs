@@ -1,6 +1,12 @@
 pub mod backends;
 pub mod entities;
+pub mod export;
 pub mod frontend;
 pub mod import;
+pub mod inserters;
 pub mod ir;
+pub mod prototypes;
+pub mod recipes;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
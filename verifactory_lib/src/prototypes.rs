@@ -0,0 +1,401 @@
+//! Prototypes of the entities that can be imported.
+//!
+//! A prototype maps the name of an entity, as found in a blueprint, to its kind and its properties.
+//! The vanilla entities are built in, modded entities can be added by loading a JSON file of the form:
+//! ```json
+//! {
+//!     "ultra-transport-belt": { "kind": "belt", "throughput": 90 },
+//!     "ultra-underground-belt": { "kind": "underground", "throughput": 90, "max_underground_distance": 13 },
+//!     "ultra-splitter": { "kind": "splitter", "throughput": 90 },
+//!     "speed-module-4": { "kind": "module", "effects": { "speed": 0.7 } },
+//!     "ultra-beacon": { "kind": "beacon", "supply_area_distance": 4 }
+//! }
+//! ```
+//! The throughput is required for the entities moving items and for assemblers,
+//! beacons without one transmit the effects of their modules unchanged.
+//! Modded prototypes are used for blueprints of both Factorio 1.1 and 2.0.
+
+use serde::Deserialize;
+use std::{collections::HashMap, fs};
+
+use crate::{
+    entities::ModuleEffects,
+    import::{GameVersion, ImportError},
+};
+
+/// Kind of an entity, determines how it is imported and compiled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntityKind {
+    Belt,
+    Underground,
+    Splitter,
+    Inserter,
+    LongInserter,
+    Assembler,
+    Beacon,
+    Module,
+    /// Loader occupying two tiles
+    Loader,
+    #[serde(rename = "loader-1x1")]
+    Loader1x1,
+    Container,
+    /// Container creating and voiding items, like the infinity chest
+    InfinityContainer,
+}
+
+impl EntityKind {
+    /// Returns `true` if the throughput of the entity limits the flow, i.e. it moves or crafts items.
+    pub fn needs_throughput(&self) -> bool {
+        !matches!(
+            self,
+            Self::Beacon | Self::Module | Self::Container | Self::InfinityContainer
+        )
+    }
+}
+
+/// Properties of an entity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prototype {
+    pub kind: EntityKind,
+    /// Items per second for belts, loaders and inserters, crafting speed for assemblers,
+    /// distribution effectivity for beacons, unused for modules and containers.
+    /// Defaults to 1 for the kinds which don't need it, see [`EntityKind::needs_throughput`].
+    pub throughput: f64,
+    /// Maximum distance between the entrance and the exit of an underground belt
+    pub max_underground_distance: Option<i32>,
+    /// Effects of a module on the machine it is inserted in
+    pub effects: ModuleEffects,
+    /// Distance between a beacon and the edge of its supply area
+    pub supply_area_distance: Option<i32>,
+}
+
+/// [`Prototype`] as found in the JSON, whose throughput is optional for some kinds
+#[derive(Deserialize)]
+struct JsonPrototype {
+    kind: EntityKind,
+    #[serde(default)]
+    throughput: Option<f64>,
+    #[serde(default)]
+    max_underground_distance: Option<i32>,
+    #[serde(default)]
+    effects: ModuleEffects,
+    #[serde(default)]
+    supply_area_distance: Option<i32>,
+}
+
+impl TryFrom<JsonPrototype> for Prototype {
+    type Error = ImportError;
+
+    fn try_from(json: JsonPrototype) -> Result<Self, Self::Error> {
+        let throughput = match json.throughput {
+            Some(t) if t > 0.0 && t.is_finite() => t,
+            None if !json.kind.needs_throughput() => 1.0,
+            _ => {
+                return Err(ImportError::InvalidValue {
+                    key: "throughput",
+                    expected: "a positive number",
+                })
+            }
+        };
+        Ok(Self {
+            kind: json.kind,
+            throughput,
+            max_underground_distance: json.max_underground_distance,
+            effects: json.effects,
+            supply_area_distance: json.supply_area_distance,
+        })
+    }
+}
+
+/// Tiers of the vanilla belts: name prefix, throughput and max. underground distance
+const BELT_TIERS: [(&str, f64, i32); 4] = [
+    ("", 15.0, 5),
+    ("fast-", 30.0, 7),
+    ("express-", 45.0, 9),
+    ("turbo-", 60.0, 11),
+];
+
+/// Vanilla inserters and their throughput
+const INSERTERS: [(&str, EntityKind, f64); 8] = [
+    ("burner-inserter", EntityKind::Inserter, 0.6),
+    ("inserter", EntityKind::Inserter, 0.83),
+    ("long-handed-inserter", EntityKind::LongInserter, 1.2),
+    ("fast-inserter", EntityKind::Inserter, 2.31),
+    ("filter-inserter", EntityKind::Inserter, 2.31),
+    ("stack-inserter", EntityKind::Inserter, 2.31),
+    ("stack-filter-inserter", EntityKind::Inserter, 2.31),
+    ("bulk-inserter", EntityKind::Inserter, 2.31),
+];
+
+/// Vanilla containers and their kind
+const CONTAINERS: [(&str, EntityKind); 5] = [
+    ("wooden-chest", EntityKind::Container),
+    ("iron-chest", EntityKind::Container),
+    ("steel-chest", EntityKind::Container),
+    /* the link between linked chests is not modelled, each one is a source or a sink */
+    ("linked-chest", EntityKind::Container),
+    ("infinity-chest", EntityKind::InfinityContainer),
+];
+
+/// Vanilla assemblers and their crafting speed
+const ASSEMBLERS: [(&str, f64); 3] = [
+    ("assembling-machine-1", 0.5),
+    ("assembling-machine-2", 0.75),
+    ("assembling-machine-3", 1.25),
+];
+
+/// Vanilla beacons: name, distribution effectivity in Factorio 1.1 and 2.0 and supply area distance
+const BEACONS: [(&str, f64, f64, i32); 1] = [("beacon", 0.5, 1.5, 3)];
+
+/// Vanilla modules affecting the crafting speed: name, speed and productivity bonus
+const MODULES: [(&str, f64, f64); 6] = [
+    ("speed-module", 0.2, 0.0),
+    ("speed-module-2", 0.3, 0.0),
+    ("speed-module-3", 0.5, 0.0),
+    ("productivity-module", -0.05, 0.04),
+    ("productivity-module-2", -0.1, 0.06),
+    ("productivity-module-3", -0.15, 0.1),
+];
+
+/// Table mapping entity names to their prototype
+///
+/// The default registry contains the vanilla entities.
+#[derive(Debug, Clone)]
+pub struct PrototypeRegistry {
+    prototypes: HashMap<String, Prototype>,
+    /// Vanilla prototypes whose properties changed in Factorio 2.0, see [`PrototypeRegistry::get_in`]
+    prototypes_v2: HashMap<String, Prototype>,
+}
+
+impl Default for PrototypeRegistry {
+    fn default() -> Self {
+        Self::vanilla()
+    }
+}
+
+impl PrototypeRegistry {
+    /// Creates a registry without any prototypes.
+    pub fn empty() -> Self {
+        Self {
+            prototypes: HashMap::new(),
+            prototypes_v2: HashMap::new(),
+        }
+    }
+
+    /// Creates a registry containing the vanilla entities of Factorio 1.1 and 2.0.
+    pub fn vanilla() -> Self {
+        let mut registry = Self::empty();
+        for (prefix, throughput, distance) in BELT_TIERS {
+            let belt = |kind, max_underground_distance| Prototype {
+                kind,
+                throughput,
+                max_underground_distance,
+                effects: ModuleEffects::default(),
+                supply_area_distance: None,
+            };
+            registry.insert(
+                format!("{}transport-belt", prefix),
+                belt(EntityKind::Belt, None),
+            );
+            registry.insert(
+                format!("{}underground-belt", prefix),
+                belt(EntityKind::Underground, Some(distance)),
+            );
+            registry.insert(
+                format!("{}splitter", prefix),
+                belt(EntityKind::Splitter, None),
+            );
+            registry.insert(format!("{}loader", prefix), belt(EntityKind::Loader, None));
+        }
+        let entities = INSERTERS
+            .into_iter()
+            .chain(
+                ASSEMBLERS
+                    .into_iter()
+                    .map(|(name, speed)| (name, EntityKind::Assembler, speed)),
+            )
+            .chain([("loader-1x1", EntityKind::Loader1x1, 15.0)])
+            .chain(CONTAINERS.into_iter().map(|(name, kind)| (name, kind, 1.0)));
+        for (name, kind, throughput) in entities {
+            let prototype = Prototype {
+                kind,
+                throughput,
+                max_underground_distance: None,
+                effects: ModuleEffects::default(),
+                supply_area_distance: None,
+            };
+            registry.insert(name.to_owned(), prototype);
+        }
+        for (name, effectivity, effectivity_v2, distance) in BEACONS {
+            let beacon = |throughput| Prototype {
+                kind: EntityKind::Beacon,
+                throughput,
+                max_underground_distance: None,
+                effects: ModuleEffects::default(),
+                supply_area_distance: Some(distance),
+            };
+            registry.insert(name.to_owned(), beacon(effectivity));
+            registry
+                .prototypes_v2
+                .insert(name.to_owned(), beacon(effectivity_v2));
+        }
+        for (name, speed, productivity) in MODULES {
+            let prototype = Prototype {
+                kind: EntityKind::Module,
+                throughput: 1.0,
+                max_underground_distance: None,
+                effects: ModuleEffects {
+                    speed,
+                    productivity,
+                },
+                supply_area_distance: None,
+            };
+            registry.insert(name.to_owned(), prototype);
+        }
+        registry
+    }
+
+    /// Parses a registry from its JSON representation, see the [module documentation](self).
+    ///
+    /// Only the prototypes found in the JSON are part of the registry,
+    /// use [`PrototypeRegistry::extend`] to add them to the vanilla ones.
+    pub fn from_json_str(json: &str) -> Result<Self, ImportError> {
+        let prototypes: HashMap<String, JsonPrototype> = serde_json::from_str(json)?;
+        let prototypes = prototypes
+            .into_iter()
+            .map(|(name, prototype)| Ok((name, prototype.try_into()?)))
+            .collect::<Result<_, ImportError>>()?;
+        Ok(Self {
+            prototypes,
+            prototypes_v2: HashMap::new(),
+        })
+    }
+
+    /// Parses a registry from a JSON file, see [`PrototypeRegistry::from_json_str`].
+    pub fn from_file(file: &str) -> Result<Self, ImportError> {
+        let json = fs::read_to_string(file)?;
+        Self::from_json_str(&json)
+    }
+
+    /// Adds the prototypes of `other`, replacing the ones with the same name in any game version.
+    pub fn extend(&mut self, other: PrototypeRegistry) {
+        for name in other.prototypes.keys() {
+            self.prototypes_v2.remove(name);
+        }
+        self.prototypes.extend(other.prototypes);
+        self.prototypes_v2.extend(other.prototypes_v2);
+    }
+
+    /// Adds a single prototype, replacing the one with the same name in any game version.
+    pub fn insert(&mut self, name: String, prototype: Prototype) {
+        self.prototypes_v2.remove(&name);
+        self.prototypes.insert(name, prototype);
+    }
+
+    /// Returns the prototype of the entity with the given name.
+    ///
+    /// This is the prototype of Factorio 1.1 for the vanilla entities that changed in 2.0.
+    pub fn get(&self, name: &str) -> Option<&Prototype> {
+        self.prototypes.get(name)
+    }
+
+    /// Returns the prototype of the entity with the given name in a blueprint of the game `version`.
+    pub fn get_in(&self, name: &str, version: GameVersion) -> Option<&Prototype> {
+        match version {
+            GameVersion::V1 => None,
+            GameVersion::V2 => self.prototypes_v2.get(name),
+        }
+        .or_else(|| self.get(name))
+    }
+
+    /// Returns the maximum distance of underground belts with the given throughput.
+    ///
+    /// Falls back to the formula followed by the vanilla tiers if no such underground belt is registered.
+    pub fn max_underground_distance(&self, throughput: f64) -> i32 {
+        self.prototypes
+            .values()
+            .filter(|p| p.kind == EntityKind::Underground && p.throughput == throughput)
+            .filter_map(|p| p.max_underground_distance)
+            .max()
+            .unwrap_or(3 + 2 * throughput as i32 / 15)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vanilla_prototypes() {
+        let registry = PrototypeRegistry::default();
+        let belt = registry.get("express-transport-belt").unwrap();
+        assert_eq!(belt.kind, EntityKind::Belt);
+        assert_eq!(belt.throughput, 45.0);
+
+        let inserter = registry.get("long-handed-inserter").unwrap();
+        assert_eq!(inserter.kind, EntityKind::LongInserter);
+        assert!(registry.get("small-electric-pole").is_none());
+
+        let module = registry.get("productivity-module-3").unwrap();
+        assert_eq!(module.kind, EntityKind::Module);
+        assert_eq!(module.effects.productivity, 0.1);
+        let beacon = |version| registry.get_in("beacon", version).unwrap();
+        assert_eq!(beacon(GameVersion::V1).throughput, 0.5);
+        assert_eq!(beacon(GameVersion::V2).throughput, 1.5);
+        assert_eq!(beacon(GameVersion::V2).supply_area_distance, Some(3));
+
+        let loader = registry.get("express-loader").unwrap();
+        assert_eq!(loader.kind, EntityKind::Loader);
+        assert_eq!(loader.throughput, 45.0);
+        assert_eq!(
+            registry.get("loader-1x1").unwrap().kind,
+            EntityKind::Loader1x1
+        );
+        let chest = registry.get("infinity-chest").unwrap();
+        assert_eq!(chest.kind, EntityKind::InfinityContainer);
+
+        let distances = [15.0, 30.0, 45.0, 60.0].map(|t| registry.max_underground_distance(t));
+        assert_eq!(distances, [5, 7, 9, 11]);
+    }
+
+    #[test]
+    fn modded_prototypes() {
+        let mut registry = PrototypeRegistry::vanilla();
+        let modded = PrototypeRegistry::from_file("tests/modded_prototypes.json").unwrap();
+        registry.extend(modded);
+
+        let belt = registry.get("ultra-transport-belt").unwrap();
+        assert_eq!(belt.kind, EntityKind::Belt);
+        assert_eq!(belt.throughput, 90.0);
+        assert_eq!(registry.max_underground_distance(90.0), 13);
+        assert_eq!(registry.max_underground_distance(120.0), 15);
+        /* vanilla prototypes are kept */
+        assert!(registry.get("transport-belt").is_some());
+
+        let loader = registry.get("ultra-loader-1x1").unwrap();
+        assert_eq!(loader.kind, EntityKind::Loader1x1);
+
+        let module = registry.get("speed-module-4").unwrap();
+        assert_eq!(module.effects.speed, 0.7);
+        assert_eq!(module.effects.productivity, 0.0);
+
+        /* modded prototypes replace the vanilla ones of both versions */
+        let beacon = r#"{"beacon": {"kind": "beacon", "supply_area_distance": 4}}"#;
+        registry.extend(PrototypeRegistry::from_json_str(beacon).unwrap());
+        let beacon = registry.get_in("beacon", GameVersion::V2).unwrap();
+        assert_eq!(beacon.throughput, 1.0);
+        assert_eq!(beacon.supply_area_distance, Some(4));
+
+        for invalid in [
+            r#"{"broken-belt": {"kind": "belt", "throughput": 0}}"#,
+            r#"{"broken-inserter": {"kind": "inserter"}}"#,
+            r#"{"broken-beacon": {"kind": "beacon", "throughput": -1}}"#,
+        ] {
+            assert!(matches!(
+                PrototypeRegistry::from_json_str(invalid),
+                Err(ImportError::InvalidValue { .. })
+            ));
+        }
+    }
+}
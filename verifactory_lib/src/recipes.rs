@@ -0,0 +1,158 @@
+//! Recipes crafted by assemblers.
+//!
+//! A recipe maps the name found in the `recipe` field of an assembler, in a blueprint,
+//! to its crafting time and to the items it consumes and produces per craft.
+//! Common vanilla recipes are built in, read from `data/recipes.json`, and other recipes can be added
+//! by loading a JSON file of the same form:
+//! ```json
+//! {
+//!     "electronic-circuit": {
+//!         "crafting_time": 0.5,
+//!         "ingredients": { "iron-plate": 1, "copper-cable": 3 },
+//!         "products": { "electronic-circuit": 1 }
+//!     }
+//! }
+//! ```
+
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use crate::import::ImportError;
+
+/// JSON representation of the vanilla recipes
+const VANILLA_RECIPES: &str = include_str!("../data/recipes.json");
+
+/// Items consumed and produced by an assembler
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Recipe {
+    /// Seconds needed for a single craft at crafting speed 1
+    pub crafting_time: f64,
+    /// Amount of each item consumed per craft
+    pub ingredients: BTreeMap<String, f64>,
+    /// Amount of each item produced per craft
+    pub products: BTreeMap<String, f64>,
+}
+
+impl Recipe {
+    /// Returns the number of crafts per second of an assembler with the given crafting speed.
+    pub fn crafts_per_second(&self, crafting_speed: f64) -> f64 {
+        crafting_speed / self.crafting_time
+    }
+}
+
+/// Table mapping recipe names to their recipe
+///
+/// The default database contains the vanilla recipes.
+#[derive(Debug, Clone)]
+pub struct RecipeDatabase {
+    recipes: HashMap<String, Recipe>,
+}
+
+impl Default for RecipeDatabase {
+    fn default() -> Self {
+        Self::vanilla()
+    }
+}
+
+impl RecipeDatabase {
+    /// Creates a database without any recipes.
+    pub fn empty() -> Self {
+        Self {
+            recipes: HashMap::new(),
+        }
+    }
+
+    /// Creates a database containing the built-in vanilla recipes.
+    pub fn vanilla() -> Self {
+        Self::from_json_str(VANILLA_RECIPES).expect("the vanilla recipes are valid")
+    }
+
+    /// Parses a database from its JSON representation, see the [module documentation](self).
+    ///
+    /// Only the recipes found in the JSON are part of the database,
+    /// use [`RecipeDatabase::extend`] to add them to the vanilla ones.
+    pub fn from_json_str(json: &str) -> Result<Self, ImportError> {
+        let recipes: HashMap<String, Recipe> = serde_json::from_str(json)?;
+        for recipe in recipes.values() {
+            if recipe.crafting_time.is_nan() || recipe.crafting_time <= 0.0 {
+                return Err(ImportError::InvalidValue {
+                    key: "crafting_time",
+                    expected: "a positive number",
+                });
+            }
+            let mut amounts = recipe.ingredients.values().chain(recipe.products.values());
+            if amounts.any(|a| a.is_nan() || *a <= 0.0) {
+                return Err(ImportError::InvalidValue {
+                    key: "amount",
+                    expected: "a positive number",
+                });
+            }
+            if recipe.products.is_empty() {
+                return Err(ImportError::InvalidValue {
+                    key: "products",
+                    expected: "at least one product",
+                });
+            }
+        }
+        Ok(Self { recipes })
+    }
+
+    /// Parses a database from a JSON file, see [`RecipeDatabase::from_json_str`].
+    pub fn from_file(file: &str) -> Result<Self, ImportError> {
+        let json = fs::read_to_string(file)?;
+        Self::from_json_str(&json)
+    }
+
+    /// Adds the recipes of `other`, replacing the ones with the same name.
+    pub fn extend(&mut self, other: RecipeDatabase) {
+        self.recipes.extend(other.recipes);
+    }
+
+    /// Adds a single recipe, replacing the one with the same name.
+    pub fn insert(&mut self, name: String, recipe: Recipe) {
+        self.recipes.insert(name, recipe);
+    }
+
+    /// Returns the recipe with the given name.
+    pub fn get(&self, name: &str) -> Option<&Recipe> {
+        self.recipes.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vanilla_recipes() {
+        let recipes = RecipeDatabase::default();
+        let circuit = recipes.get("electronic-circuit").unwrap();
+        assert_eq!(circuit.crafting_time, 0.5);
+        assert_eq!(circuit.ingredients["copper-cable"], 3.0);
+        assert_eq!(circuit.products["electronic-circuit"], 1.0);
+        assert_eq!(circuit.crafts_per_second(0.75), 1.5);
+        assert!(recipes.get("rocket-part").is_none());
+    }
+
+    #[test]
+    fn modded_recipes() {
+        let mut recipes = RecipeDatabase::vanilla();
+        let modded = RecipeDatabase::from_file("tests/modded_recipes.json").unwrap();
+        recipes.extend(modded);
+
+        let board = recipes.get("circuit-board").unwrap();
+        assert_eq!(board.products.len(), 2);
+        /* modded recipes replace the vanilla ones */
+        assert_eq!(recipes.get("iron-gear-wheel").unwrap().crafting_time, 1.0);
+        assert!(recipes.get("copper-cable").is_some());
+
+        let invalid = r#"{"nothing": {"crafting_time": 1, "ingredients": {}, "products": {}}}"#;
+        assert!(matches!(
+            RecipeDatabase::from_json_str(invalid),
+            Err(ImportError::InvalidValue { .. })
+        ));
+    }
+}
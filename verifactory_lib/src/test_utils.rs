@@ -0,0 +1,36 @@
+//! Helpers shared by the unit tests
+
+use crate::{
+    entities::{EntityId, FBEntity},
+    frontend::Compiler,
+    import::file_to_entities,
+    ir::{CoalesceStrength, FlowGraph, FlowGraphFun},
+    recipes::RecipeDatabase,
+};
+
+/// Imports the blueprint of a test file and creates its graph with `create`.
+///
+/// The graph is simplified, removing the inputs and outputs of the `removed` entities.
+pub fn load_graph(
+    file: &str,
+    removed: &[EntityId],
+    create: impl FnOnce(&Compiler) -> FlowGraph,
+) -> (FlowGraph, Vec<FBEntity<i32>>) {
+    let entities = file_to_entities(file).unwrap();
+    let mut graph = create(&Compiler::new(entities.clone()));
+    graph.simplify(removed, CoalesceStrength::Aggressive);
+    (graph, entities)
+}
+
+/// Returns the simplified graph of a test file, see [`load_graph`].
+pub fn graph(file: &str, removed: &[EntityId]) -> FlowGraph {
+    load_graph(file, removed, Compiler::create_graph).0
+}
+
+/// Returns the simplified production graph of a test file, crafting the `recipes`, see [`load_graph`].
+pub fn production_graph(file: &str, recipes: &RecipeDatabase) -> FlowGraph {
+    load_graph(file, &[], |compiler| {
+        compiler.create_production_graph(recipes)
+    })
+    .0
+}
@@ -1,6 +1,9 @@
 //! Various generic utilities
 
-use std::ops::{Add, Neg, Sub};
+use std::{
+    fmt::Display,
+    ops::{Add, Neg, Sub},
+};
 
 use serde::Deserialize;
 use serde_repr::Deserialize_repr;
@@ -62,12 +65,12 @@ pub enum Direction {
 impl Direction {
     /// Returns a new `Direction` rotated in the given direction
     pub fn rotate(&self, direction: Rotation, amount: u8) -> Self {
-        let incr = match direction {
-            Rotation::Clockwise => 2,
-            Rotation::Anticlockwise => 6,
+        let steps = match direction {
+            Rotation::Clockwise => 1,
+            Rotation::Anticlockwise => 3,
         };
-        let new_u8 = (*self as u8 + amount * incr) % 8;
-        new_u8.into()
+        let index = (*self as u8 / 2 + (amount % 4) * steps) % 4;
+        [Self::North, Self::East, Self::South, Self::West][index as usize]
     }
 
     /// Returns a new `Direction` rotate to the given side
@@ -85,14 +88,28 @@ impl Direction {
     }
 }
 
-impl From<u8> for Direction {
-    fn from(value: u8) -> Self {
+/// Error returned when a number is not one of the four cardinal [`Direction`]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidDirection(pub u8);
+
+impl Display for InvalidDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Direction is not in cardinal direction: ({})", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDirection {}
+
+impl TryFrom<u8> for Direction {
+    type Error = InvalidDirection;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => Self::North,
-            2 => Self::East,
-            4 => Self::South,
-            6 => Self::West,
-            _ => panic!("Direction is not in cardinal direction: ({})", value),
+            0 => Ok(Self::North),
+            2 => Ok(Self::East),
+            4 => Ok(Self::South),
+            6 => Ok(Self::West),
+            _ => Err(InvalidDirection(value)),
         }
     }
 }
@@ -118,6 +135,15 @@ impl Side {
     pub fn is_none(&self) -> bool {
         *self == Self::None
     }
+
+    /// Returns the suffix used to tell apart the two lanes of a belt, e.g. in the names of z3 variables.
+    pub fn lane_suffix(&self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Left => "_l",
+            Self::Right => "_r",
+        }
+    }
 }
 
 impl Neg for Side {
@@ -165,4 +191,10 @@ mod test {
         let west = south.rotate(Clockwise, 1);
         assert_eq!(west, West);
     }
+
+    #[test]
+    fn dir_try_from() {
+        assert_eq!(Direction::try_from(6).unwrap(), West);
+        assert_eq!(Direction::try_from(3), Err(InvalidDirection(3)));
+    }
 }